@@ -0,0 +1,234 @@
+// benchmarks for the dsp chain, run with `cargo bench` or `cargo bench -- <name filter>`
+// every benchmark renders a fixed number of blocks from a fixed start so runs compare,
+// and reports nanoseconds per output sample and how many times faster than real time it is
+
+extern crate midi;
+extern crate organn;
+
+#[path = "../tests/common/doubles.rs"]
+mod doubles;
+
+use organn::basic_types::BUFFER_SIZE;
+use organn::basic_types::graph::Graph;
+use doubles::Constant;
+use organn::oscillator::{Oscillator, SineMethod};
+use organn::mixer::Mixer;
+use organn::additive::{Additive, AdditiveMethod};
+use organn::env::Env;
+use organn::effects::{EffectType, ImpulseResponse};
+use organn::voice::{Voice, VoiceMessage};
+use organn::organ::Organ;
+use organn::multi::{Multi, MultiConfig};
+use organn::controls::DEFAULT_REGISTRATION;
+use organn::tuning::{Tuning, TuningBank};
+
+use std::env;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Instant;
+
+const SAMPLE_RATE: u32 = 44_100;
+const WARMUP_BLOCKS: usize = 1_000;
+// about 18 seconds of audio
+const BLOCKS: usize = 50_000;
+
+// polyphony and thread counts for the whole engine
+const MULTI_SETUPS: [(usize, usize); 6] = [(8, 1), (32, 1), (32, 2), (32, 4), (64, 4), (64, 8)];
+// polyphony the pool is compared at
+const POOL_VOICES: usize = 32;
+
+fn note_on(note: midi::U7) -> midi::Message {
+    let (_, channel) = midi::utils::from_status_byte(0x90);
+    midi::Message::NoteOn(channel, note, 100)
+}
+
+// nanoseconds per sample, none if the filter left it out
+fn bench<F>(filter: &Option<String>, name: &str, mut block: F) -> Option<f64> where F: FnMut() {
+    if filter.as_ref().is_some_and(|f| !name.contains(&f[..])) {
+        return None;
+    }
+
+    for _ in 0..WARMUP_BLOCKS {
+        block();
+    }
+    let start = Instant::now();
+    for _ in 0..BLOCKS {
+        block();
+    }
+    let elapsed = start.elapsed();
+
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    let samples = (BLOCKS * BUFFER_SIZE) as f64;
+    let ns_per_sample = seconds * 1e9 / samples;
+    let real_time = (samples / SAMPLE_RATE as f64) / seconds;
+    println!("{:<28} {:>10.2} ns/sample {:>10.1}x real time", name, ns_per_sample, real_time);
+    Some(ns_per_sample)
+}
+
+// the table should stay well ahead of sin, it's what voices fall back on without simd
+fn oscillator(filter: &Option<String>) {
+    for &(name, method) in [("oscillator/sin", SineMethod::Sin), ("oscillator/table", SineMethod::Table)].iter() {
+        let mut graph = Graph::new();
+        let osc = graph.add("oscillator", Oscillator::with_method(SAMPLE_RATE, method));
+        graph.node_mut(&osc).set_freq(440.0);
+        graph.set_output(&osc).unwrap();
+
+        bench(filter, name, || {
+                graph.run().unwrap();
+            });
+    }
+}
+
+fn mixer(filter: &Option<String>) {
+    // the same nine inputs a voice has
+    let mut graph = Graph::new();
+    let mixer = graph.add("mixer", Mixer::new(DEFAULT_REGISTRATION.to_vec()));
+    for _ in 0..9 {
+        let source = graph.add("source", Constant::new([0.5; BUFFER_SIZE]));
+        graph.connect(&source, &mixer).unwrap();
+    }
+    graph.set_output(&mixer).unwrap();
+
+    bench(filter, "mixer/9", || {
+            graph.run().unwrap();
+        });
+}
+
+fn additive(filter: &Option<String>) {
+    let methods = [("additive/simd", AdditiveMethod::Simd),
+                   ("additive/sin", AdditiveMethod::Scalar(SineMethod::Sin)),
+                   ("additive/table", AdditiveMethod::Scalar(SineMethod::Table))];
+    for &(name, method) in methods.iter() {
+        if method == AdditiveMethod::Simd && !organn::additive::simd_available() {
+            continue;
+        }
+        let mut partials = Additive::with_method(SAMPLE_RATE, method);
+        for (partial, level) in DEFAULT_REGISTRATION.iter().enumerate() {
+            partials.set_freq(partial, 440.0 * (partial + 1) as f32);
+            partials.set_level(partial, *level / 9.0);
+        }
+        let mut graph = Graph::new();
+        let partials = graph.add("partials", partials);
+        graph.set_output(&partials).unwrap();
+
+        bench(filter, name, || {
+                graph.run().unwrap();
+            });
+    }
+}
+
+fn envelope(filter: &Option<String>) {
+    let mut graph = Graph::new();
+    let source = graph.add("source", Constant::new([0.5; BUFFER_SIZE]));
+    let env = graph.add("env", Env::new(20, SAMPLE_RATE));
+    graph.connect(&source, &env).unwrap();
+    graph.set_output(&env).unwrap();
+    graph.node_mut(&env).note_on();
+
+    bench(filter, "env", || {
+            graph.run().unwrap();
+        });
+}
+
+// a second of decaying noise at another rate, the longest response a cabinet takes
+fn impulse_response() -> Arc<ImpulseResponse> {
+    let mut seed: u32 = 1;
+    let samples = (0..48_000).map(|i| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            ((seed >> 8) as f32 / (1 << 23) as f32 - 1.0) * (-(i as f32) / 4_800.0).exp()
+        })
+        .collect();
+    Arc::new(ImpulseResponse {
+        name: "noise".to_string(),
+        sample_rate: 48_000,
+        samples
+    })
+}
+
+fn effects(filter: &Option<String>) {
+    let types = [("effects/eq", EffectType::Equalizer),
+                 ("effects/dc", EffectType::DcBlocker),
+                 ("effects/gain", EffectType::Gain),
+                 ("effects/spring", EffectType::SpringReverb),
+                 ("effects/cabinet", EffectType::Cabinet(vec![impulse_response()]))];
+    for &(name, ref effect_type) in types.iter() {
+        let mut effect = effect_type.create(SAMPLE_RATE);
+        let mut block = [0.5; BUFFER_SIZE];
+
+        bench(filter, name, || {
+                effect.process(&mut block);
+            });
+    }
+}
+
+fn voice(filter: &Option<String>) {
+    let (messages, midi_input) = mpsc::channel();
+    let mut voice = Voice::new(SAMPLE_RATE, Tuning::new(440.0, 0), &DEFAULT_REGISTRATION, midi_input);
+    messages.send(VoiceMessage::Midi(note_on(60))).unwrap();
+
+    bench(filter, "voice", || {
+            voice.run().unwrap();
+        });
+
+    // every stop of the built in organ drawn and coupled
+    let organ = Organ::builtin();
+    let (messages, midi_input) = mpsc::channel();
+    let mut voice = Voice::pipes(SAMPLE_RATE, Tuning::new(440.0, 0), Arc::new(organ.clone()), midi_input);
+    for stop in 0..organ.stops.len() {
+        messages.send(VoiceMessage::Stop(stop, true)).unwrap();
+    }
+    for coupler in 0..organ.couplers.len() {
+        messages.send(VoiceMessage::Coupler(coupler, true)).unwrap();
+    }
+    messages.send(VoiceMessage::Midi(note_on(60))).unwrap();
+
+    bench(filter, "voice/pipes", || {
+            voice.run().unwrap();
+        });
+}
+
+fn bench_multi(filter: &Option<String>, name: &str, num_voices: usize, num_threads: usize) -> Option<f64> {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_voices = num_voices;
+    config.num_threads = num_threads;
+    let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+
+    // every voice sounding, the worst case
+    for note in 0..num_voices {
+        midi_conn.midi_message(&note_on(24 + note as midi::U7));
+    }
+
+    bench(filter, name, || {
+            multi.run();
+        })
+}
+
+fn multi(filter: &Option<String>) {
+    for &(num_voices, num_threads) in MULTI_SETUPS.iter() {
+        bench_multi(filter, &format!("multi/{}v/{}t", num_voices, num_threads), num_voices, num_threads);
+    }
+}
+
+// whether a thread per core pays for the barriers it meets every block
+fn pool(filter: &Option<String>) {
+    let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).max(2);
+    let one = bench_multi(filter, &format!("pool/{}v/1t", POOL_VOICES), POOL_VOICES, 1);
+    let all = bench_multi(filter, &format!("pool/{}v/{}t", POOL_VOICES, cores), POOL_VOICES, cores);
+    if let (Some(one), Some(all)) = (one, all) {
+        println!("{:<28} {:>10.2}x one thread's speed", "pool/speedup", one / all);
+    }
+}
+
+fn main() {
+    // cargo passes --bench, anything else is a filter on the names
+    let filter = env::args().skip(1).find(|a| !a.starts_with('-'));
+
+    oscillator(&filter);
+    mixer(&filter);
+    additive(&filter);
+    envelope(&filter);
+    effects(&filter);
+    voice(&filter);
+    multi(&filter);
+    pool(&filter);
+}
@@ -0,0 +1,50 @@
+// arbitrary ramp times, sample rates and note on/offs, the envelope must never panic or
+// produce anything but a scaled copy of its input
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate organn;
+
+#[path = "../../tests/common/doubles.rs"]
+mod doubles;
+
+use organn::basic_types::BUFFER_SIZE;
+use organn::basic_types::graph::Graph;
+use doubles::{Constant, Recorder};
+use organn::env::Env;
+
+fn word(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |w, b| (w << 8) | *b as u32)
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 6 {
+        return;
+    }
+    // small times and rates are the interesting ones, a zero length ramp among them
+    let time_ms = word(&data[0..2]) % 200;
+    let sample_rate = word(&data[2..6]) % 400_000;
+
+    let mut graph = Graph::new();
+    let source = graph.add("source", Constant::new([1.0; BUFFER_SIZE]));
+    let env = graph.add("env", Env::new(time_ms, sample_rate));
+    let recorder = graph.add("recorder", Recorder::new());
+    graph.connect(&source, &env).unwrap();
+    graph.connect(&env, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+
+    for byte in data[6..].iter() {
+        match byte % 4 {
+            0 => graph.node_mut(&env).note_on(),
+            1 => graph.node_mut(&env).note_off(),
+            2 => graph.set_sample_rate(word(&[*byte, *byte]) * 16),
+            _ => {}
+        }
+        graph.run().unwrap();
+    }
+
+    for sample in graph.node(&recorder).samples() {
+        assert!((0.0..=1.0).contains(sample), "sample {} from a 1.0 input", sample);
+    }
+});
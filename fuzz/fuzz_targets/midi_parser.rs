@@ -0,0 +1,39 @@
+// arbitrary bytes, split into arbitrary packets, must never panic the parser
+// and must parse the same however they're split
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate midi_wrap;
+
+use midi_wrap::{Parser, Event};
+
+fn count(events: &mut [usize; 4], event: Event) {
+    let kind = match event {
+        Event::Message(_) => 0,
+        Event::SysEx(_) => 1,
+        Event::SystemCommon(..) => 2,
+        Event::Realtime(_) => 3
+    };
+    events[kind] += 1;
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    // the first byte picks the packet size
+    let packet = (data[0] as usize % 16) + 1;
+    let bytes = &data[1..];
+
+    let mut whole = [0; 4];
+    Parser::new().parse(bytes, |e| count(&mut whole, e));
+
+    let mut split = [0; 4];
+    let mut parser = Parser::new();
+    for chunk in bytes.chunks(packet) {
+        parser.parse(chunk, |e| count(&mut split, e));
+    }
+
+    assert_eq!(whole, split);
+});
@@ -0,0 +1,75 @@
+// arbitrary note on/off sequences through the engine's note allocation, a note off must
+// release exactly the voices holding that pitch, and once every note is off no voice may
+// be left assigned or sounding
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate midi;
+extern crate organn;
+
+use organn::multi::{Multi, MultiConfig};
+use organn::tuning::{Tuning, TuningBank};
+
+// low so a fuzzed block count covers the whole release
+const SAMPLE_RATE: u32 = 8_000;
+// comfortably past the 20ms release
+const RELEASE_BLOCKS: usize = 20;
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    let (_, channel) = midi::utils::from_status_byte(0x90);
+
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_voices = (data[0] as usize % 8) + 1;
+    config.num_threads = 1;
+    let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+    let stats = multi.stats();
+
+    // top bit is on or off, the rest a pitch from a small range so notes collide,
+    // every few messages a block is rendered
+    for (i, byte) in data[1..].iter().enumerate() {
+        let pitch = 60 + (byte & 0x0F);
+        let before = midi_conn.notes();
+
+        if byte & 0x80 != 0 {
+            midi_conn.midi_message(&midi::Message::NoteOn(channel, pitch, 100));
+
+            // one voice takes the note, stealing if it has to
+            let after = midi_conn.notes();
+            assert!(after.contains(&Some(pitch)));
+            assert!(before.iter().zip(after.iter()).filter(|&(b, a)| a != b).count() <= 1);
+        }
+        else {
+            midi_conn.midi_message(&midi::Message::NoteOff(channel, pitch, 0));
+
+            let after = midi_conn.notes();
+            for (b, a) in before.iter().zip(after.iter()) {
+                if *b == Some(pitch) {
+                    assert_eq!(*a, None);
+                }
+                else {
+                    assert_eq!(a, b);
+                }
+            }
+        }
+
+        if i % 4 == 3 {
+            multi.run();
+        }
+    }
+
+    for pitch in 60..(60 + 16) {
+        midi_conn.midi_message(&midi::Message::NoteOff(channel, pitch, 0));
+    }
+    assert!(midi_conn.notes().iter().all(|n| n.is_none()));
+
+    for _ in 0..RELEASE_BLOCKS {
+        multi.run();
+    }
+    stats.take_report();
+    multi.run();
+    assert_eq!(stats.take_report().max_active_voices, 0);
+});
@@ -0,0 +1,18 @@
+// arbitrary sysex, well formed or not, must never panic the tuning bank
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate organn;
+
+use organn::tuning::{Tuning, TuningBank};
+
+fuzz_target!(|data: &[u8]| {
+    let mut tuning = Tuning::new(440.0, 0);
+    let mut bank = TuningBank::new();
+    bank.sysex(&mut tuning, data);
+    for note in 0..128 {
+        let freq = tuning.note_to_hz(note);
+        assert!(!freq.is_nan());
+    }
+});
@@ -0,0 +1,139 @@
+// alsa sequencer input, a virtual port that other programs (or aconnect) can connect to
+
+use alsa::{self, Direction};
+use alsa::seq::{Seq, Addr, PortInfo, PortCap, PortType, PortSubscribe, MidiEvent, ClientIter, PortIter};
+use alsa::poll::{self, Descriptors};
+
+use input::{MidiInput, MidiCallback};
+use parser::Parser;
+
+use std::ffi::CString;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+// how often the input thread checks whether it should stop
+const POLL_MS: i32 = 100;
+// big enough for any sysex the parser would keep
+const DECODE_BUFFER: usize = 8192;
+
+// ports that can be read from, with names as "client:port" and their addresses
+fn readable_ports(seq: &Seq) -> Vec<(String, Addr)> {
+    let mut ports = Vec::new();
+    for client in ClientIter::new(seq) {
+        let client_name = client.get_name().unwrap_or("").to_string();
+        for port in PortIter::new(seq, client.get_client()) {
+            let caps = port.get_capability();
+            if caps.contains(PortCap::READ | PortCap::SUBS_READ) {
+                let name = format!("{}:{}", client_name, port.get_name().unwrap_or(""));
+                ports.push((name, port.addr()));
+            }
+        }
+    }
+    ports
+}
+
+/// Ports that could be connected to the input, as "client:port (address)"
+pub fn sources() -> Result<Vec<String>, String> {
+    let seq = Seq::open(None, None, true).map_err(|e| format!("alsa sequencer: {}", e))?;
+    Ok(readable_ports(&seq)
+        .into_iter()
+        .map(|(name, addr)| format!("{} ({}:{})", name, addr.client, addr.port))
+        .collect())
+}
+
+// a source is either an address like 20:0 or a "client:port" name
+fn find_source(seq: &Seq, source: &str) -> Option<Addr> {
+    source.parse::<Addr>().ok().or_else(|| {
+        readable_ports(seq)
+            .into_iter()
+            .find(|&(ref name, _)| name == source)
+            .map(|(_, addr)| addr)
+    })
+}
+
+pub struct AlsaSeqInput {
+    name: String,
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>
+}
+
+impl AlsaSeqInput {
+    pub fn open(client_name: &str, port_name: &str, source: Option<&str>, mut callback: MidiCallback) -> Result<Self, String> {
+        let err = |e: alsa::Error| format!("alsa sequencer: {}", e);
+        let c_string = |s: &str| CString::new(s).map_err(|_| format!("bad name \"{}\"", s));
+
+        let seq = Seq::open(None, Some(Direction::Capture), true).map_err(&err)?;
+        seq.set_client_name(&c_string(client_name)?).map_err(&err)?;
+
+        let mut port = PortInfo::empty().map_err(&err)?;
+        port.set_capability(PortCap::WRITE | PortCap::SUBS_WRITE);
+        port.set_type(PortType::MIDI_GENERIC | PortType::APPLICATION);
+        port.set_name(&c_string(port_name)?);
+        seq.create_port(&port).map_err(&err)?;
+
+        let client_id = seq.client_id().map_err(&err)?;
+        let name = format!("{}:{} ({}:{})", client_name, port_name, client_id, port.get_port());
+
+        // connect the source ourselves if there is one, otherwise it's up to the user
+        if let Some(source) = source {
+            let sender = find_source(&seq, source).ok_or(format!("alsa sequencer: no source \"{}\"", source))?;
+            let subscription = PortSubscribe::empty().map_err(&err)?;
+            subscription.set_sender(sender);
+            subscription.set_dest(Addr { client: client_id, port: port.get_port() });
+            seq.subscribe_port(&subscription).map_err(&err)?;
+        }
+        let mut fds = (&seq, Some(Direction::Capture)).get().map_err(&err)?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+
+        let thread = thread::spawn(move || {
+                // the decoder turns sequencer events back into bytes for the parser
+                let decoder = match MidiEvent::new(DECODE_BUFFER as u32) {
+                    Ok(decoder) => decoder,
+                    Err(_) => { return; }
+                };
+                decoder.enable_running_status(false);
+                let mut parser = Parser::new();
+                let mut bytes = vec![0; DECODE_BUFFER];
+                let mut input = seq.input();
+
+                while thread_running.load(Ordering::Relaxed) {
+                    if poll::poll(&mut fds, POLL_MS).unwrap_or(0) == 0 {
+                        continue;
+                    }
+                    // read until there's nothing left, non midi events just don't decode
+                    while let Ok(mut event) = input.event_input() {
+                        if let Ok(len) = decoder.decode(&mut bytes, &mut event) {
+                            parser.parse(&bytes[..len], |e| callback(e));
+                        }
+                    }
+                }
+            });
+
+        Ok(AlsaSeqInput {
+            name: name,
+            running: running,
+            thread: Some(thread)
+        })
+    }
+}
+
+impl MidiInput for AlsaSeqInput {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for AlsaSeqInput {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            // a panic there has lost the input already, there's nothing else to do about it
+            if thread.join().is_err() {
+                error!("alsa midi input thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use CoreFoundation_sys;
+use core_midi_services;
+use parser::{Parser, Event};
+use input::MidiInput;
+
+use std::ptr;
+use std::ffi::CString;
+
+// the user callback plus the parser state that has to survive between packets
+struct CallbackData<A> where A: FnMut(Event) {
+    callback: A,
+    parser: Parser
+}
+
+pub struct MidiWrap<A> where A: FnMut(Event) {
+    name: String,
+    client: core_midi_services::MIDIClientRef,
+    port: core_midi_services::MIDIPortRef,
+
+    // just need to store this data somewhere, it's used in the coremidi callback fn
+    #[allow(dead_code)]
+    closure_data: Box<CallbackData<A>>
+}
+
+impl<A> MidiWrap<A> where A: FnMut(Event)  {
+    /// Connects to every source, or just the one with the given index
+    pub fn new(clinet_name: &str, port_name: &str, source: Option<usize>, callback: A) -> Option<MidiWrap<A>> {
+
+        let closure_data = Box::new(CallbackData {
+            callback: callback,
+            parser: Parser::new()
+        });
+
+        // create a midi client
+        let mut client: core_midi_services::MIDIClientRef = 0;
+        let status;
+        unsafe {
+            let funky_string = CoreFoundation_sys::CFStringCreateWithCString(
+                CoreFoundation_sys::kCFAllocatorMalloc,
+                CString::new(clinet_name).unwrap().as_ptr(),
+                CoreFoundation_sys::kCFStringEncodingUTF8);
+            status = core_midi_services::MIDIClientCreate(funky_string, None, ptr::null(), &mut client);
+        }
+        if status != 0 {
+            return None;
+        }
+
+        // create an input port with a callback
+        let mut port: core_midi_services::MIDIPortRef = 0;
+        let status;
+        unsafe {
+            use std::mem::transmute;
+            let funky_string = CoreFoundation_sys::CFStringCreateWithCString(
+                CoreFoundation_sys::kCFAllocatorMalloc,
+                CString::new(port_name).unwrap().as_ptr(),
+                CoreFoundation_sys::kCFStringEncodingUTF8);
+            status = core_midi_services::MIDIInputPortCreate(client,funky_string, Some(MidiWrap::<A>::midi_callback), transmute(&*closure_data), &mut port);
+
+            // connect everything to the input, unless a source was picked
+            let num_sources = core_midi_services::MIDIGetNumberOfSources();
+
+            for i in 0..num_sources {
+                if source.map_or(true, |s| s as core_midi_services::ItemCount == i) {
+                    core_midi_services::MIDIPortConnectSource(port, core_midi_services::MIDIGetSource(i), ptr::null_mut());
+                }
+            }
+        }
+        if status != 0 {
+            return None;
+        }
+
+        Some(MidiWrap {
+            name: format!("{}:{}", clinet_name, port_name),
+            client: client,
+            port: port,
+            closure_data: closure_data
+        })
+    }
+
+    extern "C" fn midi_callback(pktlist: *const core_midi_services::MIDIPacketList,
+                                read_proc_ref_con: *mut ::libc::c_void,
+                                _: *mut ::libc::c_void) -> () {
+        unsafe {
+            use std::mem::transmute;
+            use std::slice;
+
+            let data: &mut CallbackData<A> = transmute(read_proc_ref_con);
+            let CallbackData { ref mut callback, ref mut parser } = *data;
+            let mut packet = &(*pktlist).packet[0];
+            for _ in 0..(*pktlist).numPackets {
+                let bytes = slice::from_raw_parts(packet.data.as_ptr(), packet.length  as usize);
+                parser.parse(bytes, |event| callback(event));
+
+                packet = core_midi_services::MIDIPacketNext(packet);
+            }
+        }
+    }
+}
+
+/// Sources by index, coremidi names aren't looked up
+pub fn sources() -> Vec<String> {
+    let num_sources = unsafe { core_midi_services::MIDIGetNumberOfSources() };
+    (0..num_sources).map(|i| format!("{}", i)).collect()
+}
+
+impl<A> MidiInput for MidiWrap<A> where A: FnMut(Event) {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<A> Drop for MidiWrap<A> where A: FnMut(Event)  {
+    fn drop(&mut self) {
+        unsafe {
+            core_midi_services::MIDIPortDispose(self.port);
+            core_midi_services::MIDIClientDispose(self.client);
+        }
+    }
+}
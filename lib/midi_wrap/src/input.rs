@@ -0,0 +1,108 @@
+// backend neutral midi input, picked by name at runtime
+
+use parser::Event;
+#[cfg(all(feature = "coremidi", target_os = "macos"))]
+use core_midi::{self, MidiWrap};
+#[cfg(all(feature = "alsa", target_os = "linux"))]
+use alsa_seq::{self, AlsaSeqInput};
+#[cfg(feature = "jack")]
+use jack_midi::{self, JackMidiInput};
+
+/// Gets every event from an input, on whatever thread the backend delivers them
+pub type MidiCallback = Box<dyn FnMut(Event) + Send>;
+
+/// An open midi input, events go to its callback until it's dropped
+pub trait MidiInput {
+    /// Where to send midi to reach this input, for telling the user
+    fn name(&self) -> &str;
+}
+
+// for running without midi, nothing can reach it
+struct NoInput;
+
+impl MidiInput for NoInput {
+    fn name(&self) -> &str {
+        "none"
+    }
+}
+
+/// Names of the inputs built in, the first is the default
+pub fn backends() -> Vec<&'static str> {
+    let mut names = Vec::new();
+    if cfg!(all(feature = "coremidi", target_os = "macos")) {
+        names.push("coremidi");
+    }
+    if cfg!(all(feature = "alsa", target_os = "linux")) {
+        names.push("alsa");
+    }
+    if cfg!(feature = "jack") {
+        names.push("jack");
+    }
+    names.push("none");
+    names
+}
+
+fn unknown_backend(backend: &str) -> String {
+    format!("unknown midi input \"{}\", have {}", backend, backends().join(", "))
+}
+
+/// Open an input by backend name, creating a port other programs can connect to
+/// If a source is given it gets connected, the names are whatever `sources` lists
+pub fn open(backend: &str, client_name: &str, port_name: &str, source: Option<&str>, callback: MidiCallback) -> Result<Box<dyn MidiInput>, String> {
+    match backend {
+        #[cfg(all(feature = "coremidi", target_os = "macos"))]
+        "coremidi" => {
+            let index = match source {
+                Some(source) => Some(source.parse::<usize>()
+                    .map_err(|_| format!("coremidi sources are numbered, not \"{}\"", source))?),
+                None => None
+            };
+            MidiWrap::new(client_name, port_name, index, callback)
+                .map(|input| Box::new(input) as Box<dyn MidiInput>)
+                .ok_or("couldn't create coremidi input".to_string())
+        }
+        #[cfg(all(feature = "alsa", target_os = "linux"))]
+        "alsa" => {
+            Ok(Box::new(AlsaSeqInput::open(client_name, port_name, source, callback)?))
+        }
+        #[cfg(feature = "jack")]
+        "jack" => {
+            Ok(Box::new(JackMidiInput::open(client_name, port_name, source, callback)?))
+        }
+        "none" => {
+            // names only mean something to the real backends
+            let _ = (client_name, port_name, callback);
+            match source {
+                Some(source) => Err(format!("no midi input to connect \"{}\" to", source)),
+                None => Ok(Box::new(NoInput))
+            }
+        }
+        _ => {
+            Err(unknown_backend(backend))
+        }
+    }
+}
+
+/// What an input could be connected to, for backends that can tell
+pub fn sources(backend: &str) -> Result<Vec<String>, String> {
+    match backend {
+        #[cfg(all(feature = "coremidi", target_os = "macos"))]
+        "coremidi" => {
+            Ok(core_midi::sources())
+        }
+        #[cfg(all(feature = "alsa", target_os = "linux"))]
+        "alsa" => {
+            alsa_seq::sources()
+        }
+        #[cfg(feature = "jack")]
+        "jack" => {
+            jack_midi::sources()
+        }
+        "none" => {
+            Ok(Vec::new())
+        }
+        _ => {
+            Err(unknown_backend(backend))
+        }
+    }
+}
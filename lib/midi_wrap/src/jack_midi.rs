@@ -0,0 +1,74 @@
+// jack midi input, a midi port on its own jack client
+
+use jack;
+
+use input::{MidiInput, MidiCallback};
+use parser::Parser;
+
+struct Process {
+    port: jack::Port<jack::MidiIn>,
+    parser: Parser,
+    callback: MidiCallback
+}
+
+impl jack::ProcessHandler for Process {
+    fn process(&mut self, _: &jack::Client, scope: &jack::ProcessScope) -> jack::Control {
+        let Process { ref port, ref mut parser, ref mut callback } = *self;
+        for raw in port.iter(scope) {
+            parser.parse(raw.bytes, |event| callback(event));
+        }
+        jack::Control::Continue
+    }
+}
+
+/// Midi output ports on the jack server, by full name
+pub fn sources() -> Result<Vec<String>, String> {
+    let (client, _) = jack::Client::new("organn_list", jack::ClientOptions::NO_START_SERVER)
+        .map_err(|e| format!("jack: {:?}", e))?;
+    Ok(client.ports(None, Some("8 bit raw midi"), jack::PortFlags::IS_OUTPUT))
+}
+
+pub struct JackMidiInput {
+    name: String,
+    active: Option<jack::AsyncClient<(), Process>>
+}
+
+impl JackMidiInput {
+    pub fn open(client_name: &str, port_name: &str, source: Option<&str>, callback: MidiCallback) -> Result<Self, String> {
+        let err = |e| format!("jack: {:?}", e);
+
+        let (client, _) = jack::Client::new(client_name, jack::ClientOptions::NO_START_SERVER).map_err(&err)?;
+        let port = client.register_port(port_name, jack::MidiIn::default()).map_err(&err)?;
+        let name = format!("{}:{}", client.name(), port_name);
+
+        let process = Process {
+            port,
+            parser: Parser::new(),
+            callback
+        };
+        let active = client.activate_async((), process).map_err(&err)?;
+
+        if let Some(source) = source {
+            active.as_client().connect_ports_by_name(source, &name).map_err(&err)?;
+        }
+
+        Ok(JackMidiInput {
+            name,
+            active: Some(active)
+        })
+    }
+}
+
+impl MidiInput for JackMidiInput {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for JackMidiInput {
+    fn drop(&mut self) {
+        if let Some(active) = self.active.take() {
+            active.deactivate().ok();
+        }
+    }
+}
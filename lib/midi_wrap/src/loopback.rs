@@ -0,0 +1,57 @@
+// in process midi input, bytes given to the sender come out of the input's callback
+// mostly for tests, nothing outside the process can reach it
+
+use input::{MidiInput, MidiCallback};
+use parser::Parser;
+
+use std::sync::{Arc, Mutex};
+
+// gone once the input is dropped
+type Shared = Arc<Mutex<Option<(Parser, MidiCallback)>>>;
+
+pub struct LoopbackInput {
+    shared: Shared
+}
+
+#[derive(Clone)]
+pub struct LoopbackSender {
+    shared: Shared
+}
+
+pub fn new(callback: MidiCallback) -> (LoopbackInput, LoopbackSender) {
+    let shared = Arc::new(Mutex::new(Some((Parser::new(), callback))));
+    (
+        LoopbackInput {
+            shared: shared.clone()
+        },
+        LoopbackSender {
+            shared: shared
+        }
+    )
+}
+
+impl LoopbackSender {
+    /// Send raw midi bytes, the callback runs on this thread before it returns
+    /// returns false if the input has been dropped
+    pub fn send(&self, bytes: &[u8]) -> bool {
+        match *self.shared.lock().unwrap() {
+            Some((ref mut parser, ref mut callback)) => {
+                parser.parse(bytes, |event| callback(event));
+                true
+            }
+            None => { false }
+        }
+    }
+}
+
+impl MidiInput for LoopbackInput {
+    fn name(&self) -> &str {
+        "loopback"
+    }
+}
+
+impl Drop for LoopbackInput {
+    fn drop(&mut self) {
+        *self.shared.lock().unwrap() = None;
+    }
+}
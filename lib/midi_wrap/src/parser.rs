@@ -0,0 +1,324 @@
+// stateful midi 1.0 byte stream parser
+// coremidi hands over packets that may hold several messages, use running status,
+// have realtime bytes dropped in anywhere or carry part of a sysex message, so state
+// has to be kept between packets
+
+use midi;
+
+// sysex messages longer than this are dropped rather than growing without limit
+const MAX_SYSEX_LEN: usize = 8192;
+
+/// Something parsed out of a midi byte stream
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A channel voice or channel mode message
+    Message(midi::Message),
+    /// A complete system exclusive message, without the F0/F7 framing bytes
+    SysEx(Vec<u8>),
+    /// A system common message (MTC quarter frame, song position, song select, tune request)
+    SystemCommon(u8, [u8; 2]),
+    /// A single byte realtime message (clock, start, continue, stop, active sensing, reset)
+    Realtime(u8),
+}
+
+pub struct Parser {
+    // status of the message being assembled, doubles as running status for channel messages
+    status: Option<u8>,
+    data: [u8; 2],
+    data_len: usize,
+    // Some while inside a sysex message
+    sysex: Option<Vec<u8>>,
+    sysex_overflow: bool,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser {
+            status: None,
+            data: [0; 2],
+            data_len: 0,
+            sysex: None,
+            sysex_overflow: false
+        }
+    }
+
+    /// Forget any partially received message and the running status
+    pub fn reset(&mut self) {
+        self.status = None;
+        self.data_len = 0;
+        self.sysex = None;
+        self.sysex_overflow = false;
+    }
+
+    /// Parse a chunk of the stream, calling `f` for every event completed within it
+    pub fn parse<F>(&mut self, bytes: &[u8], mut f: F) where F: FnMut(Event) {
+        for byte in bytes {
+            if let Some(event) = self.push(*byte) {
+                f(event);
+            }
+        }
+    }
+
+    /// Feed a single byte, returns an event if this byte completed one
+    pub fn push(&mut self, byte: u8) -> Option<Event> {
+        match byte {
+            // realtime bytes can turn up anywhere and don't disturb anything else
+            0xF8 | 0xFA | 0xFB | 0xFC | 0xFE | 0xFF => {
+                Some(Event::Realtime(byte))
+            }
+            0xF9 | 0xFD => {
+                // undefined realtime
+                None
+            }
+
+            0xF7 => {
+                // end of sysex, a stray one is ignored
+                self.status = None;
+                let sysex = self.sysex.take();
+                let overflow = self.sysex_overflow;
+                self.sysex_overflow = false;
+                match sysex {
+                    Some(data) if !overflow => { Some(Event::SysEx(data)) }
+                    _ => { None }
+                }
+            }
+
+            0x80..=0xFF => {
+                // any other status byte cuts off an unterminated sysex, which is dropped
+                self.sysex = None;
+                self.sysex_overflow = false;
+                self.data = [0; 2];
+                self.data_len = 0;
+
+                match byte {
+                    0xF0 => {
+                        self.status = None;
+                        self.sysex = Some(Vec::new());
+                        None
+                    }
+                    0xF6 => {
+                        // tune request has no data
+                        self.status = None;
+                        Some(Event::SystemCommon(byte, [0; 2]))
+                    }
+                    0xF1..=0xF3 => {
+                        // system common with data, cancels running status
+                        self.status = Some(byte);
+                        None
+                    }
+                    0xF4 | 0xF5 => {
+                        // undefined system common
+                        self.status = None;
+                        None
+                    }
+                    _ => {
+                        self.status = Some(byte);
+                        None
+                    }
+                }
+            }
+
+            _ => {
+                // data byte
+                if let Some(ref mut sysex) = self.sysex {
+                    if sysex.len() < MAX_SYSEX_LEN {
+                        sysex.push(byte);
+                    }
+                    else {
+                        self.sysex_overflow = true;
+                    }
+                    return None;
+                }
+
+                let status = match self.status {
+                    Some(status) => status,
+                    None => { return None; }     // no status to go with it
+                };
+
+                self.data[self.data_len] = byte;
+                self.data_len += 1;
+                if self.data_len < data_len(status) {
+                    return None;
+                }
+
+                self.data_len = 0;
+                if status >= 0xF0 {
+                    // no running status for system common
+                    self.status = None;
+                    Some(Event::SystemCommon(status, self.data))
+                }
+                else {
+                    Some(Event::Message(channel_message(status, self.data)))
+                }
+            }
+        }
+    }
+}
+
+// number of data bytes following a status byte
+fn data_len(status: u8) -> usize {
+    match status {
+        0xC0..=0xDF | 0xF1 | 0xF3 => 1,
+        _ => 2
+    }
+}
+
+fn channel_message(status: u8, data: [u8; 2]) -> midi::Message {
+    let (kind, channel) = midi::utils::from_status_byte(status);
+    match kind {
+        8 => { midi::NoteOff(channel, data[0], data[1]) }
+        9 if data[1] > 0 => { midi::NoteOn(channel, data[0], data[1]) }
+        9 => { midi::NoteOff(channel, data[0], data[1]) }
+        10 => { midi::PolyphonicPressure(channel, data[0], data[1]) }
+        11 if data[0] == 123 => { midi::AllNotesOff(channel) }
+        11 => { midi::ControlChange(channel, data[0], data[1]) }
+        12 => { midi::ProgramChange(channel, data[0]) }
+        13 => { midi::ChannelPressure(channel, data[0]) }
+        _ => { midi::PitchBend(channel, ((data[1] as u16) << 7) | data[0] as u16) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midi::Message::*;
+
+    fn ch(number: u8) -> midi::Channel {
+        midi::utils::from_status_byte(0x90 | (number - 1)).1
+    }
+
+    // every event from a stream arriving as these packets
+    fn parse(packets: &[&[u8]]) -> Vec<Event> {
+        let mut parser = Parser::new();
+        let mut events = Vec::new();
+        for packet in packets {
+            parser.parse(packet, |e| events.push(e));
+        }
+        events
+    }
+
+    fn message(message: midi::Message) -> Event {
+        Event::Message(message)
+    }
+
+    #[test]
+    fn running_status_carries_across_packets() {
+        let events = parse(&[&[0x90, 60, 100, 62], &[101], &[64, 102, 0xB1, 7], &[90, 11, 80]]);
+        assert_eq!(events, vec![
+            message(NoteOn(ch(1), 60, 100)),
+            message(NoteOn(ch(1), 62, 101)),
+            message(NoteOn(ch(1), 64, 102)),
+            message(ControlChange(ch(2), 7, 90)),
+            message(ControlChange(ch(2), 11, 80))
+        ]);
+    }
+
+    #[test]
+    fn realtime_bytes_interleave_without_disturbing_anything() {
+        let events = parse(&[&[0x90, 0xF8, 60, 0xFE, 100, 0xF0, 0x7E, 0xFA, 0x01, 0xFC, 0xF7, 62, 0xF8, 90]]);
+        assert_eq!(events, vec![
+            Event::Realtime(0xF8),
+            Event::Realtime(0xFE),
+            message(NoteOn(ch(1), 60, 100)),
+            Event::Realtime(0xFA),
+            Event::Realtime(0xFC),
+            Event::SysEx(vec![0x7E, 0x01]),
+            // sysex cancels running status, so 62 is a stray
+            Event::Realtime(0xF8)
+        ]);
+
+        // undefined realtime bytes are dropped
+        assert_eq!(parse(&[&[0x80, 0xF9, 60, 0xFD, 0]]), vec![message(NoteOff(ch(1), 60, 0))]);
+    }
+
+    #[test]
+    fn sysex_is_reassembled_across_pushes() {
+        let mut parser = Parser::new();
+        let mut events = Vec::new();
+        for byte in &[0xF0, 0x7F, 0x7F, 0x08, 0x02, 0x00, 0x01, 0xF7] {
+            events.extend(parser.push(*byte));
+        }
+        assert_eq!(events, vec![Event::SysEx(vec![0x7F, 0x7F, 0x08, 0x02, 0x00, 0x01])]);
+
+        assert_eq!(parse(&[&[0xF0, 1], &[2, 3], &[], &[4, 0xF7]]), vec![Event::SysEx(vec![1, 2, 3, 4])]);
+    }
+
+    #[test]
+    fn oversized_and_unterminated_sysex_are_dropped() {
+        let mut long = vec![0xF0];
+        long.extend(vec![0x55; MAX_SYSEX_LEN + 1]);
+        long.push(0xF7);
+        assert_eq!(parse(&[&long]), vec![]);
+
+        let mut longest = vec![0xF0];
+        longest.extend(vec![0x55; MAX_SYSEX_LEN]);
+        longest.push(0xF7);
+        assert_eq!(parse(&[&longest]), vec![Event::SysEx(vec![0x55; MAX_SYSEX_LEN])]);
+
+        // the parser is ready for the next one either way
+        assert_eq!(parse(&[&long, &[0xF0, 1, 0xF7]]), vec![Event::SysEx(vec![1])]);
+        // a status byte cuts off a sysex, a stray end is ignored
+        assert_eq!(parse(&[&[0xF0, 1, 2, 0x90, 60, 100, 0xF7]]), vec![message(NoteOn(ch(1), 60, 100))]);
+    }
+
+    #[test]
+    fn stray_data_bytes_are_ignored() {
+        assert_eq!(parse(&[&[60, 100, 0x90, 60, 100]]), vec![message(NoteOn(ch(1), 60, 100))]);
+
+        // and so is the rest of a message cut short, after a reset too
+        let mut parser = Parser::new();
+        assert!(parser.push(0x90).is_none() && parser.push(60).is_none());
+        parser.reset();
+        assert!(parser.push(100).is_none() && parser.push(61).is_none());
+    }
+
+    #[test]
+    fn note_on_at_velocity_zero_is_note_off() {
+        assert_eq!(parse(&[&[0x93, 60, 0, 61, 1]]), vec![
+            message(NoteOff(ch(4), 60, 0)),
+            message(NoteOn(ch(4), 61, 1))
+        ]);
+    }
+
+    #[test]
+    fn channel_messages_decode() {
+        let events = parse(&[&[
+            0xE0, 0x00, 0x40,
+            0xE1, 0x7F, 0x7F,
+            0xE2, 0x00, 0x00,
+            0xC5, 12, 13,
+            0xD6, 100,
+            0xA7, 60, 70,
+            0x8F, 60, 64,
+            0xB0, 123, 0
+        ]]);
+        assert_eq!(events, vec![
+            message(PitchBend(ch(1), 8192)),
+            message(PitchBend(ch(2), 16383)),
+            message(PitchBend(ch(3), 0)),
+            message(ProgramChange(ch(6), 12)),
+            // one data byte each, running status
+            message(ProgramChange(ch(6), 13)),
+            message(ChannelPressure(ch(7), 100)),
+            message(PolyphonicPressure(ch(8), 60, 70)),
+            message(NoteOff(ch(16), 60, 64)),
+            message(AllNotesOff(ch(1)))
+        ]);
+    }
+
+    #[test]
+    fn system_common_cancels_running_status() {
+        let events = parse(&[&[0x90, 60, 100, 0xF2, 0x10, 0x20, 61, 100, 0xF1, 0x35, 0xF3, 3, 0xF6, 0x90, 62, 100]]);
+        assert_eq!(events, vec![
+            message(NoteOn(ch(1), 60, 100)),
+            Event::SystemCommon(0xF2, [0x10, 0x20]),
+            Event::SystemCommon(0xF1, [0x35, 0]),
+            Event::SystemCommon(0xF3, [3, 0]),
+            Event::SystemCommon(0xF6, [0, 0]),
+            message(NoteOn(ch(1), 62, 100))
+        ]);
+
+        // undefined system common too
+        assert_eq!(parse(&[&[0x90, 60, 100, 0xF4, 61, 100, 0xF5, 62, 100]]), vec![message(NoteOn(ch(1), 60, 100))]);
+    }
+}
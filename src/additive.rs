@@ -0,0 +1,214 @@
+// all of a voice's partials in one pass, each one's drawbar gain applied as it's summed
+// rather than every partial rendered into a buffer of its own and mixed afterwards
+// on x86_64 partials are rendered four samples at a time with SSE2 and a polynomial sine,
+// elsewhere (or when asked) a scalar loop uses one of the oscillator's sine methods, the table
+// unless asked otherwise as it's the quicker (see the oscillator and additive benchmarks)
+
+use basic_types::{AudioBuffer, BUFFER_SIZE};
+use basic_types::graph::{Node, Inputs};
+use oscillator::{self, SineMethod};
+
+use std::f32::consts::PI;
+
+pub const PARTIALS: usize = 9;
+
+/// How the partials get rendered
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdditiveMethod {
+    /// Vectorized, only where the cpu has it, see simd_available
+    Simd,
+    Scalar(SineMethod)
+}
+
+/// The vectorized renderer can be used on this machine
+pub fn simd_available() -> bool {
+    simd::AVAILABLE
+}
+
+/// Simd where there is any, otherwise the table, the order benches/dsp.rs puts them in
+/// rather than anything measured on this machine
+pub fn fastest_method() -> AdditiveMethod {
+    if simd_available() {
+        AdditiveMethod::Simd
+    }
+    else {
+        AdditiveMethod::Scalar(SineMethod::Table)
+    }
+}
+
+pub struct Additive {
+    pos: [u32; PARTIALS],
+    increment: [u32; PARTIALS],
+    freq: [f32; PARTIALS],
+    level: [f32; PARTIALS],
+    sample_rate: u32,
+    method: AdditiveMethod,
+    table: &'static [f32]
+}
+
+impl Additive {
+    pub fn new(sample_rate: u32) -> Self {
+        Additive::with_method(sample_rate, fastest_method())
+    }
+
+    /// Simd falls back to the table where it isn't available
+    pub fn with_method(sample_rate: u32, method: AdditiveMethod) -> Self {
+        let method = match method {
+            AdditiveMethod::Simd if !simd_available() => AdditiveMethod::Scalar(SineMethod::Table),
+            _ => method
+        };
+
+        Additive {
+            pos: [0; PARTIALS],
+            increment: [0; PARTIALS],
+            freq: [0.0; PARTIALS],
+            level: [0.0; PARTIALS],
+            sample_rate,
+            method,
+            table: oscillator::sine_table()
+        }
+    }
+
+    pub fn method(&self) -> AdditiveMethod {
+        self.method
+    }
+
+    /// Same as Oscillator::set_freq, a partial too high for the rate goes silent
+    pub fn set_freq(&mut self, partial: usize, freq: f32) {
+        self.freq[partial] = freq;
+        match oscillator::phase_increment(freq, self.sample_rate) {
+            Some(increment) => { self.increment[partial] = increment; }
+            None => {
+                self.increment[partial] = 0;
+                self.pos[partial] = 0;
+            }
+        }
+    }
+
+    /// Gain the partial is summed at, as Mixer::set_level
+    pub fn set_level(&mut self, partial: usize, level: f32) {
+        self.level[partial] = level;
+    }
+
+}
+
+impl Node for Additive {
+    fn num_inputs(&self) -> usize {
+        0
+    }
+
+    fn process(&mut self, _inputs: &Inputs, samples: &mut AudioBuffer) {
+        for sample in samples.iter_mut() {
+            *sample = 0.0;
+        }
+
+        for partial in 0..PARTIALS {
+            let (pos, increment, level) = (self.pos[partial], self.increment[partial], self.level[partial]);
+            // a drawbar pushed all the way in adds nothing, its phase still moves on
+            if level != 0.0 {
+                match self.method {
+                    AdditiveMethod::Simd => simd::add_partial(samples, pos, increment, level),
+                    AdditiveMethod::Scalar(SineMethod::Sin) => add_partial_sin(samples, pos, increment, level),
+                    AdditiveMethod::Scalar(SineMethod::Table) => add_partial_table(self.table, samples, pos, increment, level)
+                }
+            }
+            self.pos[partial] = pos.wrapping_add(increment.wrapping_mul(BUFFER_SIZE as u32));
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        for partial in 0..PARTIALS {
+            let freq = self.freq[partial];
+            self.set_freq(partial, freq);
+        }
+    }
+}
+
+fn add_partial_sin(samples: &mut AudioBuffer, mut pos: u32, increment: u32, level: f32) {
+    for sample in samples.iter_mut() {
+        // the same sum Oscillator does
+        *sample += (((pos as f32) * (PI * 2.0)) / 2_f32.powi(32)).sin() * level;
+        pos = pos.wrapping_add(increment);
+    }
+}
+
+fn add_partial_table(table: &[f32], samples: &mut AudioBuffer, mut pos: u32, increment: u32, level: f32) {
+    for sample in samples.iter_mut() {
+        *sample += oscillator::table_sine(table, pos) * level;
+        pos = pos.wrapping_add(increment);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use basic_types::{AudioBuffer, BUFFER_SIZE};
+
+    use std::arch::x86_64::*;
+    use std::f32::consts::PI;
+
+    // sse2 is part of x86_64
+    pub const AVAILABLE: bool = true;
+
+    // blocks are rendered four samples at a time
+    const _: () = assert!(BUFFER_SIZE.is_multiple_of(4));
+
+    const PHASE_SCALE: f32 = 1.0 / 4_294_967_296.0;
+
+    // taylor series for sine to x^11, within 1e-7 over -pi/2 to pi/2
+    const C3: f32 = -1.0 / 6.0;
+    const C5: f32 = 1.0 / 120.0;
+    const C7: f32 = -1.0 / 5_040.0;
+    const C9: f32 = 1.0 / 362_880.0;
+    const C11: f32 = -1.0 / 39_916_800.0;
+
+    // sine of four fixed point phases
+    #[inline(always)]
+    unsafe fn sine(phase: __m128i) -> __m128 {
+        // the phase as signed is the same angle between -half a cycle and half a cycle
+        let t = _mm_mul_ps(_mm_cvtepi32_ps(phase), _mm_set1_ps(PHASE_SCALE));
+        // fold into a quarter cycle either side of zero, sin(x) = sin(pi - x)
+        let sign_bit = _mm_set1_ps(-0.0);
+        let sign = _mm_and_ps(t, sign_bit);
+        let a = _mm_andnot_ps(sign_bit, t);
+        let folded = _mm_or_ps(_mm_min_ps(a, _mm_sub_ps(_mm_set1_ps(0.5), a)), sign);
+
+        let x = _mm_mul_ps(folded, _mm_set1_ps(PI * 2.0));
+        let x2 = _mm_mul_ps(x, x);
+        let mut p = _mm_set1_ps(C11);
+        p = _mm_add_ps(_mm_mul_ps(p, x2), _mm_set1_ps(C9));
+        p = _mm_add_ps(_mm_mul_ps(p, x2), _mm_set1_ps(C7));
+        p = _mm_add_ps(_mm_mul_ps(p, x2), _mm_set1_ps(C5));
+        p = _mm_add_ps(_mm_mul_ps(p, x2), _mm_set1_ps(C3));
+        p = _mm_add_ps(_mm_mul_ps(p, x2), _mm_set1_ps(1.0));
+        _mm_mul_ps(p, x)
+    }
+
+    pub fn add_partial(samples: &mut AudioBuffer, pos: u32, increment: u32, level: f32) {
+        unsafe {
+            let mut phase = _mm_setr_epi32(pos as i32,
+                                           pos.wrapping_add(increment) as i32,
+                                           pos.wrapping_add(increment.wrapping_mul(2)) as i32,
+                                           pos.wrapping_add(increment.wrapping_mul(3)) as i32);
+            let step = _mm_set1_epi32(increment.wrapping_mul(4) as i32);
+            let level = _mm_set1_ps(level);
+
+            for chunk in samples.chunks_exact_mut(4) {
+                let sum = _mm_add_ps(_mm_loadu_ps(chunk.as_ptr()), _mm_mul_ps(sine(phase), level));
+                _mm_storeu_ps(chunk.as_mut_ptr(), sum);
+                phase = _mm_add_epi32(phase, step);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod simd {
+    use basic_types::AudioBuffer;
+
+    pub const AVAILABLE: bool = false;
+
+    pub fn add_partial(_samples: &mut AudioBuffer, _pos: u32, _increment: u32, _level: f32) {
+        unreachable!("Additive never picks Simd without it");
+    }
+}
@@ -0,0 +1,122 @@
+// alsa output on linux, plays from its own thread with blocking writes
+
+use alsa::{Direction, ValueOr};
+use alsa::device_name::HintIter;
+use alsa::pcm::{PCM, HwParams, Format, Access, Frames};
+
+use audio::{AudioBackend, Renderer};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+const CHANNELS: usize = 2;
+
+pub struct AlsaBackend {
+    device: String,
+    pcm: Option<PCM>,
+    sample_rate: u32,
+    period: usize,
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>
+}
+
+impl AlsaBackend {
+    pub fn open(device: &str, sample_rate: u32, period: usize) -> Result<Self, String> {
+        let err = |e| format!("alsa {}: {}", device, e);
+
+        let pcm = PCM::new(device, Direction::Playback, false).map_err(&err)?;
+        {
+            let hwp = HwParams::any(&pcm).map_err(&err)?;
+            hwp.set_channels(CHANNELS as u32).map_err(&err)?;
+            hwp.set_rate(sample_rate, ValueOr::Nearest).map_err(&err)?;
+            hwp.set_format(Format::float()).map_err(&err)?;
+            hwp.set_access(Access::RWInterleaved).map_err(&err)?;
+            hwp.set_period_size_near(period as Frames, ValueOr::Nearest).map_err(&err)?;
+            pcm.hw_params(&hwp).map_err(&err)?;
+        }
+
+        // the device may not have managed exactly what was asked for
+        let (actual_rate, actual_period) = {
+            let hwp = pcm.hw_params_current().map_err(&err)?;
+            (hwp.get_rate().map_err(&err)?, hwp.get_period_size().map_err(&err)? as usize)
+        };
+        if actual_period != period {
+            info!("alsa {}: block size is {} rather than {}", device, actual_period, period);
+        }
+
+        Ok(AlsaBackend {
+            device: device.to_string(),
+            pcm: Some(pcm),
+            sample_rate: actual_rate,
+            period: actual_period,
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None
+        })
+    }
+}
+
+/// Playback devices alsa knows about, as names that can be opened
+pub fn devices() -> Result<Vec<String>, String> {
+    let hints = HintIter::new_str(None, "pcm").map_err(|e| format!("alsa: {}", e))?;
+    Ok(hints
+        .filter(|hint| hint.direction != Some(Direction::Capture))
+        .filter_map(|hint| hint.name)
+        .collect())
+}
+
+impl AudioBackend for AlsaBackend {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn start(&mut self, mut renderer: Renderer) -> Result<(), String> {
+        let pcm = self.pcm.take().ok_or(format!("alsa {}: already started", self.device))?;
+        let period = self.period;
+        let running = self.running.clone();
+        running.store(true, Ordering::Relaxed);
+
+        self.thread = Some(thread::spawn(move || {
+                let io = pcm.io_f32().unwrap();
+                let stats = renderer.stats();
+                let mut buf = vec![0.0; period * CHANNELS];
+
+                while running.load(Ordering::Relaxed) {
+                    renderer.render_interleaved(&mut buf, CHANNELS);
+
+                    let mut written = 0;
+                    while written < period {
+                        match io.writei(&buf[(written * CHANNELS)..]) {
+                            Ok(frames) => { written += frames; }
+                            Err(e) => {
+                                // underrun or suspend, recover and carry on
+                                warn!("alsa: {}", e);
+                                stats.record_xrun();
+                                if pcm.try_recover(e, true).is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+                pcm.drain().ok();
+            }));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("alsa {}: the playback thread panicked", self.device);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AlsaBackend {
+    fn drop(&mut self) {
+        self.stop().ok();
+    }
+}
@@ -0,0 +1,76 @@
+// coreaudio output on osx
+
+use coreaudio_rs::audio_unit::{AudioUnit, Type, SubType};
+
+use audio::{AudioBackend, Renderer};
+
+pub struct CoreAudioBackend {
+    audio_unit: AudioUnit,
+    sample_rate: u32,
+    running: bool
+}
+
+impl CoreAudioBackend {
+    pub fn open(sample_rate: Option<u32>) -> Result<Self, String> {
+        // Construct an Output audio unit.
+        let mut audio_unit = AudioUnit::new(Type::Output, SubType::HalOutput)
+            .map_err(|e| format!("couldn't open coreaudio output: {:?}", e))?;
+
+        // the unit starts out at the device's rate, only change it if asked to
+        if let Some(rate) = sample_rate {
+            if let Err(e) = audio_unit.set_sample_rate(rate as f64) {
+                warn!("coreaudio couldn't run at {}Hz: {:?}", rate, e);
+            }
+        }
+        // the hal unit converts if the device changes rate under it, so this rate holds
+        // for as long as the unit is open
+        let sample_rate = audio_unit.sample_rate()
+            .map_err(|e| format!("couldn't get coreaudio sample rate: {:?}", e))? as u32;
+
+        Ok(CoreAudioBackend {
+            audio_unit,
+            sample_rate,
+            running: false
+        })
+    }
+}
+
+impl AudioBackend for CoreAudioBackend {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn start(&mut self, mut renderer: Renderer) -> Result<(), String> {
+        self.audio_unit.render_callback(Some(Box::new(move |buffer, num_frames| {
+                if buffer.is_empty() {
+                    return Ok(());
+                }
+                let (first, rest) = buffer.split_at_mut(1);
+                renderer.render(&mut first[0][..num_frames]);
+                for channel in rest.iter_mut() {
+                    channel[..num_frames].copy_from_slice(&first[0][..num_frames]);
+                }
+                Ok(())
+            }))).map_err(|e| format!("couldn't set coreaudio callback: {:?}", e))?;
+        self.audio_unit.start().map_err(|e| format!("couldn't start coreaudio: {:?}", e))?;
+        self.running = true;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        if self.running {
+            self.running = false;
+            self.audio_unit.stop().map_err(|e| format!("couldn't stop coreaudio: {:?}", e))?;
+            self.audio_unit.render_callback(None).map_err(|e| format!("couldn't remove the coreaudio callback: {:?}", e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CoreAudioBackend {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            error!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// jack output, jack picks the sample rate and calls back for audio
+
+use jack;
+
+use audio::{AudioBackend, Renderer};
+use stats::EngineStats;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const PORT_NAMES: [&str; 2] = ["out_1", "out_2"];
+
+// jack tells us about rate changes on its notification thread, the process thread
+// picks them up from here
+struct Notifications {
+    sample_rate: Arc<AtomicU32>,
+    stats: Arc<EngineStats>
+}
+
+impl jack::NotificationHandler for Notifications {
+    fn sample_rate(&mut self, _: &jack::Client, srate: jack::Frames) -> jack::Control {
+        self.sample_rate.store(srate, Ordering::Relaxed);
+        jack::Control::Continue
+    }
+
+    fn xrun(&mut self, _: &jack::Client) -> jack::Control {
+        self.stats.record_xrun();
+        jack::Control::Continue
+    }
+}
+
+struct Process {
+    renderer: Renderer,
+    ports: Vec<jack::Port<jack::AudioOut>>,
+    sample_rate: Arc<AtomicU32>,
+    current_rate: u32
+}
+
+impl jack::ProcessHandler for Process {
+    fn process(&mut self, _: &jack::Client, scope: &jack::ProcessScope) -> jack::Control {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        if sample_rate != self.current_rate {
+            self.renderer.set_sample_rate(sample_rate);
+            self.current_rate = sample_rate;
+        }
+
+        let (first, rest) = self.ports.split_at_mut(1);
+        let out = first[0].as_mut_slice(scope);
+        self.renderer.render(out);
+        for port in rest.iter_mut() {
+            port.as_mut_slice(scope).copy_from_slice(out);
+        }
+        jack::Control::Continue
+    }
+}
+
+pub struct JackBackend {
+    client: Option<jack::Client>,
+    active: Option<jack::AsyncClient<Notifications, Process>>,
+    sample_rate: Arc<AtomicU32>
+}
+
+impl JackBackend {
+    pub fn open(name: &str, requested_rate: Option<u32>) -> Result<Self, String> {
+        let (client, _) = jack::Client::new(name, jack::ClientOptions::NO_START_SERVER)
+            .map_err(|e| format!("couldn't connect to jack: {:?}", e))?;
+        let sample_rate = client.sample_rate() as u32;
+        // jack decides the rate and block size, they can only be changed on the jack server
+        if let Some(rate) = requested_rate.filter(|rate| *rate != sample_rate) {
+            warn!("jack runs at {}Hz, not {}Hz", sample_rate, rate);
+        }
+        info!("jack runs at {}Hz, {} frames at a time", sample_rate, client.buffer_size());
+
+        Ok(JackBackend {
+            client: Some(client),
+            active: None,
+            sample_rate: Arc::new(AtomicU32::new(sample_rate))
+        })
+    }
+}
+
+impl AudioBackend for JackBackend {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    fn start(&mut self, renderer: Renderer) -> Result<(), String> {
+        let client = self.client.take().ok_or("jack already started".to_string())?;
+        let err = |e| format!("jack: {:?}", e);
+
+        let mut ports = Vec::new();
+        for name in PORT_NAMES.iter() {
+            ports.push(client.register_port(name, jack::AudioOut::default()).map_err(&err)?);
+        }
+        let client_name = client.name().to_string();
+
+        let notifications = Notifications {
+            sample_rate: self.sample_rate.clone(),
+            stats: renderer.stats()
+        };
+        let process = Process {
+            renderer,
+            ports,
+            sample_rate: self.sample_rate.clone(),
+            current_rate: self.sample_rate.load(Ordering::Relaxed)
+        };
+        let active = client.activate_async(notifications, process).map_err(&err)?;
+
+        // connect to the system outputs if they're there, not being able to is fine
+        let playback = active.as_client().ports(Some("system:playback_.*"), None, jack::PortFlags::IS_INPUT);
+        for (name, dest) in PORT_NAMES.iter().zip(playback.iter()) {
+            active.as_client().connect_ports_by_name(&format!("{}:{}", client_name, name), dest).ok();
+        }
+
+        self.active = Some(active);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        if let Some(active) = self.active.take() {
+            active.deactivate().ok();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for JackBackend {
+    fn drop(&mut self) {
+        self.stop().ok();
+    }
+}
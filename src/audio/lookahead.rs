@@ -0,0 +1,77 @@
+// renders ahead of the audio callback on a thread of its own, waiting on the voice threads
+// happens here so a late one costs the device a block of silence rather than a stall
+
+use basic_types::{AudioBuffer, BLANK_BUFFER};
+use audio::Engine;
+use rb::{RB, SpscRb, Consumer, RbProducer, RbConsumer};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::thread;
+
+pub struct Lookahead {
+    blocks: Consumer<AudioBuffer>,
+    // a rate change waiting for the render thread, 0 for none
+    sample_rate: Arc<AtomicU32>,
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>
+}
+
+impl Lookahead {
+    /// Start rendering, keeping up to depth blocks ready
+    pub fn start(mut engine: Engine, depth: usize) -> Self {
+        let ring = SpscRb::new(depth.max(1));
+        let (producer, consumer) = (ring.producer(), ring.consumer());
+
+        let sample_rate = Arc::new(AtomicU32::new(0));
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_rate = sample_rate.clone();
+        let thread_running = running.clone();
+
+        let thread = thread::spawn(move || {
+                while thread_running.load(Ordering::Relaxed) {
+                    let rate = thread_rate.swap(0, Ordering::Relaxed);
+                    if rate != 0 {
+                        engine.set_sample_rate(rate);
+                    }
+                    let block = engine.render_block();
+                    producer.write_blocking(&[block]);
+                }
+            });
+
+        Lookahead {
+            blocks: consumer,
+            sample_rate,
+            running,
+            thread: Some(thread)
+        }
+    }
+
+    /// The next block if it's been rendered, never waits
+    pub fn next_block(&self) -> Option<AudioBuffer> {
+        let mut block = [BLANK_BUFFER; 1];
+        match self.blocks.read(&mut block) {
+            Ok(1) => Some(block[0]),
+            _ => None
+        }
+    }
+
+    /// Blocks already in the queue were rendered at the old rate, the change follows them
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Lookahead {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        // make room so a render thread waiting on a full queue can finish its write and see
+        // it's been stopped, it only ever writes once more
+        self.next_block();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("the lookahead render thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+// audio output backends
+// a backend owns the device, agrees a sample rate with it and then pulls audio from a Renderer
+// in whatever size chunks the device wants
+
+use basic_types::{BLANK_BUFFER, BUFFER_SIZE, AudioBuffer};
+use multi::Multi;
+use stats::EngineStats;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(all(feature = "coreaudio", target_os = "macos"))]
+mod coreaudio;
+#[cfg(all(feature = "alsa", target_os = "linux"))]
+mod alsa;
+#[cfg(feature = "jack")]
+mod jack;
+mod wav_file;
+mod null;
+mod lookahead;
+
+use self::lookahead::Lookahead;
+
+const DEFAULT_WAV_PATH: &str = "organn.wav";
+/// The rate asked for when nothing else says otherwise
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+pub trait AudioBackend {
+    /// The sample rate the device actually ended up at, build the engine at this rate
+    fn sample_rate(&self) -> u32;
+
+    /// Start pulling audio from the renderer
+    fn start(&mut self, renderer: Renderer) -> Result<(), String>;
+
+    /// Stop the audio, the renderer is dropped. An error that stopped the audio early is
+    /// given here as well as being recorded in the stats when it happened
+    fn stop(&mut self) -> Result<(), String>;
+}
+
+// the engine, rendered a block at a time
+struct Engine {
+    multi: Multi
+}
+
+impl Engine {
+    fn render_block(&mut self) -> AudioBuffer {
+        self.multi.run();
+        *self.multi.output()
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.multi.set_sample_rate(sample_rate);
+    }
+}
+
+enum Source {
+    // rendered on the audio thread as it's needed, boxed as the engine is much bigger
+    // than a lookahead handle
+    Direct(Box<Engine>),
+    Lookahead(Lookahead)
+}
+
+/// The engine as seen by a backend, turns organn's fixed size blocks into any size of buffer
+pub struct Renderer {
+    source: Source,
+    buf: AudioBuffer,
+    pos: usize,
+    stats: Arc<EngineStats>,
+    // a block was missing during this callback
+    underran: bool
+}
+
+impl Renderer {
+    /// Lookahead is how many blocks to render ahead of the device on another thread, with
+    /// any at all the audio thread never waits and plays silence if the engine falls behind.
+    /// With none the engine runs on the audio thread
+    pub fn new(multi: Multi, lookahead: usize) -> Self {
+        let stats = multi.stats();
+        let engine = Engine {
+            multi
+        };
+        let source = if lookahead > 0 {
+            Source::Lookahead(Lookahead::start(engine, lookahead))
+        }
+        else {
+            Source::Direct(Box::new(engine))
+        };
+
+        Renderer {
+            source,
+            buf: BLANK_BUFFER,
+            pos: BUFFER_SIZE,   // start at the end to trigger fetching audio
+            stats,
+            underran: false
+        }
+    }
+
+    /// The engine's stats, a callback with any silence in it because the engine wasn't ready
+    /// counts as an xrun, backends add any the device reports
+    pub fn stats(&self) -> Arc<EngineStats> {
+        self.stats.clone()
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        if self.pos >= self.buf.len() {
+            self.buf = match self.source {
+                Source::Direct(ref mut engine) => engine.render_block(),
+                Source::Lookahead(ref lookahead) => {
+                    let underran = &mut self.underran;
+                    lookahead.next_block().unwrap_or_else(|| {
+                        *underran = true;
+                        BLANK_BUFFER
+                    })
+                }
+            };
+            self.pos = 0;
+        }
+        let sample = self.buf[self.pos];
+        self.pos += 1;
+        sample
+    }
+
+    /// The device changed rate while running, for backends that get told
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        match self.source {
+            Source::Direct(ref mut engine) => engine.set_sample_rate(sample_rate),
+            Source::Lookahead(ref lookahead) => lookahead.set_sample_rate(sample_rate)
+        }
+    }
+
+    /// Fill a mono buffer
+    pub fn render(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = self.next_sample();
+        }
+        self.end_callback();
+    }
+
+    /// Fill an interleaved buffer, every channel gets the same
+    pub fn render_interleaved(&mut self, out: &mut [f32], channels: usize) {
+        for frame in out.chunks_mut(channels) {
+            let sample = self.next_sample();
+            for channel in frame.iter_mut() {
+                *channel = sample;
+            }
+        }
+        self.end_callback();
+    }
+
+    // one xrun for a callback however many blocks it was short
+    fn end_callback(&mut self) {
+        if self.underran {
+            self.stats.record_xrun();
+            self.underran = false;
+        }
+    }
+}
+
+// for backends without a device to keep time, renders a period at a time on its own thread
+// and sleeps until the next one is due, so midi input plays in real time
+struct SoftwareClock {
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<Result<(), String>>>
+}
+
+impl SoftwareClock {
+    // the clock stops at the first error from the sink
+    fn start<F>(mut renderer: Renderer, sample_rate: u32, period: usize, mut sink: F) -> Self
+        where F: FnMut(&[f32]) -> Result<(), String> + Send + 'static {
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+
+        let thread = thread::spawn(move || {
+                let mut buf = vec![0.0; period];
+                let period_time = Duration::from_secs(period as u64) / sample_rate;
+                let mut next = Instant::now();
+
+                while thread_running.load(Ordering::Relaxed) {
+                    renderer.render(&mut buf);
+                    if let Err(e) = sink(&buf) {
+                        renderer.stats().record_error(e.clone());
+                        return Err(e);
+                    }
+
+                    next += period_time;
+                    let now = Instant::now();
+                    if next > now {
+                        thread::sleep(next - now);
+                    }
+                }
+                Ok(())
+            });
+
+        SoftwareClock {
+            running,
+            thread: Some(thread)
+        }
+    }
+
+    // the sink's error if it stopped the clock
+    fn stop(&mut self) -> Result<(), String> {
+        self.running.store(false, Ordering::Relaxed);
+        match self.thread.take().map(|thread| thread.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => {
+                error!("the audio clock's thread panicked");
+                Ok(())
+            }
+            None => Ok(())
+        }
+    }
+}
+
+/// Names of the backends built in, the first is the default
+pub fn backends() -> Vec<&'static str> {
+    let mut names = Vec::new();
+    if cfg!(all(feature = "coreaudio", target_os = "macos")) {
+        names.push("coreaudio");
+    }
+    if cfg!(feature = "jack") {
+        names.push("jack");
+    }
+    if cfg!(all(feature = "alsa", target_os = "linux")) {
+        names.push("alsa");
+    }
+    names.push("wav");
+    names.push("null");
+    names
+}
+
+fn unknown_backend(backend: &str) -> String {
+    format!("unknown audio backend \"{}\", have {}", backend, backends().join(", "))
+}
+
+/// Open a backend by name, the device is backend specific (a file name for wav)
+/// Without a sample rate a device runs at its own, the block size is the number of frames
+/// asked for at a time, where the backend gets a say
+pub fn open(backend: &str, device: Option<&str>, sample_rate: Option<u32>, block_size: usize) -> Result<Box<dyn AudioBackend>, String> {
+    match backend {
+        #[cfg(all(feature = "coreaudio", target_os = "macos"))]
+        "coreaudio" => {
+            Ok(Box::new(coreaudio::CoreAudioBackend::open(sample_rate)?))
+        }
+        #[cfg(all(feature = "alsa", target_os = "linux"))]
+        "alsa" => {
+            Ok(Box::new(alsa::AlsaBackend::open(device.unwrap_or("default"), sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE), block_size)?))
+        }
+        #[cfg(feature = "jack")]
+        "jack" => {
+            Ok(Box::new(jack::JackBackend::open(device.unwrap_or("organn"), sample_rate)?))
+        }
+        "wav" => {
+            Ok(Box::new(wav_file::WavBackend::open(device.unwrap_or(DEFAULT_WAV_PATH), sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE), block_size)?))
+        }
+        "null" => {
+            Ok(Box::new(null::NullBackend::new(sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE), block_size)))
+        }
+        _ => {
+            Err(unknown_backend(backend))
+        }
+    }
+}
+
+/// Devices a backend could open, for backends that can tell
+pub fn devices(backend: &str) -> Result<Vec<String>, String> {
+    match backend {
+        #[cfg(all(feature = "coreaudio", target_os = "macos"))]
+        "coreaudio" => {
+            // only the default output is supported
+            Ok(vec!["default".to_string()])
+        }
+        #[cfg(all(feature = "alsa", target_os = "linux"))]
+        "alsa" => {
+            alsa::devices()
+        }
+        #[cfg(feature = "jack")]
+        "jack" => {
+            // the device is the jack client name
+            Ok(Vec::new())
+        }
+        "wav" | "null" => {
+            Ok(Vec::new())
+        }
+        _ => {
+            Err(unknown_backend(backend))
+        }
+    }
+}
@@ -0,0 +1,43 @@
+// plays to nowhere, in real time, for running without a sound card
+
+use audio::{AudioBackend, Renderer, SoftwareClock};
+
+pub struct NullBackend {
+    sample_rate: u32,
+    period: usize,
+    clock: Option<SoftwareClock>
+}
+
+impl NullBackend {
+    pub fn new(sample_rate: u32, period: usize) -> Self {
+        NullBackend {
+            sample_rate,
+            period: period.max(1),
+            clock: None
+        }
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn start(&mut self, renderer: Renderer) -> Result<(), String> {
+        self.clock = Some(SoftwareClock::start(renderer, self.sample_rate, self.period, |_| Ok(())));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        match self.clock.take() {
+            Some(mut clock) => clock.stop(),
+            None => Ok(())
+        }
+    }
+}
+
+impl Drop for NullBackend {
+    fn drop(&mut self) {
+        self.stop().ok();
+    }
+}
@@ -0,0 +1,69 @@
+// records to a wav file in real time, whatever gets played over midi ends up in the file
+
+use audio::{AudioBackend, Renderer, SoftwareClock};
+use wav::WavWriter;
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+pub struct WavBackend {
+    path: String,
+    sample_rate: u32,
+    period: usize,
+    writer: Arc<Mutex<Option<WavWriter>>>,
+    clock: Option<SoftwareClock>
+}
+
+impl WavBackend {
+    pub fn open(path: &str, sample_rate: u32, period: usize) -> Result<Self, String> {
+        let writer = WavWriter::create(Path::new(path), sample_rate, 1)
+            .map_err(|e| format!("{}: {}", path, e))?;
+
+        Ok(WavBackend {
+            path: path.to_string(),
+            sample_rate,
+            period: period.max(1),
+            writer: Arc::new(Mutex::new(Some(writer))),
+            clock: None
+        })
+    }
+}
+
+impl AudioBackend for WavBackend {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn start(&mut self, renderer: Renderer) -> Result<(), String> {
+        let writer = self.writer.clone();
+        let path = self.path.clone();
+        self.clock = Some(SoftwareClock::start(renderer, self.sample_rate, self.period, move |samples| {
+                match *writer.lock().unwrap() {
+                    Some(ref mut writer) => writer.write(samples).map_err(|e| format!("{}: {}", path, e)),
+                    None => Ok(())
+                }
+            }));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        let played = match self.clock.take() {
+            Some(mut clock) => clock.stop(),
+            None => Ok(())
+        };
+        // whatever was written still gets its header
+        let finished = match self.writer.lock().unwrap().take() {
+            Some(writer) => writer.finish().map_err(|e| format!("{}: {}", self.path, e)),
+            None => Ok(())
+        };
+        played.and(finished)
+    }
+}
+
+impl Drop for WavBackend {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            error!("{}", e);
+        }
+    }
+}
@@ -3,84 +3,300 @@ pub const BUFFER_SIZE: usize = 16;
 pub type AudioBuffer = [f32; BUFFER_SIZE];
 pub const BLANK_BUFFER: AudioBuffer = [0.0; BUFFER_SIZE];
 
-// audio connection traits
-// not sure which way round these shoukd be named, currently Output puts audio into the buffer...
+// processing graph
+// every node owns a buffer in the graph that it writes its block into, and reads its inputs
+// straight out of theirs, nothing is copied between stages
+// the order nodes run in comes from how they're connected, a node whose inputs aren't all
+// connected or a cycle is an error from run rather than a panic
 
-pub trait Output {
-    /// Write audio into the buffer
-    fn supply_audio(&self, buffer: AudioBuffer);
-}
-
-pub trait Input {
-    /// Get audio out of the buffer
-    fn get_audio(&self) -> AudioBuffer;
-}
+pub mod graph {
+    use std::any::Any;
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::marker::PhantomData;
+    use std::mem;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use basic_types::{AudioBuffer, BLANK_BUFFER};
 
-// unththreaded audio buffer
+    /// A stage of processing
+    pub trait Node: Send {
+        /// How many inputs have to be connected, they're passed to process in the order
+        /// they were connected
+        fn num_inputs(&self) -> usize;
 
-pub mod unthreaded_connection {
-    use std::cell::RefCell;
-    use std::rc::Rc;
-    use basic_types::{AudioBuffer, BLANK_BUFFER, Output, Input};
+        /// Render a block into output, which still holds this node's previous block
+        fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer);
 
-    pub struct UnthreadedOutput {
-        buffer: Rc<RefCell<Option<AudioBuffer>>>
+        /// The output changed rate
+        fn set_sample_rate(&mut self, _sample_rate: u32) {}
     }
 
-    pub struct UnthreadedInput {
-        buffer: Rc<RefCell<Option<AudioBuffer>>>
+    /// The blocks a node's inputs rendered this time round
+    pub struct Inputs<'a> {
+        slots: &'a [AudioBuffer],
+        sources: &'a [usize]
     }
 
-    pub fn new() -> (UnthreadedOutput, UnthreadedInput) {
-        let inner_buf = Rc::new(RefCell::new(None));
-        (
-        UnthreadedOutput {
-            buffer: inner_buf.clone()
-        },
-        UnthreadedInput {
-            buffer: inner_buf.clone()
+    impl<'a> Inputs<'a> {
+        /// For running a node with no inputs outside a graph
+        pub fn none() -> Self {
+            Inputs {
+                slots: &[],
+                sources: &[]
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.sources.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.sources.is_empty()
+        }
+
+        pub fn get(&self, input: usize) -> &'a AudioBuffer {
+            &self.slots[self.sources[input]]
         }
-        )
     }
 
-    impl Output for UnthreadedOutput {
-        fn supply_audio(&self, buffer: AudioBuffer) {
-            let mut inner_buf = self.buffer.borrow_mut();
-            *inner_buf = Some(buffer);
+    // every graph gets its own, so a handle can't be used with a graph it didn't come from
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    /// A node in a graph, for connecting it and reaching it once the graph owns it
+    pub struct Handle<N> {
+        graph: usize,
+        index: usize,
+        node: PhantomData<N>
+    }
+
+    impl<N> Clone for Handle<N> {
+        fn clone(&self) -> Self {
+            *self
         }
     }
 
-    impl Input for UnthreadedInput {
-        fn get_audio(&self) -> AudioBuffer {
-            self.buffer.borrow_mut().take().unwrap()
+    impl<N> Copy for Handle<N> {}
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum GraphError {
+        /// A node has a different number of inputs connected to what it needs
+        Inputs { node: String, needs: usize, connected: usize },
+        /// These nodes feed each other so there's no order to run them in
+        Cycle(Vec<String>),
+        /// Nothing has been chosen as the output
+        NoOutput,
+        /// A handle from some other graph
+        UnknownNode
+    }
+
+    impl fmt::Display for GraphError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                GraphError::Inputs { ref node, needs, connected } => {
+                    write!(f, "{} needs {} input(s) but has {} connected", node, needs, connected)
+                }
+                GraphError::Cycle(ref nodes) => {
+                    write!(f, "cycle between {}, no order to run them in", nodes.join(", "))
+                }
+                GraphError::NoOutput => {
+                    write!(f, "no output node")
+                }
+                GraphError::UnknownNode => {
+                    write!(f, "node isn't in this graph")
+                }
+            }
         }
     }
-}
 
+    // lets the graph hand nodes back as what they really are
+    trait AnyNode: Node {
+        fn as_any(&self) -> &dyn Any;
+        fn as_any_mut(&mut self) -> &mut dyn Any;
+    }
+
+    impl<N> AnyNode for N where N: Node + 'static {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
 
-pub mod threaded_connection {
-    use rb::{RB, SpscRb, Producer, RbProducer, Consumer, RbConsumer};
-    use basic_types::{AudioBuffer, BLANK_BUFFER, Output, Input};
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
 
-    pub type ThreadedOutput = Producer<AudioBuffer>;
-    pub type ThreadedInput = Consumer<AudioBuffer>;
+    struct Entry {
+        name: String,
+        node: Box<dyn AnyNode>,
+        sources: Vec<usize>
+    }
 
-    pub fn new() -> (ThreadedOutput, ThreadedInput) {
-        let buf = SpscRb::<AudioBuffer>::new(1);
-        (buf.producer(), buf.consumer())
+    pub struct Graph {
+        id: usize,
+        entries: Vec<Entry>,
+        // a buffer per node
+        slots: Vec<AudioBuffer>,
+        // stands in for the running node's slot while it writes to its own
+        spare: AudioBuffer,
+        output: Option<usize>,
+        // worked out again on the next run after any change
+        order: Option<Vec<usize>>
     }
 
-    impl Output for ThreadedOutput {
-        fn supply_audio(&self, buffer: AudioBuffer) {
-            self.write_blocking(&[buffer]);
+    impl Default for Graph {
+        fn default() -> Self {
+            Graph::new()
         }
     }
 
-    impl Input for ThreadedInput {
-        fn get_audio(&self) -> AudioBuffer {
-            let mut buf = [BLANK_BUFFER; 1];
-            self.read_blocking(&mut buf);
-            buf[0]
+    impl Graph {
+        pub fn new() -> Self {
+            Graph {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                entries: Vec::new(),
+                slots: Vec::new(),
+                spare: BLANK_BUFFER,
+                output: None,
+                order: None
+            }
+        }
+
+        /// The name is only for errors
+        pub fn add<N>(&mut self, name: &str, node: N) -> Handle<N> where N: Node + 'static {
+            self.entries.push(Entry {
+                name: name.to_string(),
+                node: Box::new(node),
+                sources: Vec::new()
+            });
+            self.slots.push(BLANK_BUFFER);
+            self.order = None;
+            Handle {
+                graph: self.id,
+                index: self.entries.len() - 1,
+                node: PhantomData
+            }
+        }
+
+        fn check<N>(&self, node: &Handle<N>) -> Result<usize, GraphError> {
+            if node.graph == self.id {
+                Ok(node.index)
+            }
+            else {
+                Err(GraphError::UnknownNode)
+            }
+        }
+
+        /// Feed one node into the next input of another
+        pub fn connect<A, B>(&mut self, from: &Handle<A>, to: &Handle<B>) -> Result<(), GraphError> {
+            let from = self.check(from)?;
+            let to = self.check(to)?;
+            self.entries[to].sources.push(from);
+            self.order = None;
+            Ok(())
+        }
+
+        /// The node whose block run gives back
+        pub fn set_output<N>(&mut self, node: &Handle<N>) -> Result<(), GraphError> {
+            self.output = Some(self.check(node)?);
+            self.order = None;
+            Ok(())
+        }
+
+        /// A node the graph owns, panics for a handle from another graph
+        pub fn node<N>(&self, node: &Handle<N>) -> &N where N: Node + 'static {
+            let index = self.check(node).expect("node from another graph");
+            self.entries[index].node.as_any().downcast_ref().expect("node from another graph")
+        }
+
+        pub fn node_mut<N>(&mut self, node: &Handle<N>) -> &mut N where N: Node + 'static {
+            let index = self.check(node).expect("node from another graph");
+            self.entries[index].node.as_any_mut().downcast_mut().expect("node from another graph")
+        }
+
+        /// Check every node has its inputs and work out an order to run them in, run does
+        /// this itself after any change but it can be done up front to catch errors early
+        pub fn resolve(&mut self) -> Result<(), GraphError> {
+            if self.order.is_some() {
+                return Ok(());
+            }
+            if self.output.is_none() {
+                return Err(GraphError::NoOutput);
+            }
+
+            for entry in self.entries.iter() {
+                let needs = entry.node.num_inputs();
+                if entry.sources.len() != needs {
+                    return Err(GraphError::Inputs {
+                        node: entry.name.clone(),
+                        needs,
+                        connected: entry.sources.len()
+                    });
+                }
+            }
+
+            // a node runs once everything feeding it has, ties go in the order nodes were added
+            let mut waiting: Vec<usize> = self.entries.iter().map(|e| e.sources.len()).collect();
+            let mut ready: VecDeque<usize> = (0..self.entries.len()).filter(|i| waiting[*i] == 0).collect();
+            let mut order = Vec::with_capacity(self.entries.len());
+            while let Some(index) = ready.pop_front() {
+                order.push(index);
+                for (next, entry) in self.entries.iter().enumerate() {
+                    let uses = entry.sources.iter().filter(|s| **s == index).count();
+                    if uses > 0 {
+                        waiting[next] -= uses;
+                        if waiting[next] == 0 {
+                            ready.push_back(next);
+                        }
+                    }
+                }
+            }
+
+            if order.len() < self.entries.len() {
+                let stuck = (0..self.entries.len())
+                    .filter(|i| waiting[*i] > 0)
+                    .map(|i| self.entries[i].name.clone())
+                    .collect();
+                return Err(GraphError::Cycle(stuck));
+            }
+
+            self.order = Some(order);
+            Ok(())
+        }
+
+        /// Run every node once, giving the output node's block
+        /// On an error nothing runs and the output is silent
+        pub fn run(&mut self) -> Result<&AudioBuffer, GraphError> {
+            if let Err(e) = self.resolve() {
+                if let Some(output) = self.output {
+                    self.slots[output] = BLANK_BUFFER;
+                }
+                return Err(e);
+            }
+
+            if let Some(ref order) = self.order {
+                for &index in order.iter() {
+                    let entry = &mut self.entries[index];
+                    mem::swap(&mut self.spare, &mut self.slots[index]);
+                    entry.node.process(&Inputs { slots: &self.slots, sources: &entry.sources }, &mut self.spare);
+                    mem::swap(&mut self.spare, &mut self.slots[index]);
+                }
+            }
+            Ok(self.output())
+        }
+
+        /// The output node's last block, silence before the first run
+        pub fn output(&self) -> &AudioBuffer {
+            match self.output {
+                Some(output) => &self.slots[output],
+                None => &BLANK_BUFFER
+            }
+        }
+
+        /// Tell every node about a new sample rate
+        pub fn set_sample_rate(&mut self, sample_rate: u32) {
+            for entry in self.entries.iter_mut() {
+                entry.node.set_sample_rate(sample_rate);
+            }
         }
     }
 }
+
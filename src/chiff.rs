@@ -0,0 +1,123 @@
+// chiff, the breathy spit of a pipe starting to speak before its tone settles
+// a burst of noise tuned a little above the pipe's pitch, added to the tone passing through
+
+use basic_types::AudioBuffer;
+use basic_types::graph::{Node, Inputs};
+
+use std::f32::consts::PI;
+
+// the burst rises and falls as the difference of two decays
+const RISE_MS: f32 = 1.5;
+const FALL_MS: f32 = 30.0;
+// the noise rings around this harmonic of the pipe, with this q
+const HARMONIC: f32 = 2.0;
+const Q: f32 = 4.0;
+// the burst has died away
+const IDLE_LEVEL: f32 = 1e-4;
+
+fn decay(time_ms: f32, sample_rate: u32) -> f32 {
+    (-1000.0 / (time_ms * sample_rate.max(1) as f32)).exp()
+}
+
+pub struct Chiff {
+    level: f32,
+    freq: f32,
+    sample_rate: u32,
+    // two pole resonator
+    a1: f32,
+    a2: f32,
+    gain: f32,
+    y1: f32,
+    y2: f32,
+    // envelope is slow - fast, both start at 1 when triggered
+    slow: f32,
+    fast: f32,
+    slow_decay: f32,
+    fast_decay: f32,
+    seed: u32
+}
+
+impl Chiff {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut chiff = Chiff {
+            level: 0.0,
+            freq: 0.0,
+            sample_rate,
+            a1: 0.0,
+            a2: 0.0,
+            gain: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+            slow: 0.0,
+            fast: 0.0,
+            slow_decay: 0.0,
+            fast_decay: 0.0,
+            seed: 1
+        };
+        chiff.set_sample_rate(sample_rate);
+        chiff
+    }
+
+    /// The pitch of the pipe
+    pub fn set_freq(&mut self, freq: f32) {
+        self.freq = freq;
+        let sample_rate = self.sample_rate.max(1) as f32;
+        let centre = (freq * HARMONIC).min(sample_rate * 0.45).max(1.0);
+        let omega = 2.0 * PI * centre / sample_rate;
+        let r = (-PI * centre / Q / sample_rate).exp();
+        self.a1 = 2.0 * r * omega.cos();
+        self.a2 = -r * r;
+        // noise comes out as loud as it went in, however narrow the band
+        let r2 = r * r;
+        let power = (1.0 + r2) / ((1.0 - r2) * ((1.0 + r2) * (1.0 + r2) - self.a1 * self.a1));
+        self.gain = 1.0 / power.sqrt();
+    }
+
+    /// Of the burst's peak against a full scale tone, 0 for none
+    pub fn set_level(&mut self, level: f32) {
+        self.level = level;
+    }
+
+    /// Start a burst
+    pub fn trigger(&mut self) {
+        self.slow = 1.0;
+        self.fast = 1.0;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.slow < IDLE_LEVEL || self.level == 0.0
+    }
+}
+
+impl Node for Chiff {
+    fn num_inputs(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer) {
+        *output = *inputs.get(0);
+        if self.is_idle() {
+            return;
+        }
+
+        for sample in output.iter_mut() {
+            self.seed = self.seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let noise = (self.seed >> 8) as f32 / (1 << 23) as f32 - 1.0;
+            let y = self.gain * noise + self.a1 * self.y1 + self.a2 * self.y2;
+            self.y2 = self.y1;
+            self.y1 = y;
+
+            *sample += y * (self.slow - self.fast) * self.level;
+            self.slow *= self.slow_decay;
+            self.fast *= self.fast_decay;
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.slow_decay = decay(FALL_MS, sample_rate);
+        self.fast_decay = decay(RISE_MS, sample_rate);
+        let freq = self.freq;
+        self.set_freq(freq);
+    }
+}
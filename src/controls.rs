@@ -0,0 +1,192 @@
+// what the midi controllers do, and the drawbar settings to start with
+
+use midi;
+use text_file;
+
+use std::path::Path;
+
+pub const NUM_DRAWBARS: usize = 9;
+
+/// Drawbar levels from 0.0 (pushed in) to 1.0 (pulled all the way out)
+pub type Registration = [f32; NUM_DRAWBARS];
+
+/// The registration organn has always started with
+pub const DEFAULT_REGISTRATION: Registration = [1.0, 0.6, 0.1, 0.4, 0.1, 0.4, 0.1, 0.1, 0.1];
+
+/// The upper manual, lower manual and pedals
+pub const NUM_DIVISIONS: usize = 3;
+
+pub const DIVISION_NAMES: [&str; NUM_DIVISIONS] = ["upper", "lower", "pedal"];
+
+/// The division a midi channel plays, channels 1 to 3 are the upper manual, lower manual and
+/// pedals and any other plays the upper manual
+pub fn division(channel: midi::Channel) -> usize {
+    match channel as usize {
+        c if c < NUM_DIVISIONS => c,
+        _ => 0
+    }
+}
+
+/// Parse hammond style drawbar settings, one digit from 0 to 8 per drawbar e.g. "888000000"
+pub fn parse_registration(text: &str) -> Result<Registration, String> {
+    let bad = || format!("bad registration \"{}\", needs {} digits from 0 to 8", text, NUM_DRAWBARS);
+
+    let digits: Vec<char> = text.chars().filter(|c| *c != ' ').collect();
+    if digits.len() != NUM_DRAWBARS {
+        return Err(bad());
+    }
+
+    let mut registration = [0.0; NUM_DRAWBARS];
+    for (level, digit) in registration.iter_mut().zip(digits.iter()) {
+        match digit.to_digit(10) {
+            Some(d) if d <= 8 => { *level = d as f32 / 8.0; }
+            _ => { return Err(bad()); }
+        }
+    }
+    Ok(registration)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Control {
+    /// Drawbar 1 to 9 as 0 to 8, reversed so the controller at 0 is pulled out
+    Drawbar(usize),
+    Expression,
+    Volume,
+    /// A parameter of an effect in the chain, both counted from 0
+    Effect(usize, usize),
+    /// Skip an effect in the chain while the controller is at 64 or over
+    EffectBypass(usize),
+    /// A division's tremulant, on while the controller is at 64 or over
+    Tremulant(usize),
+    TremulantRate(usize),
+    TremulantDepth(usize),
+    /// A pipe organ stop, counted from 0, drawn while the controller is at 64 or over
+    Stop(usize),
+    /// A pipe organ coupler, the same
+    Coupler(usize)
+}
+
+impl Control {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "expression" => Some(Control::Expression),
+            "volume" => Some(Control::Volume),
+            _ if name.starts_with("drawbar") => {
+                match name["drawbar".len()..].parse::<usize>() {
+                    Ok(n) if (1..=NUM_DRAWBARS).contains(&n) => Some(Control::Drawbar(n - 1)),
+                    _ => None
+                }
+            }
+            _ if name.starts_with("effect") => {
+                // effect<slot>.<param> or effect<slot>.bypass, counted from 1
+                let mut parts = name["effect".len()..].splitn(2, '.');
+                let slot = match parts.next().map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) if n >= 1 => n - 1,
+                    _ => { return None; }
+                };
+                match parts.next() {
+                    Some("bypass") => Some(Control::EffectBypass(slot)),
+                    Some(param) => {
+                        match param.parse::<usize>() {
+                            Ok(n) if n >= 1 => Some(Control::Effect(slot, n - 1)),
+                            _ => None
+                        }
+                    }
+                    None => None
+                }
+            }
+            _ if name.starts_with("stop") || name.starts_with("coupler") => {
+                // stop<n> or coupler<n>, counted from 1
+                let (prefix, control): (&str, fn(usize) -> Control) = if name.starts_with("stop") {
+                    ("stop", Control::Stop)
+                }
+                else {
+                    ("coupler", Control::Coupler)
+                };
+                match name[prefix.len()..].parse::<usize>() {
+                    Ok(n) if n >= 1 => Some(control(n - 1)),
+                    _ => None
+                }
+            }
+            _ if name.starts_with("tremulant") => {
+                // tremulant<division>.on, .rate or .depth, counted from 1
+                let mut parts = name["tremulant".len()..].splitn(2, '.');
+                let division = match parts.next().map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) if (1..=NUM_DIVISIONS).contains(&n) => n - 1,
+                    _ => { return None; }
+                };
+                match parts.next() {
+                    Some("on") => Some(Control::Tremulant(division)),
+                    Some("rate") => Some(Control::TremulantRate(division)),
+                    Some("depth") => Some(Control::TremulantDepth(division)),
+                    _ => None
+                }
+            }
+            _ => None
+        }
+    }
+}
+
+/// Which midi cc does what
+#[derive(Clone)]
+pub struct CcMap {
+    controls: Vec<Option<Control>>
+}
+
+impl Default for CcMap {
+    /// Drawbars on 2, 3, 4, 5, 6, 8, 9, 12 and 13 (a nanoKontrol), volume 7 and expression 11
+    fn default() -> Self {
+        let mut map = CcMap::empty();
+        for (i, cc) in [2, 3, 4, 5, 6, 8, 9, 12, 13].iter().enumerate() {
+            map.controls[*cc] = Some(Control::Drawbar(i));
+        }
+        map.controls[7] = Some(Control::Volume);
+        map.controls[11] = Some(Control::Expression);
+        map
+    }
+}
+
+impl CcMap {
+    fn empty() -> Self {
+        CcMap {
+            controls: vec![None; 128]
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        text_file::load(path, Self::parse)
+    }
+
+    /// A line per controller, "<cc> <control>" where control is drawbar1 to drawbar9,
+    /// expression, volume, effect<n>.<param>, effect<n>.bypass, tremulant<division>.on,
+    /// .rate or .depth, stop<n> or coupler<n>.
+    /// Anything after a # is a comment, unlisted ccs do nothing
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = Self::empty();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let bad = || format!("line {}: expected \"<cc> <control>\", got \"{}\"", number + 1, line.trim());
+            if words.len() != 2 {
+                return Err(bad());
+            }
+
+            let cc = match words[0].parse::<usize>() {
+                Ok(cc) if cc < 128 => cc,
+                _ => { return Err(bad()); }
+            };
+            let control = Control::parse(words[1])
+                .ok_or(format!("line {}: unknown control \"{}\"", number + 1, words[1]))?;
+            map.controls[cc] = Some(control);
+        }
+        Ok(map)
+    }
+
+    pub fn control(&self, cc: midi::U7) -> Option<Control> {
+        self.controls.get(cc as usize).and_then(|c| *c)
+    }
+}
@@ -0,0 +1,120 @@
+// speaker cabinet, the mix convolved with an impulse response of a cabinet recorded through a
+// mic, best placed last in the chain as that's where the speaker would be
+// several responses can be loaded and switched between with the ir parameter
+
+use basic_types::{AudioBuffer, BUFFER_SIZE};
+use effects::{Effect, Param};
+use effects::convolution::{Convolver, ImpulseResponse};
+
+use std::sync::Arc;
+
+const SMOOTHING_MS: f32 = 10.0;
+
+const IR: usize = 0;
+const MIX: usize = 1;
+const LEVEL: usize = 2;
+
+static PARAMS: [Param; 3] = [
+    Param { name: "ir", unit: "", min: 1.0, max: 16.0, default: 1.0, log: false },
+    Param { name: "mix", unit: "", min: 0.0, max: 1.0, default: 1.0, log: false },
+    Param { name: "level", unit: "dB", min: -24.0, max: 12.0, default: 0.0, log: false }
+];
+
+pub struct Cabinet {
+    responses: Vec<Arc<ImpulseResponse>>,
+    convolver: Convolver,
+    values: [f32; 3],
+    dry: f32,
+    wet: f32,
+    smoothing: f32
+}
+
+impl Cabinet {
+    /// With nothing loaded it passes the mix through, the responses are mono as stereo ones
+    /// were mixed down when they were loaded
+    pub fn new(mut responses: Vec<Arc<ImpulseResponse>>, sample_rate: u32) -> Self {
+        if responses.is_empty() {
+            responses.push(Arc::new(ImpulseResponse {
+                name: "none".to_string(),
+                sample_rate,
+                samples: vec![1.0]
+            }));
+        }
+        let mut cabinet = Cabinet {
+            convolver: Convolver::new(&responses, sample_rate),
+            responses,
+            values: [PARAMS[IR].default, PARAMS[MIX].default, PARAMS[LEVEL].default],
+            dry: 0.0,
+            wet: 1.0,
+            smoothing: 0.0
+        };
+        cabinet.reset(sample_rate);
+        cabinet
+    }
+
+    // everything but the responses, which the convolver has at the rate already
+    fn reset(&mut self, sample_rate: u32) {
+        let response = self.response();
+        self.convolver.select(response);
+        self.smoothing = (-1000.0 / (SMOOTHING_MS * sample_rate.max(1) as f32)).exp();
+        let (dry, wet) = self.targets();
+        self.dry = dry;
+        self.wet = wet;
+    }
+
+    pub fn responses(&self) -> &[Arc<ImpulseResponse>] {
+        &self.responses
+    }
+
+    // parameter value to response, counted from 1 and held to what's loaded
+    fn response(&self) -> usize {
+        (self.values[IR].round().max(1.0) as usize).min(self.responses.len()) - 1
+    }
+
+    fn targets(&self) -> (f32, f32) {
+        let mix = self.values[MIX];
+        (1.0 - mix, mix * 10.0_f32.powf(self.values[LEVEL] / 20.0))
+    }
+}
+
+impl Effect for Cabinet {
+    fn name(&self) -> &'static str {
+        "cabinet"
+    }
+
+    fn params(&self) -> &'static [Param] {
+        &PARAMS
+    }
+
+    fn set_param(&mut self, param: usize, value: f32) {
+        if param < self.values.len() {
+            self.values[param] = value;
+            if param == IR {
+                let response = self.response();
+                self.convolver.select(response);
+            }
+        }
+    }
+
+    fn param(&self, param: usize) -> f32 {
+        self.values.get(param).cloned().unwrap_or(0.0)
+    }
+
+    /// Resamples every response, which allocates, and forgets what was playing
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.convolver = Convolver::new(&self.responses, sample_rate);
+        self.reset(sample_rate);
+    }
+
+    fn process(&mut self, samples: &mut AudioBuffer) {
+        let mut convolved = [0.0; BUFFER_SIZE];
+        self.convolver.process(samples, &mut convolved);
+
+        let (dry, wet) = self.targets();
+        for (sample, convolved) in samples.iter_mut().zip(convolved.iter()) {
+            self.dry = dry + (self.dry - dry) * self.smoothing;
+            self.wet = wet + (self.wet - wet) * self.smoothing;
+            *sample = *sample * self.dry + *convolved * self.wet;
+        }
+    }
+}
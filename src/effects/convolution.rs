@@ -0,0 +1,386 @@
+// partitioned convolution with impulse responses loaded from wav files
+// the first HEAD_TAPS of a response are split into partitions the size of a block, so the
+// convolution adds no latency, the rest into partitions of TAIL samples
+// a tail partition's result isn't needed until HEAD_TAPS after its input arrived, which is
+// time enough to spread its work evenly over the blocks in between instead of all at once
+// the input spectra don't depend on the response, so a second response can be convolved from
+// the same history and faded in without a click
+
+use basic_types::{AudioBuffer, BUFFER_SIZE};
+use fft::{Complex, Fft};
+use wav::WavData;
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+const BLOCK: usize = BUFFER_SIZE;
+const HEAD_TAPS: usize = 512;
+const HEAD_PARTITIONS: usize = HEAD_TAPS / BLOCK;
+const TAIL: usize = 256;
+// blocks in a tail partition, its work is shared out between them
+const PHASES: usize = TAIL / BLOCK;
+
+// a tail partition is done a whole partition after its input, the head has to cover that
+const _: () = assert!(HEAD_TAPS == 2 * TAIL && TAIL.is_multiple_of(BLOCK));
+
+/// Longer responses are cut short
+pub const MAX_IR_SECONDS: f32 = 1.0;
+
+const FADE_MS: f32 = 25.0;
+
+// zero crossings either side of each output sample when resampling
+const RESAMPLE_ZEROS: f64 = 32.0;
+
+/// A mono impulse response at the rate it was recorded
+#[derive(Clone, PartialEq)]
+pub struct ImpulseResponse {
+    pub name: String,
+    pub sample_rate: u32,
+    pub samples: Vec<f32>
+}
+
+impl ImpulseResponse {
+    /// Mono or stereo, stereo is mixed down as organn's output is mono
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let wav = WavData::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = path.file_name().map_or(path.display().to_string(), |n| n.to_string_lossy().into_owned());
+        ImpulseResponse::from_wav(&name, &wav).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Stereo is mixed down the same as `load` does
+    pub fn from_wav(name: &str, wav: &WavData) -> Result<Self, String> {
+        let samples: Vec<f32> = match wav.channels {
+            1 => wav.samples.clone(),
+            2 => wav.samples.chunks(2).map(|frame| frame.iter().sum::<f32>() / 2.0).collect(),
+            n => { return Err(format!("{} channels, impulse responses have to be mono or stereo", n)); }
+        };
+        if samples.is_empty() || wav.sample_rate == 0 {
+            return Err("empty impulse response".to_string());
+        }
+
+        Ok(ImpulseResponse {
+            name: name.to_string(),
+            sample_rate: wav.sample_rate,
+            samples
+        })
+    }
+
+    /// Taps at another rate, cut at MAX_IR_SECONDS and scaled so noise comes out as loud
+    /// as it went in
+    pub fn taps(&self, sample_rate: u32) -> Vec<f32> {
+        let mut taps = resample(&self.samples, self.sample_rate, sample_rate);
+        taps.truncate((MAX_IR_SECONDS * sample_rate as f32) as usize);
+
+        let energy = taps.iter().map(|t| t * t).sum::<f32>().sqrt();
+        if energy > 0.0 {
+            for tap in taps.iter_mut() {
+                *tap /= energy;
+            }
+        }
+        taps
+    }
+}
+
+impl fmt::Debug for ImpulseResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ImpulseResponse({}, {} samples at {}Hz)", self.name, self.samples.len(), self.sample_rate)
+    }
+}
+
+/// Windowed sinc resampling, band limited to the lower of the two rates
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = to as f64 / from as f64;
+    // cutoff in cycles per input sample, a little under nyquist for the window's roll off
+    let cutoff = 0.5 * ratio.min(1.0) * 0.95;
+    let width = RESAMPLE_ZEROS / (2.0 * cutoff);
+    let len = (samples.len() as f64 * ratio).ceil() as usize;
+
+    (0..len)
+        .map(|n| {
+                let centre = n as f64 / ratio;
+                let first = (centre - width).ceil().max(0.0) as usize;
+                let last = ((centre + width).floor() as usize).min(samples.len() - 1);
+                let mut sum = 0.0;
+                for (j, sample) in samples.iter().enumerate().take(last + 1).skip(first) {
+                    let x = j as f64 - centre;
+                    let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * cutoff * x).sin() / (PI * x) / (2.0 * cutoff) };
+                    // blackman
+                    let w = 0.5 + 0.5 * x / width;
+                    let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+                    sum += *sample as f64 * 2.0 * cutoff * sinc * window;
+                }
+                sum as f32
+            })
+        .collect()
+}
+
+// the spectra of a response's partitions, half of each as the rest mirrors it
+struct Kernel {
+    head: Vec<Vec<Complex>>,
+    tail: Vec<Vec<Complex>>
+}
+
+fn spectrum(taps: &[f32], fft: &Fft) -> Vec<Complex> {
+    let mut buffer = vec![Complex::zero(); fft.size()];
+    for (value, tap) in buffer.iter_mut().zip(taps.iter()) {
+        value.re = *tap;
+    }
+    fft.forward(&mut buffer);
+    buffer.truncate(fft.size() / 2 + 1);
+    buffer
+}
+
+impl Kernel {
+    fn new(taps: &[f32], head_fft: &Fft, tail_fft: &Fft) -> Self {
+        let split = taps.len().min(HEAD_TAPS);
+        Kernel {
+            head: taps[..split].chunks(BLOCK).map(|p| spectrum(p, head_fft)).collect(),
+            tail: taps[split..].chunks(TAIL).map(|p| spectrum(p, tail_fft)).collect()
+        }
+    }
+}
+
+// the mirrored half of a real signal's spectrum, then back to samples
+fn unfold_inverse(half: &[Complex], full: &mut [Complex], fft: &Fft) {
+    let n = full.len();
+    full[..half.len()].copy_from_slice(half);
+    for k in 1..(n / 2) {
+        full[n - k] = half[k].conj();
+    }
+    fft.inverse(full);
+}
+
+// a response being convolved, two while fading between them
+struct Lane {
+    kernel: Option<usize>,
+    head: Vec<Complex>,
+    tail: Vec<Complex>,
+    // tail output being worked out this partition, and the one being played
+    pending: Vec<f32>,
+    playing: Vec<f32>,
+    output: AudioBuffer
+}
+
+impl Lane {
+    fn new(kernel: Option<usize>) -> Self {
+        Lane {
+            kernel,
+            head: vec![Complex::zero(); BLOCK + 1],
+            tail: vec![Complex::zero(); TAIL + 1],
+            pending: vec![0.0; TAIL],
+            playing: vec![0.0; TAIL],
+            output: [0.0; BLOCK]
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Switch {
+    None,
+    // the new lane's tail needs a whole partition before it's any use
+    Warming,
+    Fading(usize)
+}
+
+/// Convolves with one of a set of responses, switching between them with a crossfade
+/// Nothing allocates once it's made
+pub struct Convolver {
+    kernels: Vec<Kernel>,
+    head_fft: Fft,
+    tail_fft: Fft,
+    // the last two blocks of input, and the last two tail partitions
+    head_frame: Vec<f32>,
+    tail_frame: Vec<f32>,
+    // spectra of past input, newest at the positions
+    head_history: Vec<Vec<Complex>>,
+    head_pos: usize,
+    tail_history: Vec<Vec<Complex>>,
+    tail_pos: usize,
+    scratch: Vec<Complex>,
+    phase: usize,
+    lanes: [Lane; 2],
+    live: usize,
+    wanted: usize,
+    switch: Switch,
+    fade_samples: usize
+}
+
+impl Convolver {
+    /// Starts on the first response, there has to be at least one
+    pub fn new(responses: &[Arc<ImpulseResponse>], sample_rate: u32) -> Self {
+        let head_fft = Fft::new(2 * BLOCK);
+        let tail_fft = Fft::new(2 * TAIL);
+        let kernels: Vec<Kernel> = responses.iter()
+            .map(|r| Kernel::new(&r.taps(sample_rate), &head_fft, &tail_fft))
+            .collect();
+        let tail_partitions = kernels.iter().map(|k| k.tail.len()).max().unwrap_or(0);
+
+        Convolver {
+            kernels,
+            head_fft,
+            tail_fft,
+            head_frame: vec![0.0; 2 * BLOCK],
+            tail_frame: vec![0.0; 2 * TAIL],
+            head_history: vec![vec![Complex::zero(); BLOCK + 1]; HEAD_PARTITIONS],
+            head_pos: 0,
+            tail_history: vec![vec![Complex::zero(); TAIL + 1]; tail_partitions],
+            tail_pos: 0,
+            scratch: vec![Complex::zero(); 2 * TAIL],
+            phase: 0,
+            lanes: [Lane::new(Some(0)), Lane::new(None)],
+            live: 0,
+            wanted: 0,
+            switch: Switch::None,
+            fade_samples: ((FADE_MS * sample_rate as f32 / 1000.0) as usize).max(1)
+        }
+    }
+
+    pub fn responses(&self) -> usize {
+        self.kernels.len()
+    }
+
+    /// Fade to another response, it takes over within a couple of tail partitions
+    pub fn select(&mut self, response: usize) {
+        if response < self.kernels.len() {
+            self.wanted = response;
+        }
+    }
+
+    /// The response playing, or being faded to
+    pub fn selected(&self) -> usize {
+        self.wanted
+    }
+
+    // spectrum of the input frame into the history
+    fn transform_input(frame: &[f32], fft: &Fft, scratch: &mut [Complex], history: &mut [Complex]) {
+        for (value, sample) in scratch.iter_mut().zip(frame.iter()) {
+            *value = Complex::new(*sample, 0.0);
+        }
+        fft.forward(scratch);
+        history.copy_from_slice(&scratch[..history.len()]);
+    }
+
+    fn start_partition(&mut self) {
+        if !self.tail_history.is_empty() {
+            self.tail_pos = (self.tail_pos + 1) % self.tail_history.len();
+            Convolver::transform_input(&self.tail_frame, &self.tail_fft, &mut self.scratch, &mut self.tail_history[self.tail_pos]);
+        }
+        self.tail_frame.copy_within(TAIL.., 0);
+
+        let spare = 1 - self.live;
+        match self.switch {
+            Switch::None if self.lanes[self.live].kernel != Some(self.wanted) => {
+                self.lanes[spare].kernel = Some(self.wanted);
+                self.switch = Switch::Warming;
+            }
+            Switch::Warming => {
+                self.switch = Switch::Fading(0);
+            }
+            _ => {}
+        }
+
+        for lane in self.lanes.iter_mut().filter(|l| l.kernel.is_some()) {
+            ::std::mem::swap(&mut lane.pending, &mut lane.playing);
+            for value in lane.tail.iter_mut() {
+                *value = Complex::zero();
+            }
+        }
+    }
+
+    fn run_lane(&mut self, lane: usize) {
+        let Convolver { ref kernels, ref head_fft, ref tail_fft, ref head_history, head_pos,
+                        ref tail_history, tail_pos, ref mut scratch, phase, ref mut lanes, .. } = *self;
+        let lane = &mut lanes[lane];
+        let kernel = match lane.kernel {
+            Some(index) => &kernels[index],
+            None => { return; }
+        };
+
+        // every head partition, this block's output straight away
+        for value in lane.head.iter_mut() {
+            *value = Complex::zero();
+        }
+        for (k, partition) in kernel.head.iter().enumerate() {
+            let input = &head_history[(head_pos + HEAD_PARTITIONS - k) % HEAD_PARTITIONS];
+            for ((acc, x), h) in lane.head.iter_mut().zip(input.iter()).zip(partition.iter()) {
+                acc.mul_add(*x, *h);
+            }
+        }
+        let head = &mut scratch[..(2 * BLOCK)];
+        unfold_inverse(&lane.head, head, head_fft);
+        for (out, value) in lane.output.iter_mut().zip(head[BLOCK..].iter()) {
+            *out = value.re;
+        }
+
+        // this block's share of the tail partitions
+        let partitions = kernel.tail.len();
+        if partitions > 0 {
+            let share = partitions.div_ceil(PHASES);
+            let end = ((phase + 1) * share).min(partitions);
+            for k in (phase * share)..end {
+                let input = &tail_history[(tail_pos + tail_history.len() - k) % tail_history.len()];
+                for ((acc, x), h) in lane.tail.iter_mut().zip(input.iter()).zip(kernel.tail[k].iter()) {
+                    acc.mul_add(*x, *h);
+                }
+            }
+            if phase == PHASES - 1 {
+                unfold_inverse(&lane.tail, scratch, tail_fft);
+                for (out, value) in lane.pending.iter_mut().zip(scratch[TAIL..].iter()) {
+                    *out = value.re;
+                }
+            }
+
+            let playing = &lane.playing[(phase * BLOCK)..((phase + 1) * BLOCK)];
+            for (out, tail) in lane.output.iter_mut().zip(playing.iter()) {
+                *out += *tail;
+            }
+        }
+    }
+
+    /// Convolve a block
+    pub fn process(&mut self, input: &AudioBuffer, output: &mut AudioBuffer) {
+        self.head_frame.copy_within(BLOCK.., 0);
+        self.head_frame[BLOCK..].copy_from_slice(input);
+        self.head_pos = (self.head_pos + 1) % HEAD_PARTITIONS;
+        Convolver::transform_input(&self.head_frame, &self.head_fft, &mut self.scratch[..(2 * BLOCK)], &mut self.head_history[self.head_pos]);
+
+        if self.phase == 0 {
+            self.start_partition();
+        }
+        let start = TAIL + self.phase * BLOCK;
+        self.tail_frame[start..(start + BLOCK)].copy_from_slice(input);
+
+        self.run_lane(0);
+        self.run_lane(1);
+
+        let spare = 1 - self.live;
+        match self.switch {
+            Switch::Fading(pos) => {
+                let (from, to) = (&self.lanes[self.live].output, &self.lanes[spare].output);
+                for (i, out) in output.iter_mut().enumerate() {
+                    let fade = ((pos + i) as f32 / self.fade_samples as f32).min(1.0);
+                    *out = from[i] * (1.0 - fade) + to[i] * fade;
+                }
+                if pos + BLOCK >= self.fade_samples {
+                    self.lanes[self.live].kernel = None;
+                    self.live = spare;
+                    self.switch = Switch::None;
+                }
+                else {
+                    self.switch = Switch::Fading(pos + BLOCK);
+                }
+            }
+            _ => {
+                *output = self.lanes[self.live].output;
+            }
+        }
+
+        self.phase = (self.phase + 1) % PHASES;
+    }
+}
@@ -0,0 +1,71 @@
+// one pole high pass that takes out any dc offset, and not much else
+
+use basic_types::AudioBuffer;
+use effects::{Effect, Param};
+
+use std::f32::consts::PI;
+
+static PARAMS: [Param; 1] = [
+    Param { name: "cutoff", unit: "Hz", min: 2.0, max: 40.0, default: 10.0, log: true }
+];
+
+pub struct DcBlocker {
+    cutoff: f32,
+    sample_rate: u32,
+    coeff: f32,
+    last_in: f32,
+    last_out: f32
+}
+
+impl DcBlocker {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut blocker = DcBlocker {
+            cutoff: PARAMS[0].default,
+            sample_rate,
+            coeff: 0.0,
+            last_in: 0.0,
+            last_out: 0.0
+        };
+        blocker.update();
+        blocker
+    }
+
+    fn update(&mut self) {
+        self.coeff = (-2.0 * PI * self.cutoff / self.sample_rate.max(1) as f32).exp();
+    }
+}
+
+impl Effect for DcBlocker {
+    fn name(&self) -> &'static str {
+        "dc"
+    }
+
+    fn params(&self) -> &'static [Param] {
+        &PARAMS
+    }
+
+    fn set_param(&mut self, param: usize, value: f32) {
+        if param == 0 {
+            self.cutoff = value;
+            self.update();
+        }
+    }
+
+    fn param(&self, _param: usize) -> f32 {
+        self.cutoff
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.update();
+    }
+
+    fn process(&mut self, samples: &mut AudioBuffer) {
+        for sample in samples.iter_mut() {
+            let out = *sample - self.last_in + self.coeff * self.last_out;
+            self.last_in = *sample;
+            self.last_out = out;
+            *sample = out;
+        }
+    }
+}
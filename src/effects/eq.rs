@@ -0,0 +1,166 @@
+// three band eq, low and high shelves either side of a peaking mid band whose centre moves
+// the filters are the usual biquads from the audio eq cookbook
+
+use basic_types::AudioBuffer;
+use effects::{Effect, Param};
+
+use std::f64::consts::PI;
+
+const LOW_SHELF_HZ: f64 = 200.0;
+const HIGH_SHELF_HZ: f64 = 4_000.0;
+const MID_Q: f64 = 0.7;
+
+const LOW: usize = 0;
+const MID: usize = 1;
+const MID_FREQ: usize = 2;
+const HIGH: usize = 3;
+
+static PARAMS: [Param; 4] = [
+    Param { name: "low", unit: "dB", min: -12.0, max: 12.0, default: 0.0, log: false },
+    Param { name: "mid", unit: "dB", min: -12.0, max: 12.0, default: 0.0, log: false },
+    Param { name: "mid freq", unit: "Hz", min: 200.0, max: 5_000.0, default: 1_000.0, log: true },
+    Param { name: "high", unit: "dB", min: -12.0, max: 12.0, default: 0.0, log: false }
+];
+
+enum Shape {
+    LowShelf,
+    Peak,
+    HighShelf
+}
+
+// transposed direct form II, the state survives coefficient changes
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32
+}
+
+impl Biquad {
+    fn new() -> Self {
+        Biquad {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            z1: 0.0,
+            z2: 0.0
+        }
+    }
+
+    fn design(&mut self, shape: Shape, freq: f64, db: f64, sample_rate: u32) {
+        // keep below nyquist however low the rate
+        let sample_rate = sample_rate.max(1) as f64;
+        let freq = freq.min(sample_rate * 0.45);
+
+        let a = 10.0_f64.powf(db / 40.0);
+        let w0 = 2.0 * PI * freq / sample_rate;
+        let (sin, cos) = (w0.sin(), w0.cos());
+
+        let (b0, b1, b2, a0, a1, a2) = match shape {
+            Shape::Peak => {
+                let alpha = sin / (2.0 * MID_Q);
+                (1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a,
+                 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a)
+            }
+            Shape::LowShelf => {
+                // 2 sqrt(A) alpha, with a shelf slope of 1, as steep as it goes without a bump
+                let k = (2.0 * a).sqrt() * sin;
+                (a * ((a + 1.0) - (a - 1.0) * cos + k),
+                 2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                 a * ((a + 1.0) - (a - 1.0) * cos - k),
+                 (a + 1.0) + (a - 1.0) * cos + k,
+                 -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                 (a + 1.0) + (a - 1.0) * cos - k)
+            }
+            Shape::HighShelf => {
+                let k = (2.0 * a).sqrt() * sin;
+                (a * ((a + 1.0) + (a - 1.0) * cos + k),
+                 -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                 a * ((a + 1.0) + (a - 1.0) * cos - k),
+                 (a + 1.0) - (a - 1.0) * cos + k,
+                 2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                 (a + 1.0) - (a - 1.0) * cos - k)
+            }
+        };
+
+        self.b0 = (b0 / a0) as f32;
+        self.b1 = (b1 / a0) as f32;
+        self.b2 = (b2 / a0) as f32;
+        self.a1 = (a1 / a0) as f32;
+        self.a2 = (a2 / a0) as f32;
+    }
+
+    #[inline]
+    fn run(&mut self, sample: f32) -> f32 {
+        let out = self.b0 * sample + self.z1;
+        self.z1 = self.b1 * sample - self.a1 * out + self.z2;
+        self.z2 = self.b2 * sample - self.a2 * out;
+        out
+    }
+}
+
+pub struct Equalizer {
+    values: [f32; 4],
+    sample_rate: u32,
+    low: Biquad,
+    mid: Biquad,
+    high: Biquad
+}
+
+impl Equalizer {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut eq = Equalizer {
+            values: [PARAMS[LOW].default, PARAMS[MID].default, PARAMS[MID_FREQ].default, PARAMS[HIGH].default],
+            sample_rate,
+            low: Biquad::new(),
+            mid: Biquad::new(),
+            high: Biquad::new()
+        };
+        eq.update();
+        eq
+    }
+
+    fn update(&mut self) {
+        let values = self.values;
+        self.low.design(Shape::LowShelf, LOW_SHELF_HZ, values[LOW] as f64, self.sample_rate);
+        self.mid.design(Shape::Peak, values[MID_FREQ] as f64, values[MID] as f64, self.sample_rate);
+        self.high.design(Shape::HighShelf, HIGH_SHELF_HZ, values[HIGH] as f64, self.sample_rate);
+    }
+}
+
+impl Effect for Equalizer {
+    fn name(&self) -> &'static str {
+        "eq"
+    }
+
+    fn params(&self) -> &'static [Param] {
+        &PARAMS
+    }
+
+    fn set_param(&mut self, param: usize, value: f32) {
+        if param < self.values.len() {
+            self.values[param] = value;
+            self.update();
+        }
+    }
+
+    fn param(&self, param: usize) -> f32 {
+        self.values.get(param).cloned().unwrap_or(0.0)
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.update();
+    }
+
+    fn process(&mut self, samples: &mut AudioBuffer) {
+        for sample in samples.iter_mut() {
+            *sample = self.high.run(self.mid.run(self.low.run(*sample)));
+        }
+    }
+}
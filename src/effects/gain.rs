@@ -0,0 +1,64 @@
+// a fixed gain in dB, smoothed so moving it doesn't zip
+
+use basic_types::AudioBuffer;
+use effects::{Effect, Param};
+
+const SMOOTHING_MS: f32 = 10.0;
+
+static PARAMS: [Param; 1] = [
+    Param { name: "gain", unit: "dB", min: -24.0, max: 12.0, default: 0.0, log: false }
+];
+
+fn smoothing_coeff(sample_rate: u32) -> f32 {
+    (-1000.0 / (SMOOTHING_MS * sample_rate.max(1) as f32)).exp()
+}
+
+pub struct Gain {
+    db: f32,
+    target: f32,
+    gain: f32,
+    smoothing: f32
+}
+
+impl Gain {
+    pub fn new(sample_rate: u32) -> Self {
+        Gain {
+            db: PARAMS[0].default,
+            target: 1.0,
+            gain: 1.0,
+            smoothing: smoothing_coeff(sample_rate)
+        }
+    }
+}
+
+impl Effect for Gain {
+    fn name(&self) -> &'static str {
+        "gain"
+    }
+
+    fn params(&self) -> &'static [Param] {
+        &PARAMS
+    }
+
+    fn set_param(&mut self, param: usize, value: f32) {
+        if param == 0 {
+            self.db = value;
+            self.target = 10.0_f32.powf(value / 20.0);
+        }
+    }
+
+    fn param(&self, _param: usize) -> f32 {
+        self.db
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.smoothing = smoothing_coeff(sample_rate);
+    }
+
+    fn process(&mut self, samples: &mut AudioBuffer) {
+        for sample in samples.iter_mut() {
+            self.gain = self.target + (self.gain - self.target) * self.smoothing;
+            *sample *= self.gain;
+        }
+    }
+}
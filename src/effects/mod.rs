@@ -0,0 +1,339 @@
+// processing after the organ mix, an ordered chain of effects between the swell pedal and
+// the limiter
+// every effect works on the block in place and describes its parameters so they can be set
+// from midi ccs without knowing what kind of effect is in a slot
+
+use basic_types::AudioBuffer;
+use basic_types::graph::{Node, Inputs};
+use midi;
+
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+mod eq;
+mod dc_blocker;
+mod gain;
+mod spring;
+mod convolution;
+mod cabinet;
+
+pub use self::eq::Equalizer;
+pub use self::dc_blocker::DcBlocker;
+pub use self::gain::Gain;
+pub use self::spring::SpringReverb;
+pub use self::convolution::{Convolver, ImpulseResponse, resample};
+pub use self::cabinet::Cabinet;
+
+/// A parameter an effect exposes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Param {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    /// Controllers move through the range logarithmically, for frequencies
+    pub log: bool
+}
+
+impl Param {
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.max(self.min).min(self.max)
+    }
+
+    /// A midi controller's position across the range, 0 is min and 127 max
+    pub fn from_cc(&self, value: midi::U7) -> f32 {
+        let position = value.min(127) as f32 / 127.0;
+        if self.log {
+            self.min * (self.max / self.min).powf(position)
+        }
+        else {
+            self.min + (self.max - self.min) * position
+        }
+    }
+}
+
+pub trait Effect: Send {
+    fn name(&self) -> &'static str;
+
+    /// Every parameter, set_param and param take an index into this
+    fn params(&self) -> &'static [Param];
+
+    /// Values come already clamped to the parameter's range
+    fn set_param(&mut self, param: usize, value: f32);
+
+    fn param(&self, param: usize) -> f32;
+
+    fn set_sample_rate(&mut self, sample_rate: u32);
+
+    /// Process a block in place
+    fn process(&mut self, samples: &mut AudioBuffer);
+}
+
+/// The effects organn comes with, for choosing them by name
+#[derive(Clone, Debug, PartialEq)]
+pub enum EffectType {
+    Equalizer,
+    DcBlocker,
+    Gain,
+    SpringReverb,
+    /// With the impulse responses it can switch between
+    Cabinet(Vec<Arc<ImpulseResponse>>)
+}
+
+impl EffectType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "eq" => Some(EffectType::Equalizer),
+            "dc" => Some(EffectType::DcBlocker),
+            "gain" => Some(EffectType::Gain),
+            "spring" => Some(EffectType::SpringReverb),
+            _ => None
+        }
+    }
+
+    /// A name, or a cabinet with the wav files to load its responses from e.g.
+    /// "cabinet=open.wav+closed.wav"
+    pub fn load(text: &str) -> Result<Self, String> {
+        if let Some(files) = text.strip_prefix("cabinet=") {
+            let responses = files.split('+')
+                .map(|file| file.trim())
+                .filter(|file| !file.is_empty())
+                .map(|file| ImpulseResponse::load(Path::new(file)).map(Arc::new))
+                .collect::<Result<Vec<_>, _>>()?;
+            if responses.is_empty() {
+                return Err("a cabinet needs an impulse response, cabinet=<file>[+<file>...]".to_string());
+            }
+            return Ok(EffectType::Cabinet(responses));
+        }
+        EffectType::parse(text).ok_or(format!("unknown effect \"{}\", expected eq, dc, gain, spring or cabinet=<file>", text))
+    }
+
+    pub fn create(&self, sample_rate: u32) -> Box<dyn Effect> {
+        match *self {
+            EffectType::Equalizer => Box::new(Equalizer::new(sample_rate)),
+            EffectType::DcBlocker => Box::new(DcBlocker::new(sample_rate)),
+            EffectType::Gain => Box::new(Gain::new(sample_rate)),
+            EffectType::SpringReverb => Box::new(SpringReverb::new(sample_rate)),
+            EffectType::Cabinet(ref responses) => Box::new(Cabinet::new(responses.clone(), sample_rate))
+        }
+    }
+}
+
+/// A chain of effects by name, comma separated in the order they run e.g. "dc,eq,gain", any
+/// impulse responses are loaded
+pub fn parse_chain(text: &str) -> Result<Vec<EffectType>, String> {
+    text.split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(EffectType::load)
+        .collect()
+}
+
+/// A change to a chain that's running, see ChainControl
+pub enum ChainMessage {
+    /// An effect to go before the one at the index and the rate it was made at
+    Insert(usize, Box<dyn Effect>, u32),
+    Remove(usize),
+    /// From and to, as move_effect
+    Move(usize, usize),
+    Bypass(usize, bool)
+}
+
+/// Changes the chain once the engine is running on the audio thread, the changes are made
+/// before the next block. Effects are made here, and the ones taken out come back to be
+/// dropped here, so the audio thread doesn't allocate or free them
+pub struct ChainControl {
+    messages: mpsc::Sender<ChainMessage>,
+    removed: mpsc::Receiver<Box<dyn Effect>>,
+    sample_rate: Arc<AtomicU32>
+}
+
+impl ChainControl {
+    /// A control and the ends the audio thread keeps, the chain's messages and where removed
+    /// effects go. The sample rate is kept up to date by whoever runs the chain
+    pub fn new(sample_rate: Arc<AtomicU32>) -> (Self, mpsc::Receiver<ChainMessage>, mpsc::Sender<Box<dyn Effect>>) {
+        let (messages, chain_messages) = mpsc::channel();
+        let (chain_removed, removed) = mpsc::channel();
+        let control = ChainControl {
+            messages,
+            removed,
+            sample_rate
+        };
+        (control, chain_messages, chain_removed)
+    }
+
+    fn send(&self, message: ChainMessage) {
+        // drop anything taken out since last time
+        while self.removed.try_recv().is_ok() {}
+        self.messages.send(message).ok();
+    }
+
+    /// Put a new effect before the one at index, or at the end
+    pub fn insert(&self, index: usize, effect: &EffectType) {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        self.send(ChainMessage::Insert(index, effect.create(sample_rate), sample_rate));
+    }
+
+    pub fn remove(&self, index: usize) {
+        self.send(ChainMessage::Remove(index));
+    }
+
+    pub fn move_effect(&self, from: usize, to: usize) {
+        self.send(ChainMessage::Move(from, to));
+    }
+
+    pub fn set_bypass(&self, index: usize, bypass: bool) {
+        self.send(ChainMessage::Bypass(index, bypass));
+    }
+}
+
+struct Slot {
+    effect: Box<dyn Effect>,
+    bypass: bool
+}
+
+/// Effects run in order on the mix, each one can be bypassed
+pub struct EffectChain {
+    slots: Vec<Slot>,
+    sample_rate: u32
+}
+
+impl EffectChain {
+    pub fn new(sample_rate: u32) -> Self {
+        EffectChain {
+            slots: Vec::new(),
+            sample_rate
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Add an effect to the end of the chain
+    pub fn push(&mut self, effect: Box<dyn Effect>) {
+        let end = self.slots.len();
+        self.insert(end, effect);
+    }
+
+    /// Put an effect before the one at index, it's set to the chain's sample rate
+    pub fn insert(&mut self, index: usize, mut effect: Box<dyn Effect>) {
+        effect.set_sample_rate(self.sample_rate);
+        self.slots.insert(index.min(self.slots.len()), Slot {
+            effect,
+            bypass: false
+        });
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Box<dyn Effect>> {
+        if index < self.slots.len() {
+            Some(self.slots.remove(index).effect)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Move an effect to another place in the order
+    pub fn move_effect(&mut self, from: usize, to: usize) {
+        if from < self.slots.len() {
+            let slot = self.slots.remove(from);
+            let to = to.min(self.slots.len());
+            self.slots.insert(to, slot);
+        }
+    }
+
+    pub fn effect(&self, index: usize) -> Option<&dyn Effect> {
+        self.slots.get(index).map(|s| &*s.effect)
+    }
+
+    pub fn effect_mut(&mut self, index: usize) -> Option<&mut dyn Effect> {
+        match self.slots.get_mut(index) {
+            Some(slot) => Some(&mut *slot.effect),
+            None => None
+        }
+    }
+
+    /// Make a change from a ChainControl, returns any effect taken out
+    pub fn apply(&mut self, message: ChainMessage) -> Option<Box<dyn Effect>> {
+        match message {
+            ChainMessage::Insert(index, effect, sample_rate) => {
+                // made at another rate if the rate changed on the way here
+                if sample_rate == self.sample_rate {
+                    self.slots.insert(index.min(self.slots.len()), Slot {
+                        effect,
+                        bypass: false
+                    });
+                }
+                else {
+                    self.insert(index, effect);
+                }
+                None
+            }
+            ChainMessage::Remove(index) => self.remove(index),
+            ChainMessage::Move(from, to) => {
+                self.move_effect(from, to);
+                None
+            }
+            ChainMessage::Bypass(index, bypass) => {
+                self.set_bypass(index, bypass);
+                None
+            }
+        }
+    }
+
+    /// Skip an effect without taking it out of the chain
+    pub fn set_bypass(&mut self, index: usize, bypass: bool) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            slot.bypass = bypass;
+        }
+    }
+
+    pub fn is_bypassed(&self, index: usize) -> bool {
+        self.slots.get(index).is_some_and(|s| s.bypass)
+    }
+
+    /// Set a parameter, clamped to its range, anything not in the chain is ignored
+    pub fn set_param(&mut self, index: usize, param: usize, value: f32) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            if let Some(info) = slot.effect.params().get(param) {
+                slot.effect.set_param(param, info.clamp(value));
+            }
+        }
+    }
+
+    /// Set a parameter from a midi controller
+    pub fn set_param_cc(&mut self, index: usize, param: usize, value: midi::U7) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            if let Some(info) = slot.effect.params().get(param) {
+                slot.effect.set_param(param, info.from_cc(value));
+            }
+        }
+    }
+}
+
+impl Node for EffectChain {
+    fn num_inputs(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer) {
+        *output = *inputs.get(0);
+        for slot in self.slots.iter_mut().filter(|s| !s.bypass) {
+            slot.effect.process(output);
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        for slot in self.slots.iter_mut() {
+            slot.effect.set_sample_rate(sample_rate);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// spring reverb, two springs of different lengths each modelled as a delay loop with a chain
+// of allpasses in it
+// the allpasses hold low frequencies back more than high ones so every trip along the spring
+// smears an echo into a falling chirp, the "drip" of a real tank, and each echo drips more
+// than the one before
+
+use basic_types::AudioBuffer;
+use effects::{Effect, Param};
+
+use std::f32::consts::PI;
+
+// round trip times, not multiples of each other so the echoes don't line up
+const SPRING_MS: [f32; 2] = [33.0, 41.0];
+// allpasses per spring and their coefficient, negative delays the low end
+const ALLPASSES: usize = 40;
+const ALLPASS_COEFF: f32 = -0.7;
+
+const SMOOTHING_MS: f32 = 10.0;
+// keeps a dying tail out of denormals
+const ANTI_DENORMAL: f32 = 1e-18;
+
+const MIX: usize = 0;
+const DECAY: usize = 1;
+const TONE: usize = 2;
+
+static PARAMS: [Param; 3] = [
+    Param { name: "mix", unit: "", min: 0.0, max: 1.0, default: 0.25, log: false },
+    Param { name: "decay", unit: "s", min: 0.5, max: 6.0, default: 2.0, log: true },
+    Param { name: "tone", unit: "Hz", min: 1_000.0, max: 8_000.0, default: 3_500.0, log: true }
+];
+
+// first order allpass, (a + z^-1) / (1 + a z^-1)
+#[derive(Clone, Copy)]
+struct Allpass {
+    last_in: f32,
+    last_out: f32
+}
+
+impl Allpass {
+    #[inline]
+    fn run(&mut self, sample: f32) -> f32 {
+        let out = ALLPASS_COEFF * sample + self.last_in - ALLPASS_COEFF * self.last_out;
+        self.last_in = sample;
+        self.last_out = out;
+        out
+    }
+}
+
+struct Spring {
+    length_ms: f32,
+    delay: Vec<f32>,
+    pos: usize,
+    allpasses: [Allpass; ALLPASSES],
+    lowpass: f32,
+    feedback: f32,
+    // an echo train of this feedback comes out at about the level that went in
+    level: f32
+}
+
+impl Spring {
+    fn new(length_ms: f32, sample_rate: u32) -> Self {
+        Spring {
+            length_ms,
+            delay: vec![0.0; ((length_ms * sample_rate as f32 / 1000.0) as usize).max(1)],
+            pos: 0,
+            allpasses: [Allpass { last_in: 0.0, last_out: 0.0 }; ALLPASSES],
+            lowpass: 0.0,
+            feedback: 0.0,
+            level: 1.0
+        }
+    }
+
+    // feedback for the loop to fall 60dB in the decay time
+    fn set_decay(&mut self, decay: f32) {
+        self.feedback = 10.0_f32.powf(-3.0 * self.length_ms / (1000.0 * decay));
+        self.level = (1.0 - self.feedback * self.feedback).sqrt();
+    }
+
+    #[inline]
+    fn run(&mut self, sample: f32, tone_coeff: f32) -> f32 {
+        let mut echo = self.delay[self.pos];
+        for allpass in self.allpasses.iter_mut() {
+            echo = allpass.run(echo);
+        }
+        self.lowpass = echo + (self.lowpass - echo) * tone_coeff;
+
+        self.delay[self.pos] = sample + self.lowpass * self.feedback + ANTI_DENORMAL;
+        self.pos = (self.pos + 1) % self.delay.len();
+        self.lowpass * self.level
+    }
+}
+
+pub struct SpringReverb {
+    values: [f32; 3],
+    sample_rate: u32,
+    springs: Vec<Spring>,
+    tone_coeff: f32,
+    mix: f32,
+    smoothing: f32
+}
+
+impl SpringReverb {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut reverb = SpringReverb {
+            values: [PARAMS[MIX].default, PARAMS[DECAY].default, PARAMS[TONE].default],
+            sample_rate,
+            springs: Vec::new(),
+            tone_coeff: 0.0,
+            mix: PARAMS[MIX].default,
+            smoothing: 0.0
+        };
+        reverb.set_sample_rate(sample_rate);
+        reverb
+    }
+
+    fn update(&mut self) {
+        let sample_rate = self.sample_rate.max(1) as f32;
+        let tone = self.values[TONE].min(sample_rate * 0.45);
+        self.tone_coeff = (-2.0 * PI * tone / sample_rate).exp();
+        for spring in self.springs.iter_mut() {
+            spring.set_decay(self.values[DECAY]);
+        }
+    }
+}
+
+impl Effect for SpringReverb {
+    fn name(&self) -> &'static str {
+        "spring"
+    }
+
+    fn params(&self) -> &'static [Param] {
+        &PARAMS
+    }
+
+    fn set_param(&mut self, param: usize, value: f32) {
+        if param < self.values.len() {
+            self.values[param] = value;
+            self.update();
+        }
+    }
+
+    fn param(&self, param: usize) -> f32 {
+        self.values.get(param).cloned().unwrap_or(0.0)
+    }
+
+    /// The springs empty, what was ringing is lost
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.springs = SPRING_MS.iter().map(|ms| Spring::new(*ms, sample_rate)).collect();
+        self.smoothing = (-1000.0 / (SMOOTHING_MS * sample_rate.max(1) as f32)).exp();
+        self.update();
+    }
+
+    fn process(&mut self, samples: &mut AudioBuffer) {
+        let target = self.values[MIX];
+        let scale = 1.0 / self.springs.len() as f32;
+
+        for sample in samples.iter_mut() {
+            self.mix = target + (self.mix - target) * self.smoothing;
+
+            let dry = *sample;
+            let mut wet = 0.0;
+            for spring in self.springs.iter_mut() {
+                wet += spring.run(dry, self.tone_coeff);
+            }
+            *sample = dry * (1.0 - self.mix) + wet * scale * self.mix;
+        }
+    }
+}
@@ -1,6 +1,7 @@
 // really simple envelope, short linear attack/release, mostly for preventing clicks
 
-use basic_types::{Input, Output, BLANK_BUFFER, AudioBuffer};
+use basic_types::AudioBuffer;
+use basic_types::graph::{Node, Inputs};
 
 enum State {
     Off,
@@ -9,22 +10,25 @@ enum State {
     Down
 }
 
-pub struct Env<T, U> where T: Input, U: Output {
-    input: T,
-    output: U,
+pub struct Env {
     state: State,
     pos: u32,
+    time_ms: u32,
     ramp_samples: u32,
 }
 
-impl<T, U> Env<T, U> where T: Input, U: Output {
-    pub fn new(input: T, output: U, time_ms: u32, sample_rate: u32) -> Self {
+// at least a sample so the level never divides by zero, even for a zero time or rate
+fn ramp_samples(time_ms: u32, sample_rate: u32) -> u32 {
+    ((time_ms as u64 * sample_rate as u64) / 1000).clamp(1, u32::MAX as u64) as u32
+}
+
+impl Env {
+    pub fn new(time_ms: u32, sample_rate: u32) -> Self {
         Env {
-            input: input,
-            output: output,
             state: State::Off,
             pos: 0,
-            ramp_samples: (time_ms * sample_rate) / 1000
+            time_ms,
+            ramp_samples: ramp_samples(time_ms, sample_rate)
         }
     }
 
@@ -36,6 +40,11 @@ impl<T, U> Env<T, U> where T: Input, U: Output {
         self.state = State::Down;
     }
 
+    /// Released all the way, the output would be silent
+    pub fn is_idle(&self) -> bool {
+        matches!(self.state, State::Off)
+    }
+
     // update pos/state once per sample
     fn update(&mut self) {
         match self.state {
@@ -59,15 +68,24 @@ impl<T, U> Env<T, U> where T: Input, U: Output {
         }
     }
 
-    pub fn run(&mut self) {
-        let mut samples: AudioBuffer = BLANK_BUFFER;
+}
+
+impl Node for Env {
+    fn num_inputs(&self) -> usize {
+        1
+    }
 
-        let in_samples = self.input.get_audio();
-        for (sample, in_sample) in samples.iter_mut().zip(in_samples.iter()) {
+    fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer) {
+        for (sample, in_sample) in output.iter_mut().zip(inputs.get(0).iter()) {
             self.update();
             *sample = (*in_sample * self.pos as f32) / self.ramp_samples as f32;
         }
+    }
 
-        self.output.supply_audio(samples);
+    /// Rederive the ramp length, a ramp in progress carries on from the same level
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        let ramp_samples = ramp_samples(self.time_ms, sample_rate);
+        self.pos = ((self.pos as u64 * ramp_samples as u64) / self.ramp_samples as u64) as u32;
+        self.ramp_samples = ramp_samples;
     }
 }
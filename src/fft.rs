@@ -0,0 +1,141 @@
+// radix 2 fft, enough for convolution
+// everything is worked out when it's made so transforms don't allocate and can run on the
+// audio thread
+
+use std::f64::consts::PI;
+use std::ops::{Add, Sub, Mul};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Complex {
+            re,
+            im
+        }
+    }
+
+    pub fn zero() -> Self {
+        Complex::new(0.0, 0.0)
+    }
+
+    pub fn conj(self) -> Self {
+        Complex::new(self.re, -self.im)
+    }
+
+    /// Add a * b, the inner loop of convolution
+    #[inline]
+    pub fn mul_add(&mut self, a: Complex, b: Complex) {
+        self.re += a.re * b.re - a.im * b.im;
+        self.im += a.re * b.im + a.im * b.re;
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+}
+
+impl Mul<f32> for Complex {
+    type Output = Complex;
+    fn mul(self, other: f32) -> Complex {
+        Complex::new(self.re * other, self.im * other)
+    }
+}
+
+pub struct Fft {
+    size: usize,
+    // e^(-2 pi i k / size) for the first half
+    twiddles: Vec<Complex>,
+    reversed: Vec<usize>
+}
+
+impl Fft {
+    /// Size has to be a power of two
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two(), "fft size {} isn't a power of two", size);
+        let bits = size.trailing_zeros();
+
+        let twiddles = (0..(size / 2))
+            .map(|k| {
+                    let angle = -2.0 * PI * k as f64 / size as f64;
+                    Complex::new(angle.cos() as f32, angle.sin() as f32)
+                })
+            .collect();
+        let reversed = (0..size)
+            .map(|i| if bits == 0 { 0 } else { i.reverse_bits() >> (usize::BITS - bits) })
+            .collect();
+
+        Fft {
+            size,
+            twiddles,
+            reversed
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn transform(&self, data: &mut [Complex], inverse: bool) {
+        assert_eq!(data.len(), self.size);
+
+        for i in 0..self.size {
+            let j = self.reversed[i];
+            if j > i {
+                data.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= self.size {
+            let half = len / 2;
+            let stride = self.size / len;
+            for start in (0..self.size).step_by(len) {
+                for k in 0..half {
+                    let twiddle = self.twiddles[k * stride];
+                    let twiddle = if inverse { twiddle.conj() } else { twiddle };
+                    let a = data[start + k];
+                    let b = data[start + k + half] * twiddle;
+                    data[start + k] = a + b;
+                    data[start + k + half] = a - b;
+                }
+            }
+            len *= 2;
+        }
+    }
+
+    /// In place, unscaled
+    pub fn forward(&self, data: &mut [Complex]) {
+        self.transform(data, false);
+    }
+
+    /// In place and scaled by 1 / size, so forward then inverse gives back what went in
+    pub fn inverse(&self, data: &mut [Complex]) {
+        self.transform(data, true);
+        let scale = 1.0 / self.size as f32;
+        for value in data.iter_mut() {
+            *value = *value * scale;
+        }
+    }
+}
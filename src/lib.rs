@@ -0,0 +1,39 @@
+// the platform independent part of organn, everything from midi messages to mixed audio
+
+extern crate midi;
+extern crate rb;
+#[macro_use]
+extern crate log;
+
+#[cfg(unix)]
+extern crate libc;
+#[cfg(all(feature = "coreaudio", target_os = "macos"))]
+extern crate coreaudio_rs;
+#[cfg(all(feature = "alsa", target_os = "linux"))]
+extern crate alsa;
+#[cfg(feature = "jack")]
+extern crate jack;
+
+pub mod basic_types;
+pub mod oscillator;
+pub mod additive;
+pub mod mixer;
+pub mod env;
+pub mod chiff;
+pub mod organ;
+pub mod voice;
+pub mod pool;
+pub mod multi;
+pub mod controls;
+pub mod swell;
+pub mod tremulant;
+pub mod limiter;
+pub mod effects;
+pub mod fft;
+pub mod stats;
+pub mod tuning;
+pub mod scala;
+pub mod text_file;
+pub mod wav;
+pub mod audio;
+pub mod shutdown;
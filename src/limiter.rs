@@ -0,0 +1,115 @@
+// output safety stage, a soft clipper that keeps the signal inside the ceiling
+// plus peak metering that can be read from any thread
+
+use basic_types::AudioBuffer;
+use basic_types::graph::{Node, Inputs};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+const DEFAULT_THRESHOLD: f32 = 0.8;
+const DEFAULT_CEILING: f32 = 0.98;
+
+/// Peak level and limiting count, written by the audio thread and read by anyone
+pub struct PeakMeter {
+    // bits of a positive f32, which compare the same way as the floats
+    peak: AtomicU32,
+    limited: AtomicUsize
+}
+
+impl PeakMeter {
+    fn new() -> Self {
+        PeakMeter {
+            peak: AtomicU32::new(0),
+            limited: AtomicUsize::new(0)
+        }
+    }
+
+    fn update(&self, peak: f32, limited: usize) {
+        self.peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+        if limited > 0 {
+            self.limited.fetch_add(limited, Ordering::Relaxed);
+        }
+    }
+
+    /// Highest level going into the limiter since the last call, above 1.0 would have clipped
+    pub fn take_peak(&self) -> f32 {
+        f32::from_bits(self.peak.swap(0, Ordering::Relaxed))
+    }
+
+    /// Total number of samples that have been limited
+    pub fn limited_samples(&self) -> usize {
+        self.limited.load(Ordering::Relaxed)
+    }
+}
+
+// linear below the threshold, then bends over smoothly towards the ceiling
+fn soft_clip(sample: f32, threshold: f32, ceiling: f32) -> f32 {
+    let level = sample.abs();
+    if level <= threshold {
+        return sample;
+    }
+    let knee = ceiling - threshold;
+    let out = if knee > 0.0 {
+        threshold + knee * ((level - threshold) / knee).tanh()
+    }
+    else {
+        ceiling
+    };
+    out.copysign(sample)
+}
+
+pub struct Limiter {
+    threshold: f32,
+    ceiling: f32,
+    meter: Arc<PeakMeter>
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter::new()
+    }
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Limiter {
+            threshold: DEFAULT_THRESHOLD,
+            ceiling: DEFAULT_CEILING,
+            meter: Arc::new(PeakMeter::new())
+        }
+    }
+
+    /// Where limiting starts and the level it never goes over, both linear gains up to 1.0
+    pub fn set_levels(&mut self, threshold: f32, ceiling: f32) {
+        self.ceiling = ceiling.clamp(0.0, 1.0);
+        self.threshold = threshold.max(0.0).min(self.ceiling);
+    }
+
+    pub fn meter(&self) -> Arc<PeakMeter> {
+        self.meter.clone()
+    }
+
+}
+
+impl Node for Limiter {
+    fn num_inputs(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer) {
+        let mut peak: f32 = 0.0;
+        let mut limited = 0;
+
+        for (sample, in_sample) in output.iter_mut().zip(inputs.get(0).iter()) {
+            let level = in_sample.abs();
+            peak = peak.max(level);
+            if level > self.threshold {
+                limited += 1;
+            }
+            *sample = soft_clip(*in_sample, self.threshold, self.ceiling);
+        }
+
+        self.meter.update(peak, limited);
+    }
+}
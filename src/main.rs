@@ -1,59 +1,348 @@
-extern crate coreaudio_rs as coreaudio;
-extern crate pitch_calc;
-extern crate midi;
+extern crate getopts;
+#[macro_use]
+extern crate log;
 extern crate midi_wrap;
-extern crate rb;
+extern crate organn;
 
-mod basic_types;
-mod oscillator;
-mod mixer;
-mod env;
-mod voice;
-mod multi;
+use getopts::{Options, Matches};
+use log::{Log, Metadata, Record, LevelFilter};
+use midi_wrap::Event;
 
-use midi_wrap::MidiWrap;
+use std::io::{self, Write};
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use coreaudio::audio_unit::{AudioUnit, Type, SubType};
-use std::io;
+use organn::audio::{self, Renderer};
+use organn::shutdown;
+use organn::multi::{Multi, MultiConfig, MultiMidiConn};
+use organn::controls::{self, CcMap};
+use organn::effects;
+use organn::organ::Organ;
+use organn::tuning::{Tuning, TuningBank};
+use organn::scala::{self, Scale, KeyboardMap};
 
-use basic_types::{BLANK_BUFFER, BUFFER_SIZE, AudioBuffer, Input};
-use multi::Multi;
+const BLOCK_SIZE: usize = 256;
+const REFERENCE_PITCH: f32 = 440.0;
+const LIMITER_THRESHOLD: f32 = 0.8;
+const LIMITER_CEILING: f32 = 0.98;
+const LOOKAHEAD: usize = 4;
+// longest to wait for the fade out, in case the audio isn't running
+const FADE_OUT_TIMEOUT_MS: u64 = 500;
+// how often to look for errors from the audio threads
+const ERROR_CHECK_MS: u64 = 250;
 
-const SAMPLE_RATE: u32 = 44_100;
+// log messages go to stderr, filtered by --log-level
+struct StderrLog;
+
+impl Log for StderrLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            writeln!(io::stderr(), "{}: {}", record.level(), record.args()).ok();
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLog = StderrLog;
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt("p", "polyphony", "number of voices (32)", "VOICES");
+    opts.optopt("t", "threads", "threads rendering voices, counting the one doing the mixing (4)", "THREADS");
+    opts.optopt("r", "sample-rate", "sample rate to ask the device for, its own rate by default", "HZ");
+    opts.optopt("b", "block-size", "frames to render at a time, if the backend lets us pick (256)", "FRAMES");
+    opts.optopt("k", "lookahead", "blocks of 16 frames to render ahead of the device, 0 to render in the audio callback (4)", "BLOCKS");
+    opts.optopt("a", "audio", "audio backend, the first from --list-devices by default", "BACKEND");
+    opts.optopt("d", "audio-device", "device for the audio backend, a file name for wav", "DEVICE");
+    opts.optopt("m", "midi", "midi backend, the first from --list-devices by default", "BACKEND");
+    opts.optopt("s", "midi-source", "midi source to connect to the input", "SOURCE");
+    opts.optopt("", "tune", "frequency of A4 from 400 to 480 (440)", "HZ");
+    opts.optopt("", "transpose", "semitones to transpose by, from -64 to 63 (0)", "SEMITONES");
+    opts.optopt("g", "registration", "drawbars to start with, 9 digits from 0 to 8", "DIGITS");
+    opts.optopt("c", "cc-map", "file of \"<cc> <control>\" lines to replace the default cc map", "FILE");
+    opts.optopt("o", "organ", "play pipe organ stops from a file instead of drawbars, \"builtin\" for a small organ that comes with organn", "FILE");
+    opts.optopt("e", "effects", "effects after the swell pedal in order, from eq, dc, gain, spring and cabinet=<wav>[+<wav>...] e.g. \"dc,eq\"", "EFFECTS");
+    opts.optflag("", "swell-tone", "have the expression pedal keep the bass up as it closes, like a real swell box");
+    opts.optopt("l", "log-level", "off, error, warn, info, debug or trace (warn)", "LEVEL");
+    opts.optopt("", "stats", "report engine load every so many seconds, to the log when running as a daemon", "SECONDS");
+    opts.optflag("D", "daemon", "don't read the terminal, run until SIGINT or SIGTERM");
+    opts.optflag("", "list-devices", "list the audio and midi backends and their devices");
+    opts.optflag("h", "help", "print this help");
+    opts
+}
+
+fn print_usage(opts: &Options) {
+    let brief = "Usage: organn [options] [tuning.scl [mapping.kbm]]...";
+    print!("{}", opts.usage(brief));
+}
+
+// an option that's parsed from a string, or the default if it isn't there
+fn parse_opt<T: FromStr>(matches: &Matches, name: &str, default: T) -> Result<T, String> {
+    match matches.opt_str(name) {
+        Some(value) => value.parse::<T>().map_err(|_| format!("bad value for --{}: \"{}\"", name, value)),
+        None => Ok(default)
+    }
+}
+
+// as parse_opt, but a value given that fails the check is an error too, with what was expected
+fn parse_checked<T: FromStr, F>(matches: &Matches, name: &str, default: T, check: F, expected: &str) -> Result<T, String>
+    where F: Fn(&T) -> bool {
+
+    match matches.opt_str(name) {
+        Some(value) => {
+            match value.parse::<T>() {
+                Ok(parsed) if check(&parsed) => Ok(parsed),
+                _ => Err(format!("bad value for --{}: \"{}\", {}", name, value, expected))
+            }
+        }
+        None => Ok(default)
+    }
+}
+
+fn list_devices() {
+    println!("audio backends:");
+    for backend in audio::backends() {
+        println!("  {}", backend);
+        match audio::devices(backend) {
+            Ok(devices) => {
+                for device in devices {
+                    println!("    {}", device);
+                }
+            }
+            Err(e) => { println!("    ({})", e); }
+        }
+    }
+
+    println!("midi backends:");
+    for backend in midi_wrap::backends() {
+        println!("  {}", backend);
+        match midi_wrap::sources(backend) {
+            Ok(sources) => {
+                for source in sources {
+                    println!("    {}", source);
+                }
+            }
+            Err(e) => { println!("    ({})", e); }
+        }
+    }
+}
+
+// scala files given on the command line become tuning programs 0, 1, 2...
+// a .kbm file applies to the .scl file before it
+fn load_tunings(files: &[String]) -> Result<TuningBank, String> {
+    let mut tunings = TuningBank::new();
+    let mut program = 0;
+    let mut scale: Option<Scale> = None;
+
+    for arg in files {
+        let path = Path::new(arg);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("scl") => {
+                if let Some(prev) = scale.take() {
+                    let table = scala::note_table(&prev, &KeyboardMap::linear(&prev))?;
+                    tunings.set_program(program, Some(table));
+                    program += 1;
+                }
+                scale = Some(Scale::load(path)?);
+            }
+            Some("kbm") => {
+                let prev = scale.take().ok_or(format!("{}: no scale to map", arg))?;
+                let table = scala::note_table(&prev, &KeyboardMap::load(path)?)?;
+                tunings.set_program(program, Some(table));
+                program += 1;
+            }
+            _ => { return Err(format!("{}: not a .scl or .kbm file", arg)); }
+        }
+    }
+    if let Some(prev) = scale.take() {
+        let table = scala::note_table(&prev, &KeyboardMap::linear(&prev))?;
+        tunings.set_program(program, Some(table));
+    }
+
+    Ok(tunings)
+}
+
+// master tuning and transposition, to start with
+fn master_tuning(matches: &Matches) -> Result<Tuning, String> {
+    let mut tuning = Tuning::new(REFERENCE_PITCH, 0);
+    tuning.set_reference(parse_opt(matches, "tune", REFERENCE_PITCH)?)?;
+    tuning.set_transpose(parse_opt(matches, "transpose", 0)?)?;
+    Ok(tuning)
+}
+
+fn midi_event(midi_conn: &mut MultiMidiConn, event: Event) {
+    match event {
+        Event::Message(midi) => { midi_conn.midi_message(&midi); }
+        Event::SysEx(data) => { midi_conn.sysex(&data); }
+        _ => {}
+    }
+}
+
+// to the terminal, or the log for a daemon with nobody watching
+fn report(daemon: bool, message: String) {
+    if daemon {
+        info!("{}", message);
+    }
+    else {
+        println!("{}", message);
+    }
+}
+
+fn exit_with_error<T>(message: String) -> T {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn engine_config(matches: &Matches) -> Result<MultiConfig, String> {
+    let mut config = MultiConfig::new(audio::DEFAULT_SAMPLE_RATE);
+    config.num_voices = parse_checked(matches, "polyphony", config.num_voices, |v| *v > 0, "need at least one voice")?;
+    config.num_threads = parse_checked(matches, "threads", config.num_threads, |t| *t > 0, "need at least one thread")?;
+    if let Some(registration) = matches.opt_str("registration") {
+        config.registration = controls::parse_registration(&registration)?;
+    }
+    if let Some(path) = matches.opt_str("cc-map") {
+        config.cc_map = CcMap::load(Path::new(&path))?;
+    }
+    if let Some(chain) = matches.opt_str("effects") {
+        config.effects = effects::parse_chain(&chain)?;
+    }
+    if let Some(file) = matches.opt_str("organ") {
+        let organ = if file == "builtin" { Organ::builtin() } else { Organ::load(Path::new(&file))? };
+        config.organ = Some(Arc::new(organ));
+    }
+    Ok(config)
+}
 
 fn main() {
-    let (mut multi, mut midi_conn, audio_connection) = Multi::new(32, 4, SAMPLE_RATE);
+    let opts = options();
+    let matches = opts.parse(env::args().skip(1))
+        .map_err(|e| format!("{}, try --help", e))
+        .unwrap_or_else(exit_with_error);
+    if matches.opt_present("help") {
+        print_usage(&opts);
+        return;
+    }
+
+    let log_level = parse_opt(&matches, "log-level", LevelFilter::Warn).unwrap_or_else(exit_with_error);
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log_level);
+
+    if matches.opt_present("list-devices") {
+        list_devices();
+        return;
+    }
+
+    let mut config = engine_config(&matches).unwrap_or_else(exit_with_error);
+    let sample_rate = if matches.opt_present("sample-rate") {
+        Some(parse_checked(&matches, "sample-rate", 0, |r| *r > 0, "has to be over 0").unwrap_or_else(exit_with_error))
+    }
+    else {
+        None
+    };
+    let block_size = parse_checked(&matches, "block-size", BLOCK_SIZE, |b| *b > 0, "has to be over 0")
+        .unwrap_or_else(exit_with_error);
+    let stats_interval = parse_checked(&matches, "stats", 0.0, |s: &f64| s.is_finite() && *s > 0.0, "has to be a number of seconds over 0")
+        .unwrap_or_else(exit_with_error);
+
+    let mut tunings = load_tunings(&matches.free).unwrap_or_else(exit_with_error);
+    let mut tuning = master_tuning(&matches).unwrap_or_else(exit_with_error);
+    tunings.select(&mut tuning, 0);
+
+    // the backend gets the final say on sample rate
+    let backend_name = matches.opt_str("audio").unwrap_or(audio::backends()[0].to_string());
+    let device = matches.opt_str("audio-device");
+    let mut backend = audio::open(&backend_name, device.as_ref().map(|d| &d[..]), sample_rate, block_size)
+        .unwrap_or_else(exit_with_error);
+    config.sample_rate = backend.sample_rate();
+    match sample_rate {
+        Some(rate) if rate != config.sample_rate => {
+            warn!("{} runs at {}Hz rather than {}Hz", backend_name, config.sample_rate, rate);
+        }
+        _ => {
+            info!("{} runs at {}Hz", backend_name, config.sample_rate);
+        }
+    }
+
+    let (mut multi, mut midi_conn) = Multi::new(config, tuning, tunings);
+    multi.set_swell_tone(matches.opt_present("swell-tone"));
+    multi.set_limiter(LIMITER_THRESHOLD, LIMITER_CEILING);
+    let meter = multi.meter();
+    let fade_out = multi.fade_out();
+
+    let daemon = matches.opt_present("daemon");
 
     // accept midi input
-    let midi_in = MidiWrap::new("organn", "input", move |midi| { midi_conn.midi_message(&midi); });
-
-    // audio buffer and position
-    let mut buf: AudioBuffer = BLANK_BUFFER;
-    let mut pos = BUFFER_SIZE;      // start at the end to trigger fetching audio
-
-    // Construct an Output audio unit.
-    let mut audio_unit = AudioUnit::new(Type::Output, SubType::HalOutput).unwrap();
-    audio_unit.render_callback(Some(Box::new(move |buffer, num_frames| {
-            for frame in (0..num_frames) {
-                if pos >= buf.len() {
-                    multi.run();
-                    buf = audio_connection.get_audio();
-                    pos = 0;
+    let midi_backend = matches.opt_str("midi").unwrap_or(midi_wrap::backends()[0].to_string());
+    let midi_source = matches.opt_str("midi-source");
+    let midi_in = midi_wrap::open(&midi_backend, "organn", "input", midi_source.as_ref().map(|s| &s[..]),
+        Box::new(move |event| { midi_event(&mut midi_conn, event); }))
+        .unwrap_or_else(exit_with_error);
+    report(daemon, format!("midi input is {}", midi_in.name()));
+
+    let lookahead = parse_opt(&matches, "lookahead", LOOKAHEAD).unwrap_or_else(exit_with_error);
+    let renderer = Renderer::new(multi, lookahead);
+    let stats = renderer.stats();
+    backend.start(renderer).unwrap_or_else(exit_with_error);
+
+    // errors on the audio threads are only recorded there, they're logged from here
+    {
+        let stats = stats.clone();
+        thread::spawn(move || {
+                loop {
+                    thread::sleep(Duration::from_millis(ERROR_CHECK_MS));
+                    if let Some(error) = stats.take_error() {
+                        error!("{}", error);
+                    }
                 }
-                let sample = buf[pos];
-                pos += 1;
-                for channel in buffer.iter_mut() {
-                    channel[frame] = sample;
+            });
+    }
+
+    // report on the engine as it goes
+    if matches.opt_present("stats") {
+        let stats = stats.clone();
+        thread::spawn(move || {
+                stats.take_report();
+                loop {
+                    thread::sleep(Duration::from_millis((stats_interval * 1000.0) as u64));
+                    report(daemon, stats.take_report().to_string());
                 }
-            }
-            Ok(())
-        }))).unwrap();
-    audio_unit.set_sample_rate(SAMPLE_RATE as f64).unwrap();
-    audio_unit.start().unwrap();
+            });
+    }
+
+    // play until a signal, or enter is pressed
+    shutdown::handle_signals();
+    if !daemon {
+        thread::spawn(|| {
+                let mut wait_str = String::new();
+                io::stdin().read_line(&mut wait_str).ok();
+                shutdown::request();
+            });
+    }
+    shutdown::wait();
 
-    let mut wait_str = String::new();
-    io::stdin().read_line(&mut wait_str).unwrap();
+    // fade to silence before stopping so it doesn't click
+    fade_out.start();
+    let timeout = Instant::now() + Duration::from_millis(FADE_OUT_TIMEOUT_MS);
+    while !fade_out.is_done() && Instant::now() < timeout {
+        thread::sleep(Duration::from_millis(5));
+    }
 
-    audio_unit.stop().unwrap();
+    // stop midi first so nothing reaches the engine while the backend drops it
     drop(midi_in);
+    if let Err(e) = backend.stop() {
+        error!("{}", e);
+    }
+
+    report(daemon, format!("peak level {:.1}dB, {} samples limited, {} xruns",
+        20.0 * meter.take_peak().log10(), meter.limited_samples(), stats.xruns()));
 }
@@ -1,34 +1,37 @@
-use basic_types::{Input, Output, BLANK_BUFFER, AudioBuffer};
+use basic_types::AudioBuffer;
+use basic_types::graph::{Node, Inputs};
 
-pub struct Mixer<T, U> where T: Input, U: Output {
-    levels: Vec<f32>,
-    inputs: Vec<T>,
-    output: U
+pub struct Mixer {
+    levels: Vec<f32>
 }
 
-impl<T, U> Mixer<T, U> where T: Input, U: Output {
-    pub fn new(inputs: Vec<T>, levels: Vec<f32>, output: U) -> Self {
+impl Mixer {
+    /// An input for each level
+    pub fn new(levels: Vec<f32>) -> Self {
         Mixer {
-            levels: levels,
-            inputs: inputs,
-            output: output
+            levels
         }
     }
 
     pub fn set_level(&mut self, input_num: usize, level: f32) {
         self.levels[input_num] = level;
     }
+}
 
-    pub fn run(&mut self) {
-        let mut samples: AudioBuffer = BLANK_BUFFER;
+impl Node for Mixer {
+    fn num_inputs(&self) -> usize {
+        self.levels.len()
+    }
 
-        for (input, level) in self.inputs.iter().zip(self.levels.iter()) {
-            let in_samples = input.get_audio();
-            for (sample, in_sample) in samples.iter_mut().zip(in_samples.iter()) {
+    fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer) {
+        for sample in output.iter_mut() {
+            *sample = 0.0;
+        }
+
+        for (input, level) in self.levels.iter().enumerate() {
+            for (sample, in_sample) in output.iter_mut().zip(inputs.get(input).iter()) {
                 *sample += *in_sample * level;
             }
         }
-
-        self.output.supply_audio(samples);
     }
 }
@@ -1,37 +1,91 @@
 // a combined set of voices
 
-use basic_types::unthreaded_connection;
-use basic_types::threaded_connection;
-use voice::Voice;
-use mixer::Mixer;
+use basic_types::AudioBuffer;
+use basic_types::graph::{Graph, Handle};
+use voice::{Voice, VoiceMessage};
+use pool::VoicePool;
+use swell::{Swell, FadeOut};
+use tremulant::{self, Tremulant};
+use limiter::{Limiter, PeakMeter};
+use effects::{EffectChain, EffectType, ChainControl, ChainMessage, Effect};
+use organ::Organ;
+use stats::EngineStats;
+use tuning::{Tuning, TuningBank, Rpn};
+use controls::{self, Control, CcMap, Registration, DEFAULT_REGISTRATION};
 use midi::{self, Message};
 
-use std::sync::mpsc;
-use std::thread;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+// every voice goes onto the bus at this gain, whatever the polyphony or thread count
+// a voice peaks at 1.0 with all drawbars out so four of those fill the output,
+// anything more is caught by the limiter
+const VOICE_GAIN: f32 = 0.25;
 
 // voice inputs with note assignments
 struct VoiceAssign {
-    voice: mpsc::Sender<midi::Message>,
+    voice: mpsc::Sender<VoiceMessage>,
     note: Option<midi::U7>,
+    // the same note can be held on two manuals at once
+    division: usize
 }
 
 impl VoiceAssign {
-    fn new(voice: mpsc::Sender<midi::Message>) -> Self {
+    fn new(voice: mpsc::Sender<VoiceMessage>) -> Self {
         VoiceAssign {
             voice: voice,
-            note: None
+            note: None,
+            division: 0
         }
     }
 }
 
+/// How to build the engine
+#[derive(Clone)]
+pub struct MultiConfig {
+    /// Polyphony
+    pub num_voices: usize,
+    /// Threads rendering voices, counting the one that runs the Multi
+    pub num_threads: usize,
+    pub sample_rate: u32,
+    /// Drawbars to start with
+    pub registration: Registration,
+    pub cc_map: CcMap,
+    /// Effects after the swell pedal, in order
+    pub effects: Vec<EffectType>,
+    /// Play pipe organ stops instead of drawbars
+    pub organ: Option<Arc<Organ>>
+}
+
+impl MultiConfig {
+    pub fn new(sample_rate: u32) -> Self {
+        MultiConfig {
+            num_voices: 32,
+            num_threads: 4,
+            sample_rate,
+            registration: DEFAULT_REGISTRATION,
+            cc_map: CcMap::default(),
+            effects: Vec::new(),
+            organ: None
+        }
+    }
+}
 
+// the midi side of a Multi, once the Multi is dropped its voices are gone and anything
+// still arriving goes nowhere
 pub struct MultiMidiConn {
     voices: Vec<VoiceAssign>,
-    last_voice: usize
+    post_mix: mpsc::Sender<(Control, midi::U7)>,
+    last_voice: usize,
+    tuning: Tuning,
+    tunings: TuningBank,
+    rpn: Rpn,
+    cc_map: CcMap
 }
 
 impl MultiMidiConn {
-    fn new(voice_inputs: Vec<mpsc::Sender<midi::Message>>) -> Self {
+    fn new(voice_inputs: Vec<mpsc::Sender<VoiceMessage>>, post_mix: mpsc::Sender<(Control, midi::U7)>, tuning: Tuning, tunings: TuningBank, cc_map: CcMap) -> Self {
         let voice_assigns = voice_inputs
             .into_iter()
             .map(|v| {
@@ -41,7 +95,19 @@ impl MultiMidiConn {
 
         MultiMidiConn {
             voices: voice_assigns,
-            last_voice: 0
+            post_mix,
+            last_voice: 0,
+            tuning,
+            tunings,
+            rpn: Rpn::new(),
+            cc_map
+        }
+    }
+
+    // tuning is global, every voice gets a copy of any change
+    fn send_tuning(&self) {
+        for voice in self.voices.iter() {
+            voice.voice.send(VoiceMessage::Tuning(self.tuning.clone())).ok();
         }
     }
 
@@ -59,27 +125,71 @@ impl MultiMidiConn {
         &mut self.voices[index]
     }
 
+    /// The note each voice is assigned to, None for a free voice
+    pub fn notes(&self) -> Vec<Option<midi::U7>> {
+        self.voices.iter().map(|v| v.note).collect()
+    }
+
     pub fn midi_message(&mut self, message: &Message) {
         match *message {
-            Message::NoteOn(_, pitch, _) => {
+            Message::NoteOn(channel, pitch, _) => {
                 // pick a voice to use
-                let mut voice = self.pick_voice();
+                let voice = self.pick_voice();
                 voice.note = Some(pitch);
-                voice.voice.send(message.clone()).unwrap();
+                voice.division = controls::division(channel);
+                voice.voice.send(VoiceMessage::Midi(message.clone())).ok();
             }
 
-            Message::NoteOff(_, pitch, _) => {
+            Message::NoteOff(channel, pitch, _) => {
                 // send to appropriate voice(s) and unassign their notes
-                for voice in self.voices.iter_mut().filter(|v| v.note == Some(pitch)) {
+                let division = controls::division(channel);
+                for voice in self.voices.iter_mut().filter(|v| v.note == Some(pitch) && v.division == division) {
                     voice.note = None;
-                    voice.voice.send(message.clone()).unwrap();
+                    voice.voice.send(VoiceMessage::Midi(message.clone())).ok();
                 }
             }
 
-            Message::ControlChange(_, _, _) => {
-                // send to all voices
-                for voice in self.voices.iter() {
-                    voice.voice.send(message.clone()).unwrap();
+            Message::AllNotesOff(_) => {
+                // every voice lets go
+                for voice in self.voices.iter_mut() {
+                    voice.note = None;
+                    voice.voice.send(VoiceMessage::Midi(message.clone())).ok();
+                }
+            }
+
+            Message::PitchBend(_, value) => {
+                self.tuning.set_bend(value);
+                self.send_tuning();
+            }
+
+            Message::ControlChange(_, control, value) => {
+                if self.rpn.control(&mut self.tuning, &mut self.tunings, control, value) {
+                    self.send_tuning();
+                }
+                else {
+                    match self.cc_map.control(control) {
+                        Some(Control::Drawbar(drawbar)) => {
+                            // send to all voices, reversed to resemble drawbars
+                            let level = (127 - value.min(127)) as f32 / 127.0;
+                            for voice in self.voices.iter() {
+                                voice.voice.send(VoiceMessage::Drawbar(drawbar, level)).ok();
+                            }
+                        }
+                        Some(Control::Stop(stop)) => {
+                            for voice in self.voices.iter() {
+                                voice.voice.send(VoiceMessage::Stop(stop, value >= 64)).ok();
+                            }
+                        }
+                        Some(Control::Coupler(coupler)) => {
+                            for voice in self.voices.iter() {
+                                voice.voice.send(VoiceMessage::Coupler(coupler, value >= 64)).ok();
+                            }
+                        }
+                        Some(post_mix_control) => {
+                            self.post_mix.send((post_mix_control, value)).ok();
+                        }
+                        None => {}
+                    }
                 }
             }
 
@@ -88,82 +198,217 @@ impl MultiMidiConn {
             }
         }
     }
+
+    pub fn sysex(&mut self, data: &[u8]) {
+        // midi tuning standard retuning, the only sysex used so far
+        if self.tunings.sysex(&mut self.tuning, data) {
+            self.send_tuning();
+        }
+    }
 }
 
 pub struct Multi {
-    voice_threads: Vec<thread::JoinHandle<()>>,
-    mixer: Mixer<threaded_connection::ThreadedInput, unthreaded_connection::UnthreadedOutput>,
+    // the audio thread's own way to reach the voices, for sample rate changes
+    voices: Vec<mpsc::Sender<VoiceMessage>>,
+    graph: Graph,
+    pool: Handle<VoicePool>,
+    swell: Handle<Swell>,
+    effects: Handle<EffectChain>,
+    limiter: Handle<Limiter>,
+    post_mix_input: mpsc::Receiver<(Control, midi::U7)>,
+    // changes to the effects from the latest ChainControl, and where removed ones go back to it
+    chain_input: mpsc::Receiver<ChainMessage>,
+    chain_removed: mpsc::Sender<Box<dyn Effect>>,
+    sample_rate: Arc<AtomicU32>,
+    stats: Arc<EngineStats>,
+    // the graph has failed and it's been recorded, it fails the same way every block
+    graph_failed: bool
 }
 
 impl Multi {
-    pub fn new(num_voices: usize, num_threads: usize, sample_rate: u32) -> (Self, MultiMidiConn, unthreaded_connection::UnthreadedInput) {
+    pub fn new(config: MultiConfig, tuning: Tuning, tunings: TuningBank) -> (Self, MultiMidiConn) {
+        let num_voices = config.num_voices.max(1);
+        let num_threads = config.num_threads.max(1).min(num_voices);
+        let sample_rate = config.sample_rate;
+        debug!("{} voices on {} threads at {}Hz", num_voices, num_threads, sample_rate);
 
-        let mut voice_threads = Vec::new();
+        let mut voices = Vec::new();
         let mut midi_connections = Vec::new();
-        let mut thread_connections = Vec::new();
+        let mut voice_connections = Vec::new();
 
-        // spawn voice threads
-        for i in (0..num_threads) {
-            let mut voice_io = Vec::new();
+        for _ in 0..num_voices {
+            let (midi_connection, midi_input) = mpsc::channel();
+            voice_connections.push(midi_connection.clone());
+            midi_connections.push(midi_connection);
 
-            let (thread_out, mix_input) = threaded_connection::new();
-            thread_connections.push(mix_input);
-
-            // thread 0 gets extra voices if (num_voices / num_threads) has a remainder
-            let voices_for_thread =
-                if i != 0 {
-                    (num_voices / num_threads)
-                }
-                else {
-                    (num_voices / num_threads) + (num_voices % num_threads)
-                };
-
-            for _ in (0..voices_for_thread) {
-                let (midi_connection, midi_input) = mpsc::channel();
-                voice_io.push(midi_input);
-                midi_connections.push(midi_connection);
-            }
+            voices.push(match config.organ {
+                Some(ref organ) => Voice::pipes(sample_rate, tuning.clone(), organ.clone(), midi_input),
+                None => Voice::new(sample_rate, tuning.clone(), &config.registration, midi_input)
+            });
+        }
 
-            let thread = thread::spawn(move || {
-                    let mut voices = Vec::new();
-                    let mut mixer_inputs = Vec::new();
-                    for midi_input in voice_io {
-                        let (voice_output, mix_input) = unthreaded_connection::new();
-                        let voice = Voice::new(sample_rate, midi_input, voice_output);
-                        voices.push(voice);
-                        mixer_inputs.push(mix_input);
-                    }
-                    let mut thread_mix = Mixer::new(mixer_inputs, vec![1.0; voices_for_thread], thread_out);
+        let (post_mix_connection, post_mix_input) = mpsc::channel();
+        let midi_conn = MultiMidiConn::new(midi_connections, post_mix_connection, tuning, tunings, config.cc_map);
 
-                    loop {
-                        for voice in voices.iter_mut() {
-                            if voice.run().is_err() {
-                                return;
-                            }
-                        }
-                        thread_mix.run();
-                    }
-                });
+        let stats = Arc::new(EngineStats::new(sample_rate));
+        let mut graph = Graph::new();
+        let pool = graph.add("voices", VoicePool::new(voices, num_threads, VOICE_GAIN, sample_rate, stats.clone()));
+        let swell = graph.add("swell", Swell::new(sample_rate));
 
-            voice_threads.push(thread);
+        let mut chain = EffectChain::new(sample_rate);
+        for effect in config.effects.iter() {
+            chain.push(effect.create(sample_rate));
         }
+        let effects = graph.add("effects", chain);
 
-        let midi_conn = MultiMidiConn::new(midi_connections);
+        // nobody's controlling the chain until asked for a ChainControl
+        let sample_rate = Arc::new(AtomicU32::new(sample_rate));
+        let (_, chain_input, chain_removed) = ChainControl::new(sample_rate.clone());
 
-        let (output, input) = unthreaded_connection::new();
-        let mixer = Mixer::new(thread_connections, vec![0.25; num_threads], output);
+        let limiter = graph.add("limiter", Limiter::new());
+        graph.connect(&pool, &swell).unwrap();
+        graph.connect(&swell, &effects).unwrap();
+        graph.connect(&effects, &limiter).unwrap();
+        graph.set_output(&limiter).unwrap();
 
         (
             Multi {
-                voice_threads: voice_threads,
-                mixer: mixer
+                voices: voice_connections,
+                graph,
+                pool,
+                swell,
+                effects,
+                limiter,
+                post_mix_input,
+                chain_input,
+                chain_removed,
+                sample_rate,
+                stats,
+                graph_failed: false
             },
-            midi_conn,
-            input
+            midi_conn
         )
     }
 
+    /// Carry on at a different sample rate, the voices change before their next block
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        debug!("sample rate is now {}Hz", sample_rate);
+        for voice in self.voices.iter() {
+            voice.send(VoiceMessage::SampleRate(sample_rate)).ok();
+        }
+        self.graph.set_sample_rate(sample_rate);
+        self.stats.set_sample_rate(sample_rate);
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    /// Have the expression pedal change tone as well as volume
+    pub fn set_swell_tone(&mut self, tone: bool) {
+        self.graph.node_mut(&self.swell).set_tone(tone);
+    }
+
+    /// Output limiter threshold and ceiling as linear gains
+    pub fn set_limiter(&mut self, threshold: f32, ceiling: f32) {
+        self.graph.node_mut(&self.limiter).set_levels(threshold, ceiling);
+    }
+
+    pub fn meter(&self) -> Arc<PeakMeter> {
+        self.graph.node(&self.limiter).meter()
+    }
+
+    /// Render times, load and so on, for reporting from another thread
+    pub fn stats(&self) -> Arc<EngineStats> {
+        self.stats.clone()
+    }
+
+    /// The effects between the swell pedal and the limiter, to change what's in it before the
+    /// audio starts. After that use a ChainControl, controllers set parameters and bypasses
+    /// through MultiMidiConn
+    pub fn effects(&mut self) -> &mut EffectChain {
+        self.graph.node_mut(&self.effects)
+    }
+
+    /// For changing the effects from another thread once the engine is running, only the
+    /// latest one asked for reaches the chain
+    pub fn chain_control(&mut self) -> ChainControl {
+        let (control, chain_input, chain_removed) = ChainControl::new(self.sample_rate.clone());
+        self.chain_input = chain_input;
+        self.chain_removed = chain_removed;
+        control
+    }
+
+    /// A division's tremulant, None if there's no such division
+    pub fn tremulant(&mut self, division: usize) -> Option<&mut Tremulant> {
+        self.graph.node_mut(&self.pool).tremulant(division)
+    }
+
+    /// For fading to silence before the audio stops
+    pub fn fade_out(&self) -> Arc<FadeOut> {
+        self.graph.node(&self.swell).fade_out()
+    }
+
     pub fn run(&mut self) {
-        self.mixer.run();
+        let start = Instant::now();
+
+        while let Ok(message) = self.chain_input.try_recv() {
+            if let Some(removed) = self.graph.node_mut(&self.effects).apply(message) {
+                self.chain_removed.send(removed).ok();
+            }
+        }
+
+        while let Ok((control, value)) = self.post_mix_input.try_recv() {
+            match control {
+                Control::Volume => {
+                    self.graph.node_mut(&self.swell).set_volume(value as f32 / 127.0);
+                }
+                Control::Expression => {
+                    self.graph.node_mut(&self.swell).set_expression(value as f32 / 127.0);
+                }
+                Control::Effect(slot, param) => {
+                    self.graph.node_mut(&self.effects).set_param_cc(slot, param, value);
+                }
+                Control::EffectBypass(slot) => {
+                    self.graph.node_mut(&self.effects).set_bypass(slot, value >= 64);
+                }
+                Control::Tremulant(division) => {
+                    if let Some(tremulant) = self.tremulant(division) {
+                        tremulant.set_on(value >= 64);
+                    }
+                }
+                Control::TremulantRate(division) => {
+                    if let Some(tremulant) = self.tremulant(division) {
+                        let position = value.min(127) as f32 / 127.0;
+                        tremulant.set_rate(tremulant::MIN_RATE + (tremulant::MAX_RATE - tremulant::MIN_RATE) * position);
+                    }
+                }
+                Control::TremulantDepth(division) => {
+                    if let Some(tremulant) = self.tremulant(division) {
+                        tremulant.set_depth(value.min(127) as f32 / 127.0);
+                    }
+                }
+                Control::Drawbar(_) | Control::Stop(_) | Control::Coupler(_) => {}
+            }
+        }
+
+        if let Err(e) = self.graph.run() {
+            if !self.graph_failed {
+                self.graph_failed = true;
+                self.stats.record_error(format!("engine graph: {}", e));
+            }
+        }
+
+        self.stats.record_block(start.elapsed());
+    }
+
+    /// The block rendered by the last run
+    pub fn output(&self) -> &AudioBuffer {
+        self.graph.output()
+    }
+}
+
+impl Drop for Multi {
+    fn drop(&mut self) {
+        // the workers are waiting on the next block, tell them there isn't one
+        let pool = self.pool;
+        self.graph.node_mut(&pool).stop();
     }
 }
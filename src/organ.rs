@@ -0,0 +1,365 @@
+// pipe organ, stops made of ranks of pipes at any footage instead of drawbars
+// a rank is a pipe per note, each pipe a handful of harmonics in the proportions of its stop's
+// kind of pipe plus a chiff as it starts to speak
+// a voice has one pipe of every rank in the organ and sounds those whose stops are drawn on
+// the division its key was played on, or on a division coupled to it
+
+use basic_types::graph::{Graph, Handle};
+use additive::{Additive, PARTIALS};
+use mixer::Mixer;
+use chiff::Chiff;
+use controls::{DIVISION_NAMES, NUM_DIVISIONS};
+use midi;
+use text_file;
+
+use std::path::Path;
+use std::sync::Arc;
+
+// a rank with every harmonic at full goes into a voice at this
+const RANK_GAIN: f32 = 0.25;
+
+/// The tone of a kind of pipe, its harmonics from the fundamental up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Profile {
+    pub name: &'static str,
+    pub harmonics: [f32; PARTIALS],
+    /// Level of the chiff against the tone
+    pub chiff: f32
+}
+
+pub static PROFILES: [Profile; 5] = [
+    Profile { name: "principal", harmonics: [1.0, 0.5, 0.3, 0.2, 0.12, 0.08, 0.05, 0.03, 0.02], chiff: 0.3 },
+    Profile { name: "flute", harmonics: [1.0, 0.15, 0.08, 0.03, 0.01, 0.0, 0.0, 0.0, 0.0], chiff: 0.8 },
+    // closed at the top, so odd harmonics only
+    Profile { name: "stopped", harmonics: [1.0, 0.0, 0.3, 0.0, 0.1, 0.0, 0.04, 0.0, 0.02], chiff: 0.7 },
+    Profile { name: "string", harmonics: [0.6, 0.55, 0.5, 0.45, 0.4, 0.35, 0.3, 0.25, 0.2], chiff: 0.1 },
+    Profile { name: "reed", harmonics: [0.7, 0.8, 0.7, 0.6, 0.55, 0.5, 0.4, 0.35, 0.3], chiff: 0.2 }
+];
+
+impl Profile {
+    pub fn find(name: &str) -> Option<Profile> {
+        PROFILES.iter().find(|p| p.name == name).cloned()
+    }
+}
+
+/// A pipe for every note, at a footage that can change at breaks going up the keyboard
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rank {
+    /// The lowest note each footage starts from, the first from note 0
+    pub footages: Vec<(midi::U7, f32)>
+}
+
+impl Rank {
+    pub fn new(footage: f32) -> Self {
+        Rank {
+            footages: vec![(0, footage)]
+        }
+    }
+
+    pub fn footage(&self, note: midi::U7) -> f32 {
+        self.footages.iter().rev().find(|f| f.0 <= note).map_or(8.0, |f| f.1)
+    }
+
+    /// Of the pipe's pitch to the note's, 8' sounds at the note
+    pub fn ratio(&self, note: midi::U7) -> f32 {
+        8.0 / self.footage(note)
+    }
+}
+
+/// Several ranks make a mixture
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stop {
+    pub name: String,
+    pub division: usize,
+    pub profile: Profile,
+    pub ranks: Vec<Rank>,
+    /// Drawn to start with
+    pub on: bool
+}
+
+/// Sounds the stops of one division from the keys of another
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coupler {
+    pub from: usize,
+    pub to: usize,
+    pub on: bool
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Organ {
+    pub stops: Vec<Stop>,
+    pub couplers: Vec<Coupler>
+}
+
+/// A small two manual organ with pedals
+pub const BUILTIN: &str = "
+stop upper Bourdon16 stopped 16
+stop upper Principal8 principal 8 on
+stop upper Flute8 flute 8
+stop upper Octave4 principal 4 on
+stop upper Twelfth principal 2-2/3
+stop upper Fifteenth principal 2
+stop upper Mixture principal 2+1-1/3+1 60:2-2/3+2+1-1/3 72:4+2-2/3+2
+stop upper Trumpet8 reed 8
+stop lower Gedackt8 stopped 8 on
+stop lower Salicional8 string 8
+stop lower Flute4 flute 4
+stop lower Nazard flute 2-2/3
+stop pedal Subbass16 stopped 16 on
+stop pedal Octave8 principal 8
+coupler lower upper
+coupler upper pedal
+coupler lower pedal
+";
+
+/// Feet as a whole number, a fraction or both e.g. "8", "2-2/3", "1/2" or "5.333"
+pub fn parse_footage(text: &str) -> Option<f32> {
+    let fraction = |text: &str| -> Option<f32> {
+        let mut parts = text.splitn(2, '/');
+        let numerator = parts.next()?.parse::<f32>().ok()?;
+        match parts.next() {
+            Some(denominator) => denominator.parse::<f32>().ok().filter(|d| *d > 0.0).map(|d| numerator / d),
+            None => Some(numerator)
+        }
+    };
+    let footage = match text.find('-') {
+        Some(dash) => text[..dash].parse::<f32>().ok()? + fraction(&text[(dash + 1)..])?,
+        None => fraction(text)?
+    };
+    if footage > 0.0 && footage.is_finite() { Some(footage) } else { None }
+}
+
+fn parse_division(name: &str) -> Option<usize> {
+    DIVISION_NAMES.iter().position(|d| *d == name)
+}
+
+// "2+1-1/3+1", a footage per rank
+fn parse_footages(text: &str) -> Option<Vec<f32>> {
+    text.split('+').map(parse_footage).collect()
+}
+
+impl Organ {
+    pub fn builtin() -> Self {
+        Organ::parse(BUILTIN).unwrap()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        text_file::load(path, Self::parse)
+    }
+
+    /// A line per stop or coupler, in the order controllers number them
+    ///
+    /// "stop <division> <name> <profile> <footages> [<note>:<footages>]... [on]" where footages
+    /// are a footage per rank joined by +, and each <note>: starts a break at that note
+    ///
+    /// "coupler <from> <to> [on]" sounds from's stops from to's keys
+    ///
+    /// Divisions are upper, lower or pedal, anything after a # is a comment
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut organ = Organ {
+            stops: Vec::new(),
+            couplers: Vec::new()
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let error = |what: String| format!("line {}: {}", number + 1, what);
+            let on = words.last() == Some(&"on");
+            if on {
+                words.pop();
+            }
+            let division = |name: &str| parse_division(name)
+                .ok_or(error(format!("unknown division \"{}\", expected upper, lower or pedal", name)));
+
+            match words[0] {
+                "stop" if words.len() >= 5 => {
+                    let profile = Profile::find(words[3])
+                        .ok_or(error(format!("unknown pipe \"{}\", expected principal, flute, stopped, string or reed", words[3])))?;
+                    let first = parse_footages(words[4]).ok_or(error(format!("bad footages \"{}\"", words[4])))?;
+                    let mut ranks: Vec<Rank> = first.iter().map(|f| Rank::new(*f)).collect();
+
+                    for word in words[5..].iter() {
+                        let bad = || error(format!("bad break \"{}\", expected <note>:<footages>", word));
+                        let mut parts = word.splitn(2, ':');
+                        let note = parts.next().and_then(|n| n.parse::<midi::U7>().ok()).filter(|n| *n < 128).ok_or_else(&bad)?;
+                        let footages = parts.next().and_then(parse_footages).ok_or_else(&bad)?;
+                        if footages.len() != ranks.len() {
+                            return Err(error(format!("break at {} has {} ranks, not {}", note, footages.len(), ranks.len())));
+                        }
+                        for (rank, footage) in ranks.iter_mut().zip(footages.iter()) {
+                            if rank.footages.last().is_some_and(|f| f.0 >= note) {
+                                return Err(error(format!("break at {} isn't above the last", note)));
+                            }
+                            rank.footages.push((note, *footage));
+                        }
+                    }
+
+                    organ.stops.push(Stop {
+                        name: words[2].to_string(),
+                        division: division(words[1])?,
+                        profile,
+                        ranks,
+                        on
+                    });
+                }
+                "coupler" if words.len() == 3 => {
+                    organ.couplers.push(Coupler {
+                        from: division(words[1])?,
+                        to: division(words[2])?,
+                        on
+                    });
+                }
+                _ => {
+                    return Err(error(format!("expected \"stop <division> <name> <pipe> <footages>...\" or \"coupler <from> <to>\", got \"{}\"", line.trim())));
+                }
+            }
+        }
+        Ok(organ)
+    }
+
+    /// The divisions whose stops sound from a key on this one
+    pub fn sounding(&self, division: usize, couplers: &[bool]) -> [bool; NUM_DIVISIONS] {
+        let mut sounding = [false; NUM_DIVISIONS];
+        sounding[division] = true;
+        for (coupler, on) in self.couplers.iter().zip(couplers.iter()) {
+            if *on && coupler.to == division {
+                sounding[coupler.from] = true;
+            }
+        }
+        sounding
+    }
+}
+
+// a voice's pipe of one rank
+struct Pipe {
+    stop: usize,
+    rank: usize,
+    additive: Handle<Additive>,
+    chiff: Handle<Chiff>,
+    sounding: bool
+}
+
+/// A voice's pipes, one of every rank in the organ, mixed into one node
+pub struct Pipes {
+    organ: Arc<Organ>,
+    pipes: Vec<Pipe>,
+    mixer: Handle<Mixer>,
+    stops: Vec<bool>,
+    couplers: Vec<bool>,
+    division: usize,
+    note: midi::U7,
+    held: bool
+}
+
+impl Pipes {
+    /// Adds the pipes to a voice's graph, output is the node they're mixed by
+    pub fn new(organ: Arc<Organ>, graph: &mut Graph, sample_rate: u32) -> Self {
+        let mut pipes = Vec::new();
+        for (s, stop) in organ.stops.iter().enumerate() {
+            for r in 0..stop.ranks.len() {
+                let additive = graph.add(&format!("{} {}", stop.name, r + 1), Additive::new(sample_rate));
+                let chiff = graph.add(&format!("{} {} chiff", stop.name, r + 1), Chiff::new(sample_rate));
+                graph.connect(&additive, &chiff).unwrap();
+                pipes.push(Pipe {
+                    stop: s,
+                    rank: r,
+                    additive,
+                    chiff,
+                    sounding: false
+                });
+            }
+        }
+
+        let mixer = graph.add("pipes", Mixer::new(vec![1.0; pipes.len()]));
+        for pipe in pipes.iter() {
+            graph.connect(&pipe.chiff, &mixer).unwrap();
+        }
+
+        Pipes {
+            stops: organ.stops.iter().map(|s| s.on).collect(),
+            couplers: organ.couplers.iter().map(|c| c.on).collect(),
+            organ,
+            pipes,
+            mixer,
+            division: 0,
+            note: 0,
+            held: false
+        }
+    }
+
+    pub fn output(&self) -> Handle<Mixer> {
+        self.mixer
+    }
+
+    /// Draw or push in a stop, anything not in the organ is ignored
+    pub fn set_stop(&mut self, graph: &mut Graph, stop: usize, on: bool) {
+        if stop < self.stops.len() {
+            self.stops[stop] = on;
+            self.update(graph);
+        }
+    }
+
+    pub fn set_coupler(&mut self, graph: &mut Graph, coupler: usize, on: bool) {
+        if coupler < self.couplers.len() {
+            self.couplers[coupler] = on;
+            self.update(graph);
+        }
+    }
+
+    /// Tune every pipe for a note, freq is the note's own pitch
+    pub fn set_pitch(&mut self, graph: &mut Graph, note: midi::U7, freq: f32) {
+        self.note = note;
+        for pipe in self.pipes.iter() {
+            let pitch = freq * self.organ.stops[pipe.stop].ranks[pipe.rank].ratio(note);
+            let additive = graph.node_mut(&pipe.additive);
+            for harmonic in 0..PARTIALS {
+                additive.set_freq(harmonic, pitch * (harmonic + 1) as f32);
+            }
+            graph.node_mut(&pipe.chiff).set_freq(pitch);
+        }
+    }
+
+    /// A key on a division goes down, every pipe that sounds for it starts to speak
+    pub fn note_on(&mut self, graph: &mut Graph, division: usize) {
+        self.division = division.min(NUM_DIVISIONS - 1);
+        self.held = true;
+        for pipe in self.pipes.iter_mut() {
+            pipe.sounding = false;
+        }
+        self.update(graph);
+    }
+
+    pub fn note_off(&mut self) {
+        self.held = false;
+    }
+
+    // pipes that start sounding while the key is down chiff, like a stop drawn mid note
+    fn update(&mut self, graph: &mut Graph) {
+        let divisions = self.organ.sounding(self.division, &self.couplers);
+        for pipe in self.pipes.iter_mut() {
+            let stop = &self.organ.stops[pipe.stop];
+            let sounding = self.stops[pipe.stop] && divisions[stop.division];
+            if sounding == pipe.sounding {
+                continue;
+            }
+            pipe.sounding = sounding;
+
+            let total: f32 = stop.profile.harmonics.iter().sum();
+            let gain = if sounding { RANK_GAIN / total / (stop.ranks.len() as f32).sqrt() } else { 0.0 };
+            let additive = graph.node_mut(&pipe.additive);
+            for (harmonic, level) in stop.profile.harmonics.iter().enumerate() {
+                additive.set_level(harmonic, level * gain);
+            }
+
+            let chiff = graph.node_mut(&pipe.chiff);
+            chiff.set_level(if sounding { stop.profile.chiff * gain * total } else { 0.0 });
+            if sounding && self.held {
+                chiff.trigger();
+            }
+        }
+    }
+}
@@ -1,9 +1,57 @@
 use std::f32::consts::PI;
-use basic_types::{Output, BLANK_BUFFER, AudioBuffer};
+use std::sync::OnceLock;
+use basic_types::AudioBuffer;
+use basic_types::graph::{Node, Inputs};
+
+// the top bits of the phase pick a table entry, the rest interpolate to the next
+const TABLE_BITS: u32 = 12;
+const TABLE_SIZE: usize = 1 << TABLE_BITS;
+const FRACTION_BITS: u32 = 32 - TABLE_BITS;
+const FRACTION_SCALE: f32 = 1.0 / (1u32 << FRACTION_BITS) as f32;
+
+/// How an oscillator turns its phase into a sine
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SineMethod {
+    /// f32::sin of the phase
+    Sin,
+    /// Linear interpolation in a table, within about 3e-7 of Sin
+    Table
+}
+
+/// One sine cycle plus the first entry again so interpolation never wraps
+pub fn sine_table() -> &'static [f32] {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+            (0..(TABLE_SIZE + 1))
+                .map(|i| ((i as f64 / TABLE_SIZE as f64) * 2.0 * ::std::f64::consts::PI).sin() as f32)
+                .collect()
+        })
+}
+
+/// Interpolated sine of a fixed point phase, a whole cycle is the full range of a u32
+#[inline]
+pub fn table_sine(table: &[f32], pos: u32) -> f32 {
+    let index = (pos >> FRACTION_BITS) as usize;
+    let fraction = (pos & ((1 << FRACTION_BITS) - 1)) as f32 * FRACTION_SCALE;
+    let (a, b) = (table[index], table[index + 1]);
+    a + (b - a) * fraction
+}
+
+/// Fixed point phase increment per sample, None if the frequency can't be played at this rate
+pub fn phase_increment(freq: f32, sample_rate: u32) -> Option<u32> {
+    // stay away from the nyquist limit!
+    if freq > 0.0 && freq < (sample_rate as f32 / 2.1) {
+        Some(((freq * 2_f32.powi(32)) / sample_rate as f32) as u32)
+    }
+    else {
+        None
+    }
+}
 
 struct PhaseIter {
     pos: u32,
     increment: u32,
+    freq: f32,
     sample_rate: u32,
     outscale: f32,
 }
@@ -13,54 +61,93 @@ impl PhaseIter {
         PhaseIter {
             pos: 0,
             increment: 0,
+            freq: 0.0,
             outscale: outscale,
             sample_rate: sample_rate
         }
     }
 
     fn set_freq(&mut self, freq: f32) {
-        // stay away from the nyquist limit!
-        if freq > 0.0 && freq < (self.sample_rate as f32 / 2.1) {
-            self.increment = ((freq * (2 as f32).powi(32)) / self.sample_rate as f32) as u32;
-        }
-        else {
-            self.increment = 0;
-            self.pos = 0;
+        self.freq = freq;
+        match phase_increment(freq, self.sample_rate) {
+            Some(increment) => { self.increment = increment; }
+            None => {
+                self.increment = 0;
+                self.pos = 0;
+            }
         }
     }
+
+    // the increment depends on the rate, keep playing the same frequency at the new one
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        let freq = self.freq;
+        self.set_freq(freq);
+    }
+
+    // the raw fixed point phase, a whole cycle is the full range of a u32
+    fn next_pos(&mut self) -> u32 {
+        let pos = self.pos;
+        self.pos = self.pos.wrapping_add(self.increment);
+        pos
+    }
 }
 
 impl Iterator for PhaseIter {
     type Item = f32;
     fn next(&mut self) -> Option<f32> {
-        let pos = self.pos;
-        self.pos = self.pos.wrapping_add(self.increment);
+        let pos = self.next_pos();
         Some(((pos as f32) * self.outscale) / (2 as f32).powi(32))
     }
 }
 
-pub struct Oscillator<T> where T: Output {
+pub struct Oscillator {
     phase: PhaseIter,
-    output: T
+    method: SineMethod,
+    table: &'static [f32]
 }
 
-impl<T> Oscillator<T> where T: Output {
-    pub fn new(sample_rate: u32, output: T) -> Self {
+impl Oscillator {
+    /// The table, it's the faster of the two
+    pub fn new(sample_rate: u32) -> Self {
+        Oscillator::with_method(sample_rate, SineMethod::Table)
+    }
+
+    pub fn with_method(sample_rate: u32, method: SineMethod) -> Self {
         Oscillator {
             phase: PhaseIter::new(sample_rate, PI * 2.0),
-            output: output
+            method,
+            table: sine_table()
         }
     }
 
     pub fn set_freq(&mut self, freq: f32) {
         self.phase.set_freq(freq);
     }
+}
+
+impl Node for Oscillator {
+    fn num_inputs(&self) -> usize {
+        0
+    }
 
-    pub fn run(&mut self) {
-        let mut samples: AudioBuffer = BLANK_BUFFER;
-        for (sample, phase) in samples.iter_mut().zip(&mut self.phase) {
-            *sample = phase.sin();
+    fn process(&mut self, _inputs: &Inputs, output: &mut AudioBuffer) {
+        match self.method {
+            SineMethod::Sin => {
+                for (sample, phase) in output.iter_mut().zip(&mut self.phase) {
+                    *sample = phase.sin();
+                }
+            }
+            SineMethod::Table => {
+                let table = &self.table[..(TABLE_SIZE + 1)];
+                for sample in output.iter_mut() {
+                    *sample = table_sine(table, self.phase.next_pos());
+                }
+            }
         }
-        self.output.supply_audio(samples);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.phase.set_sample_rate(sample_rate);
     }
 }
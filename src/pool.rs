@@ -0,0 +1,293 @@
+// renders voices in parallel on a pool of worker threads
+// every block the thread calling run wakes the workers, they all take voices off a shared
+// counter until there are none left, then meet at a barrier so the block is mixed straight away
+// a block is short, so threads at a barrier spin a while before sleeping, the others usually
+// arrive sooner than a sleeping thread could be woken, unless they're sharing cores when the
+// spinning only holds up the threads being waited for
+
+use basic_types::{BLANK_BUFFER, AudioBuffer};
+use basic_types::graph::{Node, Inputs};
+use voice::{Voice, VoiceError};
+use tremulant::{Tremulant, Modulation, Modulations};
+use controls::NUM_DIVISIONS;
+use stats::EngineStats;
+
+use std::hint;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+// checks of the barrier before sleeping on it, tens of microseconds
+const SPINS: usize = 4_000;
+
+// a voice and whether it rendered anything this block
+struct VoiceJob {
+    voice: Voice,
+    active: bool,
+    // the voice panicked or its graph failed, it's silent from then on rather than taking
+    // its thread down and leaving the others waiting at the barrier
+    failed: bool
+}
+
+impl VoiceJob {
+    fn render(&mut self, tremulants: &Modulations, stats: &EngineStats) {
+        if self.failed {
+            self.active = false;
+            return;
+        }
+        let voice = &mut self.voice;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                voice.set_tremulants(tremulants);
+                voice.run()
+            }));
+        self.active = match result {
+            Ok(Ok(active)) => active,
+            // a voice whose channels have gone is left silent, Multi is being dropped
+            Ok(Err(VoiceError::Disconnected)) => false,
+            Ok(Err(VoiceError::Graph(e))) => {
+                self.failed = true;
+                stats.record_error(format!("voice graph: {}", e));
+                false
+            }
+            Err(_) => {
+                self.failed = true;
+                stats.record_error("a voice panicked".to_string());
+                false
+            }
+        };
+    }
+}
+
+// a lock that outlives a panic while it was held, everything behind these locks is
+// still usable (a failed voice is just left silent)
+fn lock<'a, T>(mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+struct SpinBarrier {
+    threads: usize,
+    spins: usize,
+    arrived: AtomicUsize,
+    // goes up each time everyone has arrived
+    generation: AtomicUsize,
+    sleepers: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar
+}
+
+impl SpinBarrier {
+    fn new(threads: usize) -> Self {
+        let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        SpinBarrier {
+            threads,
+            spins: if threads <= cores { SPINS } else { 0 },
+            arrived: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+            sleepers: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new()
+        }
+    }
+
+    fn wait(&self) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        if self.arrived.fetch_add(1, Ordering::SeqCst) + 1 == self.threads {
+            // last in, nobody can arrive for the next round until the generation moves on
+            self.arrived.store(0, Ordering::SeqCst);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            // a sleeper counted itself before checking the generation, so it either saw it
+            // move or gets woken, the lock keeps it from missing the wake up in between
+            if self.sleepers.load(Ordering::SeqCst) > 0 {
+                let _sleep = lock(&self.sleep);
+                self.wake.notify_all();
+            }
+            return;
+        }
+
+        for _ in 0..self.spins {
+            if self.generation.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            hint::spin_loop();
+        }
+        let mut sleep = lock(&self.sleep);
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        while self.generation.load(Ordering::SeqCst) == generation {
+            sleep = self.wake.wait(sleep).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// what the workers share with the thread running the pool
+struct Shared {
+    jobs: Vec<Mutex<VoiceJob>>,
+    next: AtomicUsize,
+    // set before the workers are woken
+    tremulants: Mutex<Modulations>,
+    barrier: SpinBarrier,
+    stopping: AtomicBool,
+    // how long each thread spent on voices this block, the thread calling run is 0
+    busy_ns: Vec<AtomicU64>,
+    stats: Arc<EngineStats>
+}
+
+impl Shared {
+    // take voices until they're all claimed
+    fn render_voices(&self, thread: usize) {
+        let start = Instant::now();
+        let tremulants = *lock(&self.tremulants);
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            match self.jobs.get(index) {
+                Some(job) => { lock(job).render(&tremulants, &self.stats); }
+                None => { break; }
+            }
+        }
+        let busy = start.elapsed();
+        self.busy_ns[thread].store(busy.as_secs() * 1_000_000_000 + busy.subsec_nanos() as u64, Ordering::Relaxed);
+    }
+
+    // 0.0 when every thread was as busy as the busiest, towards 1.0 when one did it all
+    fn imbalance(&self) -> f32 {
+        let busy: Vec<u64> = self.busy_ns.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let max = busy.iter().cloned().max().unwrap_or(0);
+        if max == 0 {
+            return 0.0;
+        }
+        let mean = busy.iter().sum::<u64>() as f32 / busy.len() as f32;
+        1.0 - mean / max as f32
+    }
+}
+
+pub struct VoicePool {
+    shared: Arc<Shared>,
+    workers: Vec<thread::JoinHandle<()>>,
+    tremulants: Vec<Tremulant>,
+    gain: f32,
+    stats: Arc<EngineStats>
+}
+
+impl VoicePool {
+    /// Threads is the total rendering, the thread calling process is one of them
+    /// Each voice goes into the mix at the given gain
+    pub fn new(voices: Vec<Voice>, num_threads: usize, gain: f32, sample_rate: u32, stats: Arc<EngineStats>) -> Self {
+
+        let jobs = voices.into_iter()
+            .map(|voice| {
+                    Mutex::new(VoiceJob {
+                        voice,
+                        active: false,
+                        failed: false
+                    })
+                })
+            .collect();
+
+        let num_workers = num_threads.max(1) - 1;
+        let shared = Arc::new(Shared {
+            jobs,
+            next: AtomicUsize::new(0),
+            tremulants: Mutex::new([Modulation::none(); NUM_DIVISIONS]),
+            barrier: SpinBarrier::new(num_workers + 1),
+            stopping: AtomicBool::new(false),
+            busy_ns: (0..(num_workers + 1)).map(|_| AtomicU64::new(0)).collect(),
+            stats: stats.clone()
+        });
+
+        let workers = (1..(num_workers + 1))
+            .map(|thread| {
+                    let shared = shared.clone();
+                    thread::spawn(move || {
+                            loop {
+                                // start of a block
+                                shared.barrier.wait();
+                                if shared.stopping.load(Ordering::Relaxed) {
+                                    return;
+                                }
+                                shared.render_voices(thread);
+                                // end of the block
+                                shared.barrier.wait();
+                            }
+                        })
+                })
+            .collect();
+
+        VoicePool {
+            shared,
+            workers,
+            tremulants: (0..NUM_DIVISIONS).map(|_| Tremulant::new(sample_rate)).collect(),
+            gain,
+            stats
+        }
+    }
+
+    /// A division's tremulant
+    pub fn tremulant(&mut self, division: usize) -> Option<&mut Tremulant> {
+        self.tremulants.get_mut(division)
+    }
+
+    /// Finish the workers, process only gives silence after this
+    pub fn stop(&mut self) {
+        if self.shared.stopping.load(Ordering::Relaxed) {
+            return;
+        }
+        // wake the workers for a block that never happens
+        self.shared.stopping.store(true, Ordering::Relaxed);
+        self.shared.barrier.wait();
+        for worker in self.workers.drain(..) {
+            // a worker that died anyway has nothing to hand back, stopping carries on
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Node for VoicePool {
+    fn num_inputs(&self) -> usize {
+        0
+    }
+
+    fn process(&mut self, _inputs: &Inputs, output: &mut AudioBuffer) {
+        *output = BLANK_BUFFER;
+        if self.shared.stopping.load(Ordering::Relaxed) {
+            return;
+        }
+
+        {
+            let mut tremulants = lock(&self.shared.tremulants);
+            for (modulation, tremulant) in tremulants.iter_mut().zip(self.tremulants.iter_mut()) {
+                *modulation = tremulant.next_block(output.len());
+            }
+        }
+        self.shared.next.store(0, Ordering::Relaxed);
+        self.shared.barrier.wait();
+        self.shared.render_voices(0);
+        self.shared.barrier.wait();
+
+        // everyone's finished, mix what the voices rendered
+        let mut active = 0;
+        for job in self.shared.jobs.iter() {
+            let job = lock(job);
+            if job.active {
+                active += 1;
+                for (sample, in_sample) in output.iter_mut().zip(job.voice.output().iter()) {
+                    *sample += *in_sample * self.gain;
+                }
+            }
+        }
+        self.stats.record_voices(active, self.shared.imbalance());
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        for tremulant in self.tremulants.iter_mut() {
+            tremulant.set_sample_rate(sample_rate);
+        }
+    }
+}
+
+impl Drop for VoicePool {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,194 @@
+// scala scale (.scl) and keyboard mapping (.kbm) files
+// see http://www.huygens-fokker.org/scala/scl_format.html and help.htm#mappings
+
+use std::path::Path;
+
+use tuning::NoteTable;
+use text_file;
+
+#[derive(Debug)]
+pub struct Scale {
+    pub description: String,
+    // cents for degrees 1 to n, the last one is the period (usually the octave)
+    degrees: Vec<f64>
+}
+
+#[derive(Debug)]
+pub struct KeyboardMap {
+    size: usize,
+    first_note: i32,
+    last_note: i32,
+    middle_note: i32,
+    reference_note: i32,
+    reference_freq: f64,
+    // scale degrees from one repeat of the mapping to the next, 0 for the scale's own period
+    octave_degree: i32,
+    // scale degree for each key in the pattern, None for unmapped keys
+    mapping: Vec<Option<i32>>
+}
+
+// the lines that aren't comments, scala comments start with !
+fn content_lines(text: &str) -> Vec<&str> {
+    text.lines()
+        .filter(|l| !l.starts_with('!'))
+        .collect()
+}
+
+// first whitespace separated word of a line
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+// a pitch is in cents if it has a '.' in it, otherwise it's a ratio or a whole number
+fn parse_pitch(line: &str) -> Result<f64, String> {
+    let word = first_word(line);
+    let bad = || format!("bad pitch \"{}\"", line.trim());
+
+    if word.contains('.') {
+        return word.parse::<f64>().map_err(|_| bad());
+    }
+
+    let mut parts = word.splitn(2, '/');
+    let num = parts.next().unwrap_or("").parse::<f64>().map_err(|_| bad())?;
+    let den = match parts.next() {
+        Some(d) => d.parse::<f64>().map_err(|_| bad())?,
+        None => 1.0
+    };
+    if num <= 0.0 || den <= 0.0 {
+        return Err(bad());
+    }
+    Ok(1200.0 * (num / den).log2())
+}
+
+fn parse_int(line: Option<&&str>, what: &str) -> Result<i32, String> {
+    line.and_then(|l| first_word(l).parse::<i32>().ok())
+        .ok_or(format!("missing or bad {}", what))
+}
+
+impl Scale {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        text_file::load(path, Self::parse)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let lines = content_lines(text);
+        let description = lines.first().map(|l| l.trim().to_string()).unwrap_or_default();
+        let count = parse_int(lines.get(1), "note count")?;
+        if count < 0 || lines.len() < 2 + count as usize {
+            return Err("not enough notes".to_string());
+        }
+
+        let mut degrees = Vec::new();
+        for line in lines[2..(2 + count as usize)].iter() {
+            degrees.push(parse_pitch(line)?);
+        }
+
+        Ok(Scale {
+            description,
+            degrees
+        })
+    }
+
+    // cents above the root for any scale degree, including ones in other periods
+    fn cents(&self, degree: i32) -> f64 {
+        let len = self.degrees.len() as i32;
+        if len == 0 {
+            return 0.0;
+        }
+        let period = self.degrees[(len - 1) as usize];
+        let periods = (degree as f64 / len as f64).floor() as i32;
+        let index = degree - periods * len;
+        let within = if index == 0 { 0.0 } else { self.degrees[(index - 1) as usize] };
+        periods as f64 * period + within
+    }
+}
+
+impl KeyboardMap {
+    /// The mapping scala uses when there's no .kbm file, the scale laid out from middle C
+    /// with A above it at 440Hz
+    pub fn linear(scale: &Scale) -> Self {
+        KeyboardMap {
+            size: 0,
+            first_note: 0,
+            last_note: 127,
+            middle_note: 60,
+            reference_note: 69,
+            reference_freq: 440.0,
+            octave_degree: scale.degrees.len() as i32,
+            mapping: Vec::new()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        text_file::load(path, Self::parse)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let lines = content_lines(text);
+
+        let size = parse_int(lines.first(), "map size")?;
+        let reference_freq = lines.get(5)
+            .and_then(|l| first_word(l).parse::<f64>().ok())
+            .ok_or("missing or bad reference frequency".to_string())?;
+        if size < 0 || lines.len() < 7 + size as usize {
+            return Err("not enough keys in mapping".to_string());
+        }
+
+        let mut mapping = Vec::new();
+        for line in lines[7..(7 + size as usize)].iter() {
+            let word = first_word(line);
+            if word == "x" {
+                mapping.push(None);
+            }
+            else {
+                mapping.push(Some(word.parse::<i32>()
+                    .map_err(|_| format!("bad mapping \"{}\"", line.trim()))?));
+            }
+        }
+
+        Ok(KeyboardMap {
+            size: size as usize,
+            first_note: parse_int(lines.get(1), "first note")?,
+            last_note: parse_int(lines.get(2), "last note")?,
+            middle_note: parse_int(lines.get(3), "middle note")?,
+            reference_note: parse_int(lines.get(4), "reference note")?,
+            reference_freq,
+            octave_degree: parse_int(lines.get(6), "formal octave")?,
+            mapping
+        })
+    }
+
+    // the scale degree played by a key, if it plays anything
+    fn degree(&self, note: i32, scale: &Scale) -> Option<i32> {
+        let offset = note - self.middle_note;
+        if self.size == 0 {
+            return Some(offset);
+        }
+        let size = self.size as i32;
+        let octaves = (offset as f64 / size as f64).floor() as i32;
+        let index = offset - octaves * size;
+        let octave_degree = if self.octave_degree == 0 { scale.degrees.len() as i32 } else { self.octave_degree };
+        self.mapping[index as usize].map(|d| d + octaves * octave_degree)
+    }
+}
+
+/// Frequencies for all midi notes, unmapped notes get 0Hz and stay silent
+pub fn note_table(scale: &Scale, map: &KeyboardMap) -> Result<NoteTable, String> {
+    let reference_cents = match map.degree(map.reference_note, scale) {
+        Some(degree) => scale.cents(degree),
+        None => { return Err("reference note isn't mapped".to_string()); }
+    };
+
+    let mut table = [0.0; 128];
+    for (note, freq) in table.iter_mut().enumerate() {
+        let note = note as i32;
+        if note < map.first_note || note > map.last_note {
+            continue;
+        }
+        if let Some(degree) = map.degree(note, scale) {
+            let cents = scale.cents(degree) - reference_cents;
+            *freq = (map.reference_freq * 2.0_f64.powf(cents / 1200.0)) as f32;
+        }
+    }
+    Ok(table)
+}
@@ -0,0 +1,47 @@
+// asking organn to stop, from a signal, the terminal or anywhere else
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+use libc;
+
+// how often wait looks to see whether it's time to stop
+const POLL_MS: u64 = 50;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+pub fn request() {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+pub fn requested() -> bool {
+    STOP.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    // only an atomic store, which is fine in a signal handler
+    request();
+}
+
+/// Make SIGINT and SIGTERM ask for a stop rather than killing the process
+#[cfg(unix)]
+pub fn handle_signals() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn handle_signals() {}
+
+/// Block until a stop is asked for
+pub fn wait() {
+    while !requested() {
+        thread::sleep(Duration::from_millis(POLL_MS));
+    }
+}
@@ -0,0 +1,158 @@
+// how hard the engine is working, written by the audio and worker threads and read by anyone
+// everything except the xrun count is since the last report was taken
+
+use basic_types::BUFFER_SIZE;
+
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// imbalance is kept as parts per million so it fits in an integer
+const PPM: f64 = 1_000_000.0;
+
+pub struct EngineStats {
+    // real time available for each block
+    budget_ns: AtomicU64,
+    blocks: AtomicU64,
+    render_ns: AtomicU64,
+    max_render_ns: AtomicU64,
+    active_voices: AtomicU64,
+    max_active_voices: AtomicU64,
+    imbalance_ppm: AtomicU64,
+    xruns: AtomicU64,
+    errors: AtomicU64,
+    // the first error not yet reported, the audio threads never wait for it
+    error: Mutex<Option<String>>
+}
+
+fn block_budget_ns(sample_rate: u32) -> u64 {
+    (BUFFER_SIZE as u64 * 1_000_000_000) / sample_rate.max(1) as u64
+}
+
+fn nanos(time: Duration) -> u64 {
+    time.as_secs() * 1_000_000_000 + time.subsec_nanos() as u64
+}
+
+impl EngineStats {
+    pub fn new(sample_rate: u32) -> Self {
+        EngineStats {
+            budget_ns: AtomicU64::new(block_budget_ns(sample_rate)),
+            blocks: AtomicU64::new(0),
+            render_ns: AtomicU64::new(0),
+            max_render_ns: AtomicU64::new(0),
+            active_voices: AtomicU64::new(0),
+            max_active_voices: AtomicU64::new(0),
+            imbalance_ppm: AtomicU64::new(0),
+            xruns: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            error: Mutex::new(None)
+        }
+    }
+
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        self.budget_ns.store(block_budget_ns(sample_rate), Ordering::Relaxed);
+    }
+
+    /// A block took this long to render
+    pub fn record_block(&self, time: Duration) {
+        let ns = nanos(time);
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        self.render_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_render_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    /// Voices sounding in a block, and how unevenly the threads were loaded from 0.0
+    /// (all equally busy) towards 1.0 (one thread did everything)
+    pub fn record_voices(&self, active: usize, imbalance: f32) {
+        self.active_voices.fetch_add(active as u64, Ordering::Relaxed);
+        self.max_active_voices.fetch_max(active as u64, Ordering::Relaxed);
+        self.imbalance_ppm.fetch_add((imbalance.clamp(0.0, 1.0) as f64 * PPM) as u64, Ordering::Relaxed);
+    }
+
+    /// The output went without audio, from the engine being late or the device
+    pub fn record_xrun(&self) {
+        self.xruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Total xruns since starting
+    pub fn xruns(&self) -> u64 {
+        self.xruns.load(Ordering::Relaxed)
+    }
+
+    /// Something stopped rendering, a voice or the engine itself. Whatever failed should only
+    /// record it once, it's left to another thread to report
+    pub fn record_error(&self, error: String) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut waiting) = self.error.try_lock() {
+            if waiting.is_none() {
+                *waiting = Some(error);
+            }
+        }
+    }
+
+    /// Total errors since starting
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// The oldest error not yet reported, any others while it waited were only counted
+    pub fn take_error(&self) -> Option<String> {
+        self.error.lock().ok().and_then(|mut waiting| waiting.take())
+    }
+
+    /// Everything since the last call, and start again
+    pub fn take_report(&self) -> StatsReport {
+        let blocks = self.blocks.swap(0, Ordering::Relaxed);
+        let render_ns = self.render_ns.swap(0, Ordering::Relaxed);
+        let max_render_ns = self.max_render_ns.swap(0, Ordering::Relaxed);
+        let active_voices = self.active_voices.swap(0, Ordering::Relaxed);
+        let max_active_voices = self.max_active_voices.swap(0, Ordering::Relaxed);
+        let imbalance_ppm = self.imbalance_ppm.swap(0, Ordering::Relaxed);
+        let budget_ns = self.budget_ns.load(Ordering::Relaxed) as f64;
+
+        let per_block = |total: u64| if blocks > 0 { total as f64 / blocks as f64 } else { 0.0 };
+        let mean_render_ns = per_block(render_ns);
+
+        StatsReport {
+            blocks,
+            mean_render_us: mean_render_ns / 1000.0,
+            max_render_us: max_render_ns as f64 / 1000.0,
+            load: 100.0 * mean_render_ns / budget_ns,
+            peak_load: 100.0 * max_render_ns as f64 / budget_ns,
+            mean_active_voices: per_block(active_voices),
+            max_active_voices: max_active_voices as usize,
+            imbalance: 100.0 * per_block(imbalance_ppm) / PPM,
+            xruns: self.xruns(),
+            errors: self.errors()
+        }
+    }
+}
+
+/// A summary of some stretch of blocks
+#[derive(Clone, Debug)]
+pub struct StatsReport {
+    pub blocks: u64,
+    pub mean_render_us: f64,
+    pub max_render_us: f64,
+    /// Mean render time as a percentage of the real time there is for a block
+    pub load: f64,
+    /// The slowest block as a percentage, over 100 means it was late
+    pub peak_load: f64,
+    pub mean_active_voices: f64,
+    pub max_active_voices: usize,
+    /// Mean worker imbalance as a percentage, 0 is perfectly even
+    pub imbalance: f64,
+    /// Total since starting
+    pub xruns: u64,
+    /// Total since starting
+    pub errors: u64
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "load {:.1}% (peak {:.1}%), render {:.1}us (max {:.1}us), voices {:.1} (max {}), imbalance {:.0}%, xruns {}, errors {}",
+            self.load, self.peak_load, self.mean_render_us, self.max_render_us,
+            self.mean_active_voices, self.max_active_voices, self.imbalance, self.xruns, self.errors)
+    }
+}
@@ -0,0 +1,166 @@
+// post mix expression (swell) pedal and master volume, plus the fade out when stopping
+// both are smoothed per sample so moving a pedal or fader doesn't zip
+
+use basic_types::AudioBuffer;
+use basic_types::graph::{Node, Inputs};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// an organ swell pedal closed is quiet rather than silent
+const EXPRESSION_FLOOR_DB: f32 = -30.0;
+// >1 puts more of the travel near the top, like a real pedal
+const EXPRESSION_CURVE: f32 = 1.5;
+
+const SMOOTHING_MS: f32 = 10.0;
+
+// long enough to not click, short enough that stopping doesn't feel slow
+const FADE_OUT_MS: f32 = 50.0;
+
+// tone mode, how much bass comes back as the pedal closes and where "bass" starts
+const TONE_BASS_BOOST: f32 = 1.5;
+const TONE_CUTOFF_HZ: f32 = 250.0;
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+// one pole coefficient for a given time constant or cutoff
+fn time_coeff(time_ms: f32, sample_rate: u32) -> f32 {
+    (-1000.0 / (time_ms * sample_rate as f32)).exp()
+}
+
+fn cutoff_coeff(freq: f32, sample_rate: u32) -> f32 {
+    (-2.0 * ::std::f32::consts::PI * freq / sample_rate as f32).exp()
+}
+
+fn fade_step(sample_rate: u32) -> f32 {
+    1000.0 / (FADE_OUT_MS * sample_rate as f32)
+}
+
+/// Fading out for shutdown, started from any thread and finished on the audio thread
+pub struct FadeOut {
+    started: AtomicBool,
+    done: AtomicBool
+}
+
+impl FadeOut {
+    fn new() -> Self {
+        FadeOut {
+            started: AtomicBool::new(false),
+            done: AtomicBool::new(false)
+        }
+    }
+
+    pub fn start(&self) {
+        self.started.store(true, Ordering::Relaxed);
+    }
+
+    /// The output is silent, it's safe to stop the audio without a click
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Swell {
+    // targets, 0.0 to 1.0 pedal/fader positions
+    expression: f32,
+    volume: f32,
+
+    // smoothed values actually applied
+    gain: f32,
+    bass: f32,
+    smoothing: f32,
+
+    // low end emphasis when the pedal is closed
+    tone: bool,
+    lowpass: f32,
+    lowpass_coeff: f32,
+
+    // linear fade to silence at the very end
+    fade: f32,
+    fade_step: f32,
+    fade_out: Arc<FadeOut>
+}
+
+impl Swell {
+    pub fn new(sample_rate: u32) -> Self {
+        Swell {
+            expression: 1.0,
+            volume: 1.0,
+            gain: 1.0,
+            bass: 0.0,
+            smoothing: time_coeff(SMOOTHING_MS, sample_rate),
+            tone: false,
+            lowpass: 0.0,
+            lowpass_coeff: cutoff_coeff(TONE_CUTOFF_HZ, sample_rate),
+            fade: 1.0,
+            fade_step: fade_step(sample_rate),
+            fade_out: Arc::new(FadeOut::new())
+        }
+    }
+
+    pub fn fade_out(&self) -> Arc<FadeOut> {
+        self.fade_out.clone()
+    }
+
+    pub fn set_expression(&mut self, value: f32) {
+        self.expression = value.clamp(0.0, 1.0);
+    }
+
+    pub fn set_volume(&mut self, value: f32) {
+        self.volume = value.clamp(0.0, 1.0);
+    }
+
+    /// Make the expression pedal change tone too, keeping the bass up as it closes
+    pub fn set_tone(&mut self, tone: bool) {
+        self.tone = tone;
+    }
+
+    fn expression_gain(&self) -> f32 {
+        if self.expression >= 1.0 {
+            1.0
+        }
+        else {
+            db_to_gain(EXPRESSION_FLOOR_DB * (1.0 - self.expression).powf(EXPRESSION_CURVE))
+        }
+    }
+
+    // midi style square law volume
+    fn volume_gain(&self) -> f32 {
+        self.volume * self.volume
+    }
+
+}
+
+impl Node for Swell {
+    fn num_inputs(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer) {
+        let target_gain = self.expression_gain() * self.volume_gain();
+        let target_bass = if self.tone { TONE_BASS_BOOST * (1.0 - self.expression) } else { 0.0 };
+
+        let fade_step = if self.fade_out.started.load(Ordering::Relaxed) { self.fade_step } else { 0.0 };
+
+        for (sample, in_sample) in output.iter_mut().zip(inputs.get(0).iter()) {
+            self.gain = target_gain + (self.gain - target_gain) * self.smoothing;
+            self.bass = target_bass + (self.bass - target_bass) * self.smoothing;
+            self.fade = (self.fade - fade_step).max(0.0);
+
+            self.lowpass = *in_sample + (self.lowpass - *in_sample) * self.lowpass_coeff;
+            *sample = (*in_sample + self.lowpass * self.bass) * self.gain * self.fade;
+        }
+
+        if self.fade <= 0.0 {
+            self.fade_out.done.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.smoothing = time_coeff(SMOOTHING_MS, sample_rate);
+        self.lowpass_coeff = cutoff_coeff(TONE_CUTOFF_HZ, sample_rate);
+        self.fade_step = fade_step(sample_rate);
+    }
+}
@@ -0,0 +1,14 @@
+// the text files organn reads its settings from, cc maps, scala tunings and organs
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Read a whole file and parse it, errors from either start with the file's path
+pub fn load<T, F>(path: &Path, parse: F) -> Result<T, String> where F: FnOnce(&str) -> Result<T, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
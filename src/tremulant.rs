@@ -0,0 +1,167 @@
+// tremulant, an lfo shaking the pitch and level of a division together the way a pipe organ's
+// tremulant shakes its wind, or deeper for a theatre organ's
+// there's one per division, run a block at a time by the voice pool so every voice in a
+// division moves together, and each voice follows its own division's
+
+use basic_types::AudioBuffer;
+use basic_types::graph::{Node, Inputs};
+use controls::NUM_DIVISIONS;
+
+use std::f32::consts::PI;
+
+pub const MIN_RATE: f32 = 1.0;
+pub const MAX_RATE: f32 = 12.0;
+const DEFAULT_RATE: f32 = 6.0;
+const DEFAULT_DEPTH: f32 = 0.5;
+
+// swing at full depth, a theatre organ's tremulant rather than a church's
+const MAX_GAIN_SWING: f32 = 0.3;
+const MAX_PITCH_CENTS: f32 = 30.0;
+
+// switching on or off eases the depth rather than jumping
+const EASE_MS: f32 = 100.0;
+
+/// What a tremulant does to its division's voices for a block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Modulation {
+    pub gain: f32,
+    /// Ratio to the voice's frequencies
+    pub pitch: f32
+}
+
+impl Modulation {
+    pub fn none() -> Self {
+        Modulation {
+            gain: 1.0,
+            pitch: 1.0
+        }
+    }
+}
+
+/// One for each division
+pub type Modulations = [Modulation; NUM_DIVISIONS];
+
+pub struct Tremulant {
+    on: bool,
+    rate: f32,
+    depth: f32,
+    // depth being used, eases to depth when on and 0 when off
+    level: f32,
+    // fraction of a cycle
+    phase: f32,
+    sample_rate: u32
+}
+
+impl Tremulant {
+    /// Off, at a middling rate and depth
+    pub fn new(sample_rate: u32) -> Self {
+        Tremulant {
+            on: false,
+            rate: DEFAULT_RATE,
+            depth: DEFAULT_DEPTH,
+            level: 0.0,
+            phase: 0.0,
+            sample_rate
+        }
+    }
+
+    pub fn set_on(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// Cycles a second, clamped to MIN_RATE to MAX_RATE
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate.clamp(MIN_RATE, MAX_RATE);
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// 0.0 to 1.0
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// The modulation for the next block of samples, then move on a block
+    pub fn next_block(&mut self, samples: usize) -> Modulation {
+        let seconds = samples as f32 / self.sample_rate.max(1) as f32;
+        let target = if self.on { self.depth } else { 0.0 };
+        let step = seconds * 1000.0 / EASE_MS;
+        self.level = if self.level < target { (self.level + step).min(target) } else { (self.level - step).max(target) };
+
+        let modulation = if self.level == 0.0 {
+            Modulation::none()
+        }
+        else {
+            let swing = (2.0 * PI * self.phase).sin() * self.level;
+            Modulation {
+                gain: 1.0 + swing * MAX_GAIN_SWING,
+                pitch: 2.0_f32.powf(swing * MAX_PITCH_CENTS / 1200.0)
+            }
+        };
+
+        self.phase = (self.phase + self.rate * seconds).fract();
+        modulation
+    }
+}
+
+/// A voice's level under its tremulant, moving to each new gain across a block so it
+/// doesn't zip
+pub struct Tremolo {
+    gain: f32,
+    target: f32
+}
+
+impl Tremolo {
+    pub fn new() -> Self {
+        Tremolo {
+            gain: 1.0,
+            target: 1.0
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.target = gain;
+    }
+}
+
+impl Default for Tremolo {
+    fn default() -> Self {
+        Tremolo::new()
+    }
+}
+
+impl Node for Tremolo {
+    fn num_inputs(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer) {
+        let input = inputs.get(0);
+        if self.gain == self.target {
+            for (out, sample) in output.iter_mut().zip(input.iter()) {
+                *out = *sample * self.gain;
+            }
+            return;
+        }
+
+        let step = (self.target - self.gain) / output.len() as f32;
+        for (i, (out, sample)) in output.iter_mut().zip(input.iter()).enumerate() {
+            *out = *sample * (self.gain + step * (i + 1) as f32);
+        }
+        self.gain = self.target;
+    }
+}
@@ -0,0 +1,307 @@
+// note to frequency mapping with pitch bend, master tuning and transposition
+// kept in one place so every voice (and anything else pitched) agrees
+
+use midi;
+
+use std::sync::Arc;
+
+/// Limits for the master tuning reference, covers baroque to high modern pitch
+pub const MIN_REFERENCE: f32 = 400.0;
+pub const MAX_REFERENCE: f32 = 480.0;
+/// Limits for transposition, the range of coarse tuning (RPN 2)
+pub const MIN_TRANSPOSE: i32 = -64;
+pub const MAX_TRANSPOSE: i32 = 63;
+// the a4 tables are made at, the master tuning reference moves them from here
+const STANDARD_PITCH: f32 = 440.0;
+
+const DEFAULT_BEND_RANGE: f32 = 2.0;
+const MAX_BEND_RANGE: f32 = 24.0;
+const BEND_CENTRE: f32 = 8192.0;
+
+// the null rpn, data entry is ignored while this is selected
+const RPN_NULL: (midi::U7, midi::U7) = (127, 127);
+
+const NUM_PROGRAMS: usize = 128;
+
+// midi tuning standard sysex ids
+const SYSEX_NON_REALTIME: u8 = 0x7E;
+const SYSEX_REALTIME: u8 = 0x7F;
+const MIDI_TUNING: u8 = 0x08;
+const BULK_DUMP: u8 = 0x01;
+const NOTE_CHANGE: u8 = 0x02;
+const BANK_NOTE_CHANGE: u8 = 0x07;
+
+/// A frequency for every midi note, with a4 at 440Hz however it's actually tuned
+pub type NoteTable = [f32; 128];
+
+#[derive(Clone)]
+pub struct Tuning {
+    reference: f32,     // frequency of A4 (midi note 69) in Hz
+    transpose: i32,     // semitones
+    bend_range: f32,    // semitones either way
+    bend: f32,          // -1.0 to 1.0
+    fine: f32,          // semitones either way, from rpn 1
+    table: Option<Arc<NoteTable>>   // replaces equal temperament when set
+}
+
+impl Tuning {
+    /// The reference and transposition are taken as they are, check ones from outside
+    /// with set_reference and set_transpose
+    pub fn new(reference: f32, transpose: i32) -> Self {
+        Tuning {
+            reference,
+            transpose,
+            bend_range: DEFAULT_BEND_RANGE,
+            bend: 0.0,
+            fine: 0.0,
+            table: None
+        }
+    }
+
+    /// Set the frequency of A4, an error outside MIN_REFERENCE to MAX_REFERENCE
+    pub fn set_reference(&mut self, reference: f32) -> Result<(), String> {
+        if !(MIN_REFERENCE..=MAX_REFERENCE).contains(&reference) {
+            return Err(format!("master tuning has to be from {}Hz to {}Hz, not {}Hz", MIN_REFERENCE, MAX_REFERENCE, reference));
+        }
+        self.reference = reference;
+        Ok(())
+    }
+
+    pub fn reference(&self) -> f32 {
+        self.reference
+    }
+
+    /// Set the pitch bend from a 14 bit midi value, 8192 is the centre
+    pub fn set_bend(&mut self, value: midi::U14) {
+        self.bend = ((value as f32 - BEND_CENTRE) / BEND_CENTRE).max(-1.0);
+    }
+
+    pub fn set_bend_range(&mut self, semitones: f32) {
+        self.bend_range = semitones.clamp(0.0, MAX_BEND_RANGE);
+    }
+
+    /// An error outside MIN_TRANSPOSE to MAX_TRANSPOSE
+    pub fn set_transpose(&mut self, semitones: i32) -> Result<(), String> {
+        if !(MIN_TRANSPOSE..=MAX_TRANSPOSE).contains(&semitones) {
+            return Err(format!("transposition has to be from {} to {} semitones, not {}", MIN_TRANSPOSE, MAX_TRANSPOSE, semitones));
+        }
+        self.transpose = semitones;
+        Ok(())
+    }
+
+    /// Fine tuning from a 14 bit midi value, 8192 is in tune and the ends are a semitone
+    /// either way
+    pub fn set_fine_tune(&mut self, value: midi::U14) {
+        self.fine = ((value as f32 - BEND_CENTRE) / BEND_CENTRE).max(-1.0);
+    }
+
+    /// Frequency in Hz to play for a midi note
+    pub fn note_to_hz(&self, note: midi::U7) -> f32 {
+        match self.table {
+            Some(ref table) => {
+                let index = (note as i32 + self.transpose).clamp(0, 127);
+                let reference = self.reference / STANDARD_PITCH;
+                table[index as usize] * reference * 2.0_f32.powf((self.bend * self.bend_range + self.fine) / 12.0)
+            }
+            None => {
+                let step = note as f32 + self.transpose as f32 + (self.bend * self.bend_range) + self.fine - 69.0;
+                self.reference * 2.0_f32.powf(step / 12.0)
+            }
+        }
+    }
+}
+
+// equal temperament as a table, the starting point for retuning single notes
+fn equal_table() -> NoteTable {
+    let mut table = [0.0; 128];
+    for (note, freq) in table.iter_mut().enumerate() {
+        *freq = STANDARD_PITCH * 2.0_f32.powf((note as f32 - 69.0) / 12.0);
+    }
+    table
+}
+
+// frequency from a midi tuning standard note, semitone plus 14 bit fraction
+// 7F 7F 7F means leave the note alone
+fn mts_freq(bytes: &[u8]) -> Option<f32> {
+    if bytes[0] == 0x7F && bytes[1] == 0x7F && bytes[2] == 0x7F {
+        return None;
+    }
+    let fraction = (((bytes[1] as u32) << 7) | bytes[2] as u32) as f32 / 16384.0;
+    Some(STANDARD_PITCH * 2.0_f32.powf((bytes[0] as f32 + fraction - 69.0) / 12.0))
+}
+
+/// Tuning programs, selected with RPN 3 and loaded or changed with midi tuning standard sysex
+/// an empty program is equal temperament
+pub struct TuningBank {
+    programs: Vec<Option<Arc<NoteTable>>>,
+    current: usize
+}
+
+impl Default for TuningBank {
+    fn default() -> Self {
+        TuningBank::new()
+    }
+}
+
+impl TuningBank {
+    pub fn new() -> Self {
+        TuningBank {
+            programs: vec![None; NUM_PROGRAMS],
+            current: 0
+        }
+    }
+
+    pub fn set_program(&mut self, program: usize, table: Option<NoteTable>) {
+        if program < NUM_PROGRAMS {
+            self.programs[program] = table.map(Arc::new);
+        }
+    }
+
+    /// Switch to a tuning program
+    pub fn select(&mut self, tuning: &mut Tuning, program: usize) {
+        if program < NUM_PROGRAMS {
+            self.current = program;
+            tuning.table = self.programs[program].clone();
+        }
+    }
+
+    // change some notes in a program, starting from equal temperament if it was empty
+    fn retune_notes(&mut self, program: usize, changes: &[u8]) {
+        if program >= NUM_PROGRAMS {
+            return;
+        }
+        let mut table = match self.programs[program] {
+            Some(ref table) => **table,
+            None => equal_table()
+        };
+        for change in changes.chunks(4).filter(|c| c.len() == 4) {
+            if let (Some(note), Some(freq)) = (table.get_mut(change[0] as usize), mts_freq(&change[1..])) {
+                *note = freq;
+            }
+        }
+        self.programs[program] = Some(Arc::new(table));
+    }
+
+    /// Handle a system exclusive message, sysex that isn't midi tuning standard is ignored
+    /// returns true if the current tuning changed
+    pub fn sysex(&mut self, tuning: &mut Tuning, data: &[u8]) -> bool {
+        if data.len() < 5 || data[2] != MIDI_TUNING {
+            return false;
+        }
+
+        let program = match (data[0], data[3]) {
+            (SYSEX_NON_REALTIME, BULK_DUMP) if data.len() >= 405 && (data[4] as usize) < NUM_PROGRAMS => {
+                // program, 16 byte name, 128 notes, the checksum is ignored
+                let mut table = equal_table();
+                for (freq, bytes) in table.iter_mut().zip(data[21..405].chunks(3)) {
+                    if let Some(f) = mts_freq(bytes) {
+                        *freq = f;
+                    }
+                }
+                self.programs[data[4] as usize] = Some(Arc::new(table));
+                data[4] as usize
+            }
+            (SYSEX_REALTIME, NOTE_CHANGE) if data.len() >= 6 => {
+                self.retune_notes(data[4] as usize, &data[6..]);
+                data[4] as usize
+            }
+            (SYSEX_NON_REALTIME, BANK_NOTE_CHANGE) | (SYSEX_REALTIME, BANK_NOTE_CHANGE) if data.len() >= 7 => {
+                // only one bank here
+                self.retune_notes(data[5] as usize, &data[7..]);
+                data[5] as usize
+            }
+            _ => { return false; }
+        };
+
+        if program == self.current {
+            let current = self.current;
+            self.select(tuning, current);
+            true
+        }
+        else {
+            false
+        }
+    }
+}
+
+// registered parameter number state, tracks which rpn data entry applies to
+// a selection is used up by its data entry msb (and an lsb straight after it) so data entry
+// is only taken while a sequence is under way, the rest of the time cc 6 is free for a drawbar
+pub struct Rpn {
+    selected: (midi::U7, midi::U7),
+    // the rpn the last data entry msb went to, for an lsb following it
+    entered: (midi::U7, midi::U7),
+    // fine tuning's msb, its lsb is added to it
+    fine_msb: midi::U7
+}
+
+impl Default for Rpn {
+    fn default() -> Self {
+        Rpn::new()
+    }
+}
+
+impl Rpn {
+    pub fn new() -> Self {
+        Rpn {
+            selected: RPN_NULL,
+            entered: RPN_NULL,
+            fine_msb: 64
+        }
+    }
+
+    /// Handle a control change that may be part of an rpn sequence
+    /// returns true if the control was used up here and shouldn't go anywhere else
+    pub fn control(&mut self, tuning: &mut Tuning, bank: &mut TuningBank, control: midi::U7, value: midi::U7) -> bool {
+        // anything but the lsb ends a sequence
+        let entered = self.entered;
+        if control != 38 {
+            self.entered = RPN_NULL;
+        }
+
+        match control {
+            101 => { self.selected.0 = value; true }
+            100 => { self.selected.1 = value; true }
+
+            // nrpn selection deselects any rpn
+            98 | 99 => { self.selected = RPN_NULL; true }
+
+            // data entry msb
+            6 if self.selected != RPN_NULL => {
+                self.entered = self.selected;
+                self.selected = RPN_NULL;
+                match self.entered {
+                    // pitch bend sensitivity in semitones
+                    (0, 0) => { tuning.set_bend_range(value as f32); }
+                    // fine tuning, 64 is in tune
+                    (0, 1) => {
+                        self.fine_msb = value.min(127);
+                        tuning.set_fine_tune((self.fine_msb as midi::U14) << 7);
+                    }
+                    // coarse tuning, 64 is no transposition, always in range
+                    (0, 2) => { tuning.set_transpose(value.min(127) as i32 - 64).ok(); }
+                    // tuning program select
+                    (0, 3) => { bank.select(tuning, value as usize); }
+                    _ => {}
+                }
+                true
+            }
+
+            // data entry lsb, cents for pitch bend sensitivity or the low bits of fine tuning,
+            // before or after the msb
+            38 if self.selected != RPN_NULL || entered != RPN_NULL => {
+                self.entered = RPN_NULL;
+                if self.selected == (0, 0) || entered == (0, 0) {
+                    let semitones = tuning.bend_range.trunc();
+                    tuning.set_bend_range(semitones + (value.min(99) as f32 / 100.0));
+                }
+                else if self.selected == (0, 1) || entered == (0, 1) {
+                    tuning.set_fine_tune(((self.fine_msb as midi::U14) << 7) | value.min(127) as midi::U14);
+                }
+                true
+            }
+
+            _ => { false }
+        }
+    }
+}
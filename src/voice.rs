@@ -1,149 +1,254 @@
-
-use pitch_calc::Step;
-
-use basic_types::{unthreaded_connection, Output};
-use oscillator::Oscillator;
-use mixer::Mixer;
+use basic_types::AudioBuffer;
+use basic_types::graph::{Graph, GraphError, Handle};
+use additive::{Additive, PARTIALS};
 use env::Env;
+use organ::{Organ, Pipes};
+use tremulant::{Tremolo, Modulation, Modulations};
+use tuning::Tuning;
+use controls::{self, Registration, NUM_DIVISIONS};
 use midi::{self, Message};
 
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
+
+// each drawbar can reach 1/9 so a voice with everything pulled out peaks at 1.0
+static MIX_MAX: f32 = 1.0 / 9.0;
+
+// each drawbar's pitch relative to the note, 16' up to 1'
+const HARMONICS: [f32; PARTIALS] = [0.5, 1.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0];
+
+/// Messages sent to a voice from the midi thread
+pub enum VoiceMessage {
+    Midi(midi::Message),
+    Tuning(Tuning),
+    /// Drawbar number and level from 0.0 to 1.0
+    Drawbar(usize, f32),
+    /// Pipe organ stop number and whether it's drawn
+    Stop(usize, bool),
+    Coupler(usize, bool),
+    /// The output changed rate, from the audio thread rather than midi
+    SampleRate(u32)
+}
 
-static MIX_MAX: f32 = 0.25;
+/// Why a voice couldn't render
+#[derive(Debug)]
+pub enum VoiceError {
+    /// Its messages have stopped coming, the Multi is being dropped
+    Disconnected,
+    /// Its graph wouldn't run
+    Graph(GraphError)
+}
 
-pub struct Voice<T> where T: Output {
-    oscillators: Vec<Oscillator<unthreaded_connection::UnthreadedOutput>>,
-    mixer: Mixer<unthreaded_connection::UnthreadedInput, unthreaded_connection::UnthreadedOutput>,
-    env: Env<unthreaded_connection::UnthreadedInput, T>,
-    pitch: midi::U7,
-    midi_input: mpsc::Receiver<midi::Message>
+// what makes the sound, everything after it is the same
+enum Model {
+    Drawbars(Handle<Additive>),
+    Pipes(Pipes)
 }
 
-impl<T> Voice<T> where T: Output {
-    pub fn new(sample_rate: u32, midi_in: mpsc::Receiver<midi::Message>, voice_output: T) -> Self {
-        // create the parts of the signal chain
-        let mut oscillators = Vec::new();
-        let mut osc_connections = Vec::new();
+pub struct Voice {
+    graph: Graph,
+    model: Model,
+    env: Handle<Env>,
+    tremolo: Handle<Tremolo>,
+    pitch: midi::U7,
+    division: usize,
+    tuning: Tuning,
+    // every division's tremulant, and what this voice's is doing to it now
+    tremulants: Modulations,
+    modulation: Modulation,
+    midi_input: mpsc::Receiver<VoiceMessage>
+}
 
-        for _ in (0..9) {
-            let (output, input) = unthreaded_connection::new();
-            let osc = Oscillator::new(sample_rate, output);
+impl Voice {
+    /// A tonewheel organ's voice, drawbars set to a registration
+    pub fn new(sample_rate: u32, tuning: Tuning, registration: &Registration, midi_in: mpsc::Receiver<VoiceMessage>) -> Self {
+        // create the parts of the signal chain, every drawbar's partial is rendered and
+        // mixed in one go
+        let mut partials = Additive::new(sample_rate);
 
-            oscillators.push(osc);
-            osc_connections.push(input);
+        for (i, level) in registration.iter().enumerate() {
+            partials.set_level(i, level * MIX_MAX);
         }
 
-        let num_oscs = osc_connections.len();
-        let (mix_output, env_input) = unthreaded_connection::new();
-        let mut mixer = Mixer::new(osc_connections, vec![0.0; num_oscs], mix_output);
+        let mut graph = Graph::new();
+        let partials = graph.add("partials", partials);
+        Voice::with_model(graph, &partials, Model::Drawbars(partials), sample_rate, tuning, midi_in)
+    }
 
-        mixer.set_level(0, 1.0 * MIX_MAX);
-        mixer.set_level(1, 0.6 * MIX_MAX);
-        mixer.set_level(2, 0.1 * MIX_MAX);
-        mixer.set_level(3, 0.4 * MIX_MAX);
-        mixer.set_level(4, 0.1 * MIX_MAX);
-        mixer.set_level(5, 0.4 * MIX_MAX);
-        mixer.set_level(6, 0.1 * MIX_MAX);
-        mixer.set_level(7, 0.1 * MIX_MAX);
-        mixer.set_level(8, 0.1 * MIX_MAX);
+    /// A pipe organ's voice, with the organ's stops and couplers as they start
+    pub fn pipes(sample_rate: u32, tuning: Tuning, organ: Arc<Organ>, midi_in: mpsc::Receiver<VoiceMessage>) -> Self {
+        let mut graph = Graph::new();
+        let pipes = Pipes::new(organ, &mut graph, sample_rate);
+        let output = pipes.output();
+        Voice::with_model(graph, &output, Model::Pipes(pipes), sample_rate, tuning, midi_in)
+    }
 
-        let env = Env::new(env_input, voice_output, 20, sample_rate);
+    fn with_model<N>(mut graph: Graph, source: &Handle<N>, model: Model, sample_rate: u32, tuning: Tuning, midi_in: mpsc::Receiver<VoiceMessage>) -> Self {
+        let env = graph.add("env", Env::new(20, sample_rate));
+        let tremolo = graph.add("tremolo", Tremolo::new());
+        graph.connect(source, &env).unwrap();
+        graph.connect(&env, &tremolo).unwrap();
+        graph.set_output(&tremolo).unwrap();
 
         Voice {
-            oscillators: oscillators,
-            mixer: mixer,
+            graph,
+            model,
             env: env,
+            tremolo,
             pitch: 0,
+            division: 0,
+            tuning,
+            tremulants: [Modulation::none(); NUM_DIVISIONS],
+            modulation: Modulation::none(),
             midi_input: midi_in
         }
     }
 
-    fn set_pitch(&mut self, pitch: f32) {
-        let freq = Step(pitch).to_hz().hz();
-        self.oscillators[0].set_freq((freq * 1.0 )  / 2.0);
-        self.oscillators[1].set_freq((freq * 3.0)  / 2.0);
-        self.oscillators[2].set_freq(freq * 1.0);
-        self.oscillators[3].set_freq(freq * 2.0);
-        self.oscillators[4].set_freq(freq * 3.0);
-        self.oscillators[5].set_freq(freq * 4.0);
-        self.oscillators[6].set_freq(freq * 5.0);
-        self.oscillators[7].set_freq(freq * 6.0);
-        self.oscillators[8].set_freq(freq * 8.0);
-    }
-
-    // convert a midi value to a mix level
-    // reversed to resemble drawbars
-    fn midi_to_mix_level(value: midi::U7) -> f32 {
-        ((127 - value) as f32 * MIX_MAX) / 127.0
-    }
-
-    fn midi_control(&mut self, control: midi::U7, value: midi::U7) {
-        // map some midi controls to the mix
-        match control {
-            2  => { self.mixer.set_level(0, Self::midi_to_mix_level(value)); }
-            3  => { self.mixer.set_level(1, Self::midi_to_mix_level(value)); }
-            4  => { self.mixer.set_level(2, Self::midi_to_mix_level(value)); }
-            5  => { self.mixer.set_level(3, Self::midi_to_mix_level(value)); }
-            6  => { self.mixer.set_level(4, Self::midi_to_mix_level(value)); }
-            8  => { self.mixer.set_level(5, Self::midi_to_mix_level(value)); }
-            9  => { self.mixer.set_level(6, Self::midi_to_mix_level(value)); }
-            12 => { self.mixer.set_level(7, Self::midi_to_mix_level(value)); }
-            13 => { self.mixer.set_level(8, Self::midi_to_mix_level(value)); }
-            _ => {}
+    fn set_pitch(&mut self, pitch: midi::U7) {
+        let freq = self.tuning.note_to_hz(pitch) * self.modulation.pitch;
+        match self.model {
+            Model::Drawbars(ref partials) => {
+                let partials = self.graph.node_mut(partials);
+                for (partial, harmonic) in HARMONICS.iter().enumerate() {
+                    partials.set_freq(partial, freq * harmonic);
+                }
+            }
+            Model::Pipes(ref mut pipes) => {
+                pipes.set_pitch(&mut self.graph, pitch, freq);
+            }
         }
+    }
+
+    fn set_drawbar(&mut self, drawbar: usize, level: f32) {
+        if let Model::Drawbars(ref partials) = self.model {
+            if drawbar < PARTIALS {
+                self.graph.node_mut(partials).set_level(drawbar, level.clamp(0.0, 1.0) * MIX_MAX);
+            }
+        }
+    }
+
+    fn set_stop(&mut self, stop: usize, on: bool) {
+        if let Model::Pipes(ref mut pipes) = self.model {
+            pipes.set_stop(&mut self.graph, stop, on);
+        }
+    }
+
+    fn set_coupler(&mut self, coupler: usize, on: bool) {
+        if let Model::Pipes(ref mut pipes) = self.model {
+            pipes.set_coupler(&mut self.graph, coupler, on);
+        }
+    }
 
+    fn release(&mut self) {
+        self.graph.node_mut(&self.env).note_off();
+        if let Model::Pipes(ref mut pipes) = self.model {
+            pipes.note_off();
+        }
     }
 
     fn midi_message(&mut self, message: &Message) {
         match *message {
-            Message::NoteOn(_, pitch, _) => {
-                self.set_pitch(pitch as f32);
-                self.env.note_on();
+            Message::NoteOn(channel, pitch, _) => {
+                self.division = controls::division(channel);
+                self.set_pitch(pitch);
+                if let Model::Pipes(ref mut pipes) = self.model {
+                    pipes.note_on(&mut self.graph, self.division);
+                }
+                self.graph.node_mut(&self.env).note_on();
 
                 self.pitch = pitch;
             }
 
             Message::NoteOff(_, pitch, _) if (pitch == self.pitch) => {
-                self.env.note_off();
+                self.release();
             }
 
             Message::AllNotesOff(_) => {
-                self.env.note_off();
-            }
-
-            Message::ControlChange(_, control, value) => {
-                self.midi_control(control, value);
+                self.release();
             }
 
             _ => { }
         }
     }
 
-    pub fn run(&mut self) -> Result<(), ()>{
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.graph.set_sample_rate(sample_rate);
+    }
+
+    fn set_tuning(&mut self, tuning: Tuning) {
+        // retune whatever is playing, or still releasing
+        self.tuning = tuning;
+        let pitch = self.pitch;
+        self.set_pitch(pitch);
+    }
+
+    /// The division of the last note played
+    pub fn division(&self) -> usize {
+        self.division
+    }
+
+    /// What each division's tremulant does this block, the voice follows its own division's
+    pub fn set_tremulants(&mut self, tremulants: &Modulations) {
+        self.tremulants = *tremulants;
+    }
+
+    fn follow_tremulant(&mut self) {
+        let modulation = self.tremulants[self.division];
+        if modulation != self.modulation {
+            let retune = modulation.pitch != self.modulation.pitch;
+            self.modulation = modulation;
+            if retune {
+                let pitch = self.pitch;
+                self.set_pitch(pitch);
+            }
+            self.graph.node_mut(&self.tremolo).set_gain(modulation.gain);
+        }
+    }
+
+    /// Handle messages then render a block, Ok(false) if the voice is silent and rendered nothing
+    pub fn run(&mut self) -> Result<bool, VoiceError> {
         // process messages for this voice
         loop {
             let message = self.midi_input.try_recv();
             match message {
-                Ok(midi_message) => {
+                Ok(VoiceMessage::Midi(midi_message)) => {
                     self.midi_message(&midi_message);
                 }
+                Ok(VoiceMessage::Tuning(tuning)) => {
+                    self.set_tuning(tuning);
+                }
+                Ok(VoiceMessage::Drawbar(drawbar, level)) => {
+                    self.set_drawbar(drawbar, level);
+                }
+                Ok(VoiceMessage::Stop(stop, on)) => {
+                    self.set_stop(stop, on);
+                }
+                Ok(VoiceMessage::Coupler(coupler, on)) => {
+                    self.set_coupler(coupler, on);
+                }
+                Ok(VoiceMessage::SampleRate(sample_rate)) => {
+                    self.set_sample_rate(sample_rate);
+                }
                 Err(mpsc::TryRecvError::Empty) => {
                     break;
                 }
                 Err(mpsc::TryRecvError::Disconnected) => {
                     // get out of here
-                    return Err(());
+                    return Err(VoiceError::Disconnected);
                 }
             }
         }
 
-        // just run every part of the audio chain in order
-        for osc in self.oscillators.iter_mut() {
-            osc.run();
+        // a voice that's released all the way doesn't need rendering
+        if self.graph.node(&self.env).is_idle() {
+            return Ok(false);
         }
-        self.mixer.run();
-        self.env.run();
 
-        Ok(())
+        self.follow_tremulant();
+        self.graph.run().map(|_| true).map_err(VoiceError::Graph)
+    }
+
+    /// The block rendered by the last run that returned Ok(true)
+    pub fn output(&self) -> &AudioBuffer {
+        self.graph.output()
     }
 }
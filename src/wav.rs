@@ -0,0 +1,174 @@
+// minimal wav files, written as 32 bit float and read from float or integer pcm
+
+use std::fs::File;
+use std::io::{self, Read, Write, Seek, SeekFrom, BufWriter};
+use std::path::Path;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+const HEADER_LEN: u32 = 44;
+// the riff length is 32 bits, that's as much sample data as there's room for after the header
+const MAX_SAMPLES: u32 = (u32::MAX - (HEADER_LEN - 8)) / 4;
+
+fn write_u16(out: &mut dyn Write, value: u16) -> io::Result<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+fn write_u32(out: &mut dyn Write, value: u32) -> io::Result<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+pub struct WavWriter {
+    file: BufWriter<File>,
+    sample_rate: u32,
+    channels: u16,
+    samples: u32
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut writer = WavWriter {
+            file: BufWriter::new(File::create(path)?),
+            sample_rate,
+            channels,
+            samples: 0
+        };
+        // sizes get filled in properly by finish
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let data_len = self.samples * 4;
+        let block_align = self.channels * 4;
+        let out = &mut self.file;
+
+        out.write_all(b"RIFF")?;
+        write_u32(out, HEADER_LEN - 8 + data_len)?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        write_u32(out, 16)?;
+        write_u16(out, WAVE_FORMAT_IEEE_FLOAT)?;
+        write_u16(out, self.channels)?;
+        write_u32(out, self.sample_rate)?;
+        write_u32(out, self.sample_rate * block_align as u32)?;
+        write_u16(out, block_align)?;
+        write_u16(out, 32)?;
+
+        out.write_all(b"data")?;
+        write_u32(out, data_len)
+    }
+
+    /// Write interleaved samples, an error without writing any once the file is full
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let total = match self.samples.checked_add(samples.len() as u32) {
+            Some(total) if samples.len() <= MAX_SAMPLES as usize && total <= MAX_SAMPLES => total,
+            _ => { return Err(io::Error::other("the wav file is full, it can only hold 4GB")); }
+        };
+        for sample in samples {
+            write_u32(&mut self.file, sample.to_bits())?;
+        }
+        self.samples = total;
+        Ok(())
+    }
+
+    /// Fill in the header now the length is known
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()
+    }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+fn bad_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// The contents of a wav file, samples are interleaved and scaled to -1.0 to 1.0
+pub struct WavData {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>
+}
+
+impl WavData {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Self::parse(&bytes)
+    }
+
+    /// 16, 24 and 32 bit integer or 32 bit float, other chunks are skipped
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(bad_data("not a wav file"));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..(pos + 4)];
+            let len = read_u32(&bytes[(pos + 4)..]) as usize;
+            let start = pos + 8;
+            let end = (start + len).min(bytes.len());
+            if id == b"fmt " && end - start >= 16 {
+                format = Some(&bytes[start..end]);
+            }
+            else if id == b"data" {
+                data = Some(&bytes[start..end]);
+            }
+            // chunks are padded to an even length
+            pos = start + len + (len & 1);
+        }
+
+        let format = format.ok_or(bad_data("no fmt chunk"))?;
+        let data = data.ok_or(bad_data("no data chunk"))?;
+
+        let mut tag = read_u16(&format[0..]);
+        let channels = read_u16(&format[2..]);
+        let sample_rate = read_u32(&format[4..]);
+        let bits = read_u16(&format[14..]);
+        if tag == WAVE_FORMAT_EXTENSIBLE && format.len() >= 26 {
+            // the real format is the start of the sub format guid
+            tag = read_u16(&format[24..]);
+        }
+        if channels == 0 {
+            return Err(bad_data("no channels"));
+        }
+
+        let samples = match (tag, bits) {
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => {
+                data.chunks(4).filter(|b| b.len() == 4).map(|b| f32::from_bits(read_u32(b))).collect()
+            }
+            (WAVE_FORMAT_PCM, 16) => {
+                data.chunks(2).filter(|b| b.len() == 2).map(|b| read_u16(b) as i16 as f32 / 32768.0).collect()
+            }
+            (WAVE_FORMAT_PCM, 24) => {
+                data.chunks(3).filter(|b| b.len() == 3)
+                    .map(|b| ((read_u32(&[0, b[0], b[1], b[2]]) as i32) >> 8) as f32 / 8388608.0)
+                    .collect()
+            }
+            (WAVE_FORMAT_PCM, 32) => {
+                data.chunks(4).filter(|b| b.len() == 4).map(|b| read_u32(b) as i32 as f32 / 2147483648.0).collect()
+            }
+            _ => { return Err(bad_data(&format!("unsupported format {} with {} bits", tag, bits))); }
+        };
+
+        Ok(WavData {
+            sample_rate,
+            channels,
+            samples
+        })
+    }
+}
@@ -0,0 +1,124 @@
+// the fused additive renderer against the oscillator and mixer chain it replaces in Voice
+
+extern crate organn;
+
+#[path = "common/doubles.rs"]
+mod doubles;
+
+use organn::additive::{self, Additive, AdditiveMethod, PARTIALS};
+use organn::basic_types::graph::Graph;
+use doubles::Recorder;
+use organn::controls::DEFAULT_REGISTRATION;
+use organn::mixer::Mixer;
+use organn::oscillator::{Oscillator, SineMethod};
+
+// nine partials each within a couple of 1e-6 of sin, at a ninth of full scale
+const MAX_ERROR: f32 = 2e-6;
+const BLOCKS: usize = 1_000;
+const HARMONICS: [f32; PARTIALS] = [0.5, 1.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0];
+
+fn levels() -> Vec<f32> {
+    DEFAULT_REGISTRATION.iter().map(|l| l / 9.0).collect()
+}
+
+// partway through the drawbars change, and later the sample rate
+fn render_chain(sample_rate: u32, freq: f32) -> Vec<f32> {
+    let mut graph = Graph::new();
+    let mixer = graph.add("mixer", Mixer::new(levels()));
+    for harmonic in HARMONICS.iter() {
+        let mut osc = Oscillator::with_method(sample_rate, SineMethod::Sin);
+        osc.set_freq(freq * harmonic);
+        let osc = graph.add("oscillator", osc);
+        graph.connect(&osc, &mixer).unwrap();
+    }
+    let recorder = graph.add("recorder", Recorder::new());
+    graph.connect(&mixer, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+
+    for block in 0..BLOCKS {
+        if block == BLOCKS / 3 {
+            graph.node_mut(&mixer).set_level(0, 0.0);
+            graph.node_mut(&mixer).set_level(8, 1.0 / 9.0);
+        }
+        if block == 2 * BLOCKS / 3 {
+            graph.set_sample_rate(sample_rate * 2);
+        }
+        graph.run().unwrap();
+    }
+    graph.node(&recorder).samples().to_vec()
+}
+
+fn render_additive(method: AdditiveMethod, sample_rate: u32, freq: f32) -> Vec<f32> {
+    let mut partials = Additive::with_method(sample_rate, method);
+    for (partial, (harmonic, level)) in HARMONICS.iter().zip(levels()).enumerate() {
+        partials.set_freq(partial, freq * harmonic);
+        partials.set_level(partial, level);
+    }
+    let mut graph = Graph::new();
+    let partials = graph.add("partials", partials);
+    let recorder = graph.add("recorder", Recorder::new());
+    graph.connect(&partials, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+
+    for block in 0..BLOCKS {
+        if block == BLOCKS / 3 {
+            graph.node_mut(&partials).set_level(0, 0.0);
+            graph.node_mut(&partials).set_level(8, 1.0 / 9.0);
+        }
+        if block == 2 * BLOCKS / 3 {
+            graph.set_sample_rate(sample_rate * 2);
+        }
+        graph.run().unwrap();
+    }
+    graph.node(&recorder).samples().to_vec()
+}
+
+fn check(method: AdditiveMethod) {
+    for &sample_rate in [22_050, 44_100, 48_000].iter() {
+        // low, middle, and high enough for the top partials to go past nyquist
+        for &freq in [32.7, 261.63, 440.0, 2093.0].iter() {
+            let chain = render_chain(sample_rate, freq);
+            let fused = render_additive(method, sample_rate, freq);
+            assert_eq!(chain.len(), fused.len());
+
+            let error = chain.iter().zip(fused.iter()).map(|(c, f)| (c - f).abs()).fold(0.0, f32::max);
+            assert!(error < MAX_ERROR, "{:?} {}Hz at {}Hz: error {}", method, freq, sample_rate, error);
+        }
+    }
+}
+
+#[test]
+fn scalar_sin_matches_chain() {
+    check(AdditiveMethod::Scalar(SineMethod::Sin));
+}
+
+#[test]
+fn scalar_table_matches_chain() {
+    check(AdditiveMethod::Scalar(SineMethod::Table));
+}
+
+#[test]
+fn simd_matches_chain() {
+    // falls back to the table where there's no simd, still worth checking
+    check(AdditiveMethod::Simd);
+}
+
+#[test]
+fn simd_used_where_available() {
+    let partials = Additive::new(44_100);
+    if additive::simd_available() {
+        assert_eq!(partials.method(), AdditiveMethod::Simd);
+    }
+}
+
+#[test]
+fn silent_drawbars_add_nothing() {
+    let mut partials = Additive::new(44_100);
+    for partial in 0..PARTIALS {
+        partials.set_freq(partial, 440.0);
+    }
+    let mut graph = Graph::new();
+    let partials = graph.add("partials", partials);
+    graph.set_output(&partials).unwrap();
+    assert!(graph.run().unwrap().iter().all(|s| *s == 0.0));
+}
@@ -0,0 +1,147 @@
+// the renderer backends pull audio from, with and without lookahead
+
+extern crate midi;
+extern crate organn;
+
+use organn::audio::{self, Renderer};
+use organn::basic_types::BUFFER_SIZE;
+use organn::multi::{Multi, MultiConfig, MultiMidiConn};
+use organn::tuning::{Tuning, TuningBank};
+
+use std::thread;
+use std::time::Duration;
+
+const SAMPLE_RATE: u32 = 44_100;
+
+fn multi() -> (Multi, MultiMidiConn) {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_voices = 4;
+    config.num_threads = 1;
+    Multi::new(config, Tuning::new(440.0, 0), TuningBank::new())
+}
+
+fn note_on(conn: &mut MultiMidiConn) {
+    let channel = midi::utils::from_status_byte(0x90).1;
+    conn.midi_message(&midi::Message::NoteOn(channel, 69, 100));
+}
+
+#[test]
+fn without_lookahead_the_engine_renders_in_the_callback() {
+    let (mut reference, mut reference_conn) = multi();
+    let (engine, mut conn) = multi();
+    note_on(&mut reference_conn);
+    note_on(&mut conn);
+    let mut renderer = Renderer::new(engine, 0);
+
+    // callbacks that don't line up with blocks
+    let mut samples = Vec::new();
+    for size in [5, 16, 100, 1, 250].iter().cycle().take(40) {
+        let mut out = vec![1.0; *size];
+        renderer.render(&mut out);
+        samples.extend_from_slice(&out);
+    }
+
+    let mut expected = Vec::new();
+    while expected.len() < samples.len() {
+        reference.run();
+        expected.extend_from_slice(reference.output());
+    }
+    assert!(samples.iter().any(|s| *s != 0.0));
+    assert_eq!(samples[..], expected[..samples.len()]);
+    assert_eq!(renderer.stats().take_report().xruns, 0);
+}
+
+#[test]
+fn interleaved_channels_all_get_the_same() {
+    let (engine, mut conn) = multi();
+    note_on(&mut conn);
+    let mut renderer = Renderer::new(engine, 0);
+    let mut out = vec![0.0; BUFFER_SIZE * 30];
+    renderer.render_interleaved(&mut out, 3);
+    assert!(out.iter().any(|s| *s != 0.0));
+    for frame in out.chunks(3) {
+        assert!(frame[1] == frame[0] && frame[2] == frame[0]);
+    }
+}
+
+#[test]
+fn an_underrun_is_one_xrun_per_callback() {
+    let (engine, _conn) = multi();
+    let mut renderer = Renderer::new(engine, 2);
+    let stats = renderer.stats();
+
+    // far more blocks than can be rendered ahead, short of many of them but only one xrun
+    let mut out = vec![0.0; BUFFER_SIZE * 4096];
+    renderer.render(&mut out);
+    assert_eq!(stats.xruns(), 1);
+
+    // however the callbacks are split, no more than one each
+    for _ in 0..10 {
+        renderer.render_interleaved(&mut out, 2);
+    }
+    assert!(stats.xruns() >= 2 && stats.xruns() <= 11, "{}", stats.xruns());
+}
+
+#[test]
+fn lookahead_catches_up() {
+    let (engine, mut conn) = multi();
+    note_on(&mut conn);
+    let mut renderer = Renderer::new(engine, 4);
+    let stats = renderer.stats();
+
+    // given time the render thread fills the queue and a callback that fits in it never runs short
+    let mut out = vec![0.0; BUFFER_SIZE * 4];
+    let mut sounded = false;
+    for _ in 0..50 {
+        thread::sleep(Duration::from_millis(20));
+        let before = stats.xruns();
+        renderer.render(&mut out);
+        assert_eq!(stats.xruns(), before);
+        sounded |= out.iter().any(|s| *s != 0.0);
+    }
+    assert!(sounded);
+}
+
+#[test]
+fn midi_after_the_engine_has_gone_goes_nowhere() {
+    let (engine, mut conn) = multi();
+    // as a backend stopping does
+    drop(Renderer::new(engine, 2));
+
+    let channel = midi::utils::from_status_byte(0x90).1;
+    for message in [
+            midi::Message::NoteOn(channel, 60, 100),
+            midi::Message::NoteOff(channel, 60, 0),
+            midi::Message::AllNotesOff(channel),
+            midi::Message::PitchBend(channel, 0),
+            // a drawbar and the volume
+            midi::Message::ControlChange(channel, 2, 64),
+            midi::Message::ControlChange(channel, 7, 64)
+        ].iter() {
+        conn.midi_message(message);
+    }
+}
+
+// /dev/full takes the header but fails as soon as the samples are flushed
+#[cfg(target_os = "linux")]
+#[test]
+fn a_wav_file_that_cant_be_written_stops_the_clock() {
+    let (engine, _conn) = multi();
+    let mut backend = audio::open("wav", Some("/dev/full"), Some(SAMPLE_RATE), 256).unwrap();
+    let renderer = Renderer::new(engine, 0);
+    let stats = renderer.stats();
+    backend.start(renderer).unwrap();
+
+    for _ in 0..100 {
+        if stats.errors() > 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(stats.errors(), 1);
+    assert!(stats.take_error().unwrap().starts_with("/dev/full: "));
+    let error = backend.stop().unwrap_err();
+    assert!(error.starts_with("/dev/full: "), "{}", error);
+    // stopped already
+    assert_eq!(backend.stop(), Ok(()));
+}
@@ -0,0 +1,94 @@
+// stand ins for either end of a chain, for driving parts of it on their own
+// shared by the tests, benches and fuzz targets with #[path], each using some of it
+
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use organn::basic_types::{AudioBuffer, BLANK_BUFFER, BUFFER_SIZE};
+use organn::basic_types::graph::{Node, Inputs};
+
+/// Gives the same block every time
+pub struct Constant {
+    buffer: AudioBuffer
+}
+
+impl Constant {
+    pub fn new(buffer: AudioBuffer) -> Self {
+        Constant {
+            buffer
+        }
+    }
+}
+
+impl Node for Constant {
+    fn num_inputs(&self) -> usize {
+        0
+    }
+
+    fn process(&mut self, _inputs: &Inputs, output: &mut AudioBuffer) {
+        *output = self.buffer;
+    }
+}
+
+/// Plays back blocks given up front, then silence
+pub struct Scripted {
+    buffers: VecDeque<AudioBuffer>
+}
+
+impl Scripted {
+    pub fn new(buffers: Vec<AudioBuffer>) -> Self {
+        Scripted {
+            buffers: buffers.into_iter().collect()
+        }
+    }
+}
+
+impl Node for Scripted {
+    fn num_inputs(&self) -> usize {
+        0
+    }
+
+    fn process(&mut self, _inputs: &Inputs, output: &mut AudioBuffer) {
+        *output = self.buffers.pop_front().unwrap_or(BLANK_BUFFER);
+    }
+}
+
+/// Passes its input through and keeps everything
+pub struct Recorder {
+    samples: Vec<f32>
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder::new()
+    }
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder {
+            samples: Vec::new()
+        }
+    }
+
+    /// Everything recorded so far
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// How many blocks have gone through
+    pub fn blocks(&self) -> usize {
+        self.samples.len() / BUFFER_SIZE
+    }
+}
+
+impl Node for Recorder {
+    fn num_inputs(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, inputs: &Inputs, output: &mut AudioBuffer) {
+        *output = *inputs.get(0);
+        self.samples.extend_from_slice(output);
+    }
+}
@@ -0,0 +1,359 @@
+// golden audio, fixed midi scripts rendered through a Voice or the whole Multi and compared
+// with reference renders kept in the repo by tests/golden.rs
+// the comparison is of envelopes and spectra with some tolerance, so floating point differences
+// between platforms pass but a change in the sound doesn't
+
+use organn::basic_types::BLANK_BUFFER;
+use organn::voice::{Voice, VoiceMessage};
+use organn::multi::{Multi, MultiConfig};
+use organn::controls::{Control, CcMap, DEFAULT_REGISTRATION};
+use organn::tuning::{Tuning, TuningBank};
+use organn::effects::EffectType;
+use organn::organ::Organ;
+use midi;
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::{mpsc, Arc};
+
+/// Low to keep the reference files small, a block is 1ms
+pub const SAMPLE_RATE: u32 = 16_000;
+
+// 10ms
+const ENVELOPE_WINDOW: usize = 160;
+const ENVELOPE_TOLERANCE: f32 = 0.002;
+const ENVELOPE_RELATIVE_TOLERANCE: f32 = 0.02;
+
+// 64ms windows split into 250Hz bands
+const SPECTRUM_WINDOW: usize = 1024;
+const SPECTRUM_BANDS: usize = 32;
+const SPECTRUM_TOLERANCE_DB: f32 = 1.0;
+// quieter than this counts as nothing
+const SPECTRUM_FLOOR_DB: f32 = -70.0;
+
+// mismatches listed of each kind, the worst first
+const MAX_REPORTED: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Action {
+    NoteOn(midi::U7),
+    NoteOff(midi::U7),
+    Control(midi::U7, midi::U7),
+    PitchBend(midi::U14)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Target {
+    /// A single voice driven directly, controllers go through the default cc map
+    Voice,
+    /// The whole engine, with these effects after the swell pedal, playing the built in pipe
+    /// organ rather than drawbars if organ is set
+    Multi { voices: usize, threads: usize, effects: &'static [EffectType], organ: bool }
+}
+
+pub struct Script {
+    pub name: &'static str,
+    pub target: Target,
+    pub blocks: usize,
+    /// Block number and what happens at the start of it
+    pub actions: Vec<(usize, Action)>
+}
+
+/// Every script there's a reference render for
+pub fn scripts() -> Vec<Script> {
+    vec![
+        // attack, sustain and release of one note
+        Script {
+            name: "voice_note",
+            target: Target::Voice,
+            blocks: 700,
+            actions: vec![(0, Action::NoteOn(69)), (500, Action::NoteOff(69))]
+        },
+        // drawbars moving under a held note change the spectrum
+        Script {
+            name: "voice_drawbars",
+            target: Target::Voice,
+            blocks: 600,
+            actions: vec![
+                (0, Action::NoteOn(57)),
+                (200, Action::Control(2, 127)),
+                (300, Action::Control(13, 0)),
+                (500, Action::NoteOff(57))
+            ]
+        },
+        // a chord through the whole engine with the swell pedal, pitch bend and volume
+        Script {
+            name: "multi_chord",
+            target: Target::Multi { voices: 8, threads: 2, effects: &[], organ: false },
+            blocks: 650,
+            actions: vec![
+                (0, Action::NoteOn(60)),
+                (0, Action::NoteOn(64)),
+                (0, Action::NoteOn(67)),
+                (200, Action::Control(11, 64)),
+                (300, Action::PitchBend(0x3000)),
+                (350, Action::Control(7, 100)),
+                (500, Action::NoteOff(60)),
+                (500, Action::NoteOff(64)),
+                (500, Action::NoteOff(67))
+            ]
+        },
+        // a third note with only two voices steals one, the stolen note's off does nothing
+        Script {
+            name: "multi_steal",
+            target: Target::Multi { voices: 2, threads: 1, effects: &[], organ: false },
+            blocks: 600,
+            actions: vec![
+                (0, Action::NoteOn(60)),
+                (100, Action::NoteOn(64)),
+                (200, Action::NoteOn(67)),
+                (300, Action::NoteOff(60)),
+                (350, Action::NoteOff(64)),
+                (450, Action::NoteOff(67))
+            ]
+        },
+        // short notes into the spring reverb, mostly its tail
+        Script {
+            name: "multi_spring",
+            target: Target::Multi { voices: 4, threads: 1, effects: &[EffectType::SpringReverb], organ: false },
+            blocks: 1_000,
+            actions: vec![
+                (0, Action::NoteOn(48)),
+                (60, Action::NoteOff(48)),
+                (300, Action::NoteOn(67)),
+                (320, Action::NoteOff(67))
+            ]
+        },
+        // the built in organ's upper manual as it starts, 8' and 4' principals, chiff and all,
+        // a low note then a chord
+        Script {
+            name: "multi_pipes",
+            target: Target::Multi { voices: 4, threads: 1, effects: &[], organ: true },
+            blocks: 800,
+            actions: vec![
+                (0, Action::NoteOn(36)),
+                (250, Action::NoteOff(36)),
+                (300, Action::NoteOn(62)),
+                (300, Action::NoteOn(66)),
+                (300, Action::NoteOn(69)),
+                (650, Action::NoteOff(62)),
+                (650, Action::NoteOff(66)),
+                (650, Action::NoteOff(69))
+            ]
+        }
+    ]
+}
+
+fn message(action: Action) -> midi::Message {
+    let (_, channel) = midi::utils::from_status_byte(0x90);
+    match action {
+        Action::NoteOn(note) => midi::Message::NoteOn(channel, note, 100),
+        Action::NoteOff(note) => midi::Message::NoteOff(channel, note, 0),
+        Action::Control(control, value) => midi::Message::ControlChange(channel, control, value),
+        Action::PitchBend(value) => midi::Message::PitchBend(channel, value)
+    }
+}
+
+// actions for a block, in order
+fn actions_at(script: &Script, block: usize) -> Vec<Action> {
+    script.actions.iter().filter(|a| a.0 == block).map(|a| a.1).collect()
+}
+
+fn render_voice(script: &Script) -> Vec<f32> {
+    let (messages, midi_input) = mpsc::channel();
+    let mut samples = Vec::new();
+    let mut tuning = Tuning::new(440.0, 0);
+    let mut voice = Voice::new(SAMPLE_RATE, tuning.clone(), &DEFAULT_REGISTRATION, midi_input);
+    let cc_map = CcMap::default();
+
+    for block in 0..script.blocks {
+        for action in actions_at(script, block) {
+            let voice_message = match action {
+                Action::Control(control, value) => {
+                    match cc_map.control(control) {
+                        Some(Control::Drawbar(drawbar)) => VoiceMessage::Drawbar(drawbar, (127 - value.min(127)) as f32 / 127.0),
+                        _ => { continue; }
+                    }
+                }
+                Action::PitchBend(value) => {
+                    tuning.set_bend(value);
+                    VoiceMessage::Tuning(tuning.clone())
+                }
+                _ => VoiceMessage::Midi(message(action))
+            };
+            messages.send(voice_message).unwrap();
+        }
+        // a silent voice renders nothing, fill the gap
+        if voice.run().unwrap() {
+            samples.extend_from_slice(voice.output());
+        }
+        else {
+            samples.extend_from_slice(&BLANK_BUFFER);
+        }
+    }
+    samples
+}
+
+fn render_multi(script: &Script, voices: usize, threads: usize, effects: &[EffectType], organ: bool) -> Vec<f32> {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_voices = voices;
+    config.num_threads = threads;
+    config.effects = effects.to_vec();
+    if organ {
+        config.organ = Some(Arc::new(Organ::builtin()));
+    }
+    let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+
+    let mut samples = Vec::new();
+    for block in 0..script.blocks {
+        for action in actions_at(script, block) {
+            midi_conn.midi_message(&message(action));
+        }
+        multi.run();
+        samples.extend_from_slice(multi.output());
+    }
+    samples
+}
+
+/// Render a script at SAMPLE_RATE, the same every time
+pub fn render(script: &Script) -> Vec<f32> {
+    match script.target {
+        Target::Voice => render_voice(script),
+        Target::Multi { voices, threads, effects, organ } => render_multi(script, voices, threads, effects, organ)
+    }
+}
+
+/// RMS level of each envelope window
+pub fn envelope(samples: &[f32]) -> Vec<f32> {
+    samples.chunks(ENVELOPE_WINDOW)
+        .map(|window| (window.iter().map(|s| s * s).sum::<f32>() / window.len() as f32).sqrt())
+        .collect()
+}
+
+/// Band levels in dB for each whole spectrum window, a plain DFT as speed doesn't matter here
+pub fn spectrum(samples: &[f32]) -> Vec<[f32; SPECTRUM_BANDS]> {
+    let n = SPECTRUM_WINDOW;
+    let hann: Vec<f32> = (0..n).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos()).collect();
+    let cos: Vec<f32> = (0..n).map(|i| (2.0 * PI * i as f32 / n as f32).cos()).collect();
+    let sin: Vec<f32> = (0..n).map(|i| (2.0 * PI * i as f32 / n as f32).sin()).collect();
+    let bins_per_band = (n / 2) / SPECTRUM_BANDS;
+
+    samples.chunks(n)
+        .filter(|window| window.len() == n)
+        .map(|window| {
+                let windowed: Vec<f32> = window.iter().zip(hann.iter()).map(|(s, h)| s * h).collect();
+                let mut bands = [0.0; SPECTRUM_BANDS];
+                for (band, level) in bands.iter_mut().enumerate() {
+                    let mut energy = 0.0;
+                    for k in (band * bins_per_band)..((band + 1) * bins_per_band) {
+                        let (mut re, mut im) = (0.0, 0.0);
+                        for (i, s) in windowed.iter().enumerate() {
+                            let phase = (k * i) % n;
+                            re += s * cos[phase];
+                            im -= s * sin[phase];
+                        }
+                        energy += re * re + im * im;
+                    }
+                    // normalised so a full scale sine is around 0dB
+                    *level = 10.0 * (energy / (n * n) as f32 + 1e-12).log10() + 12.0;
+                }
+                bands
+            })
+        .collect()
+}
+
+/// One place a render differs from its reference by more than the tolerance
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub what: String,
+    pub expected: f32,
+    pub actual: f32
+}
+
+impl Mismatch {
+    fn size(&self) -> f32 {
+        (self.actual - self.expected).abs()
+    }
+}
+
+pub struct Comparison {
+    pub lengths: (usize, usize),
+    pub max_sample_difference: f32,
+    pub envelope: Vec<Mismatch>,
+    pub spectrum: Vec<Mismatch>
+}
+
+impl Comparison {
+    pub fn passed(&self) -> bool {
+        self.lengths.0 == self.lengths.1 && self.envelope.is_empty() && self.spectrum.is_empty()
+    }
+}
+
+fn seconds(sample: usize) -> f32 {
+    sample as f32 / SAMPLE_RATE as f32
+}
+
+pub fn compare(expected: &[f32], actual: &[f32]) -> Comparison {
+    let max_sample_difference = expected.iter().zip(actual.iter())
+        .map(|(e, a)| (e - a).abs())
+        .fold(0.0, f32::max);
+
+    let mut envelope_mismatches = Vec::new();
+    for (i, (e, a)) in envelope(expected).into_iter().zip(envelope(actual)).enumerate() {
+        if (a - e).abs() > ENVELOPE_TOLERANCE + ENVELOPE_RELATIVE_TOLERANCE * e {
+            envelope_mismatches.push(Mismatch {
+                what: format!("envelope at {:.3}s", seconds(i * ENVELOPE_WINDOW)),
+                expected: e,
+                actual: a
+            });
+        }
+    }
+
+    let mut spectrum_mismatches = Vec::new();
+    let band_hz = SAMPLE_RATE as f32 / 2.0 / SPECTRUM_BANDS as f32;
+    for (i, (e, a)) in spectrum(expected).into_iter().zip(spectrum(actual)).enumerate() {
+        for band in 0..SPECTRUM_BANDS {
+            let audible = e[band] > SPECTRUM_FLOOR_DB || a[band] > SPECTRUM_FLOOR_DB;
+            if audible && (a[band] - e[band]).abs() > SPECTRUM_TOLERANCE_DB {
+                spectrum_mismatches.push(Mismatch {
+                    what: format!("spectrum at {:.3}s, {:.0}-{:.0}Hz", seconds(i * SPECTRUM_WINDOW),
+                        band as f32 * band_hz, (band + 1) as f32 * band_hz),
+                    expected: e[band],
+                    actual: a[band]
+                });
+            }
+        }
+    }
+
+    Comparison {
+        lengths: (expected.len(), actual.len()),
+        max_sample_difference,
+        envelope: envelope_mismatches,
+        spectrum: spectrum_mismatches
+    }
+}
+
+fn write_mismatches(f: &mut fmt::Formatter, kind: &str, unit: &str, mismatches: &[Mismatch]) -> fmt::Result {
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    writeln!(f, "  {} {} mismatches, the worst:", mismatches.len(), kind)?;
+    let mut worst = mismatches.to_vec();
+    worst.sort_by(|a, b| b.size().partial_cmp(&a.size()).unwrap());
+    for mismatch in worst.iter().take(MAX_REPORTED) {
+        writeln!(f, "    {}: expected {:.4}{}, got {:.4}{} ({:+.4})", mismatch.what,
+            mismatch.expected, unit, mismatch.actual, unit, mismatch.actual - mismatch.expected)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.lengths.0 != self.lengths.1 {
+            writeln!(f, "  length: expected {} samples, got {}", self.lengths.0, self.lengths.1)?;
+        }
+        write_mismatches(f, "envelope", "", &self.envelope)?;
+        write_mismatches(f, "spectrum", "dB", &self.spectrum)?;
+        write!(f, "  largest sample difference {:.6}", self.max_sample_difference)
+    }
+}
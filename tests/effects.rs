@@ -0,0 +1,471 @@
+// the effects chain and the effects that come with it
+
+extern crate midi;
+extern crate organn;
+
+#[path = "common/doubles.rs"]
+mod doubles;
+
+use organn::audio::Renderer;
+use organn::basic_types::{AudioBuffer, BUFFER_SIZE};
+use organn::basic_types::graph::Graph;
+use doubles::{Constant, Recorder};
+use organn::controls::{CcMap, Control};
+use organn::effects::{self, Effect, EffectChain, EffectType, DcBlocker, Equalizer, Gain, SpringReverb, Cabinet, ImpulseResponse};
+use organn::multi::{Multi, MultiConfig};
+use organn::tuning::{Tuning, TuningBank};
+use organn::wav::WavWriter;
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+const SAMPLE_RATE: u32 = 44_100;
+// a second
+const BLOCKS: usize = 2_756;
+// of a full scale sine
+const SINE_RMS: f32 = ::std::f32::consts::FRAC_1_SQRT_2;
+
+fn sine(freq: f32) -> Vec<AudioBuffer> {
+    (0..BLOCKS).map(|block| {
+            let mut buffer = [0.0; BUFFER_SIZE];
+            for (i, sample) in buffer.iter_mut().enumerate() {
+                let t = (block * BUFFER_SIZE + i) as f32 / SAMPLE_RATE as f32;
+                *sample = (2.0 * PI * freq * t).sin();
+            }
+            buffer
+        })
+        .collect()
+}
+
+fn process(effect: &mut dyn Effect, mut blocks: Vec<AudioBuffer>) -> Vec<f32> {
+    let mut samples = Vec::new();
+    for block in blocks.iter_mut() {
+        effect.process(block);
+        samples.extend_from_slice(block);
+    }
+    samples
+}
+
+// rms of the second half, once anything has settled
+fn settled_rms(samples: &[f32]) -> f32 {
+    let half = &samples[samples.len() / 2..];
+    (half.iter().map(|s| s * s).sum::<f32>() / half.len() as f32).sqrt()
+}
+
+fn db(ratio: f32) -> f32 {
+    20.0 * ratio.log10()
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+fn zero_crossings(samples: &[f32]) -> usize {
+    samples.windows(2).filter(|w| (w[0] >= 0.0) != (w[1] >= 0.0)).count()
+}
+
+fn impulse() -> Vec<AudioBuffer> {
+    let mut blocks = vec![[0.0; BUFFER_SIZE]; BLOCKS];
+    blocks[0][0] = 1.0;
+    blocks
+}
+
+fn noise(blocks: usize, seed: u32) -> Vec<AudioBuffer> {
+    let mut seed = seed;
+    (0..blocks).map(|_| {
+            let mut block = [0.0; BUFFER_SIZE];
+            for sample in block.iter_mut() {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                *sample = (seed >> 8) as f32 / (1 << 23) as f32 - 1.0;
+            }
+            block
+        })
+        .collect()
+}
+
+fn response(sample_rate: u32, samples: Vec<f32>) -> Arc<ImpulseResponse> {
+    Arc::new(ImpulseResponse {
+        name: "test".to_string(),
+        sample_rate,
+        samples
+    })
+}
+
+// a response that just delays
+fn delay(samples: usize) -> Arc<ImpulseResponse> {
+    let mut taps = vec![0.0; samples + 1];
+    taps[samples] = 1.0;
+    response(SAMPLE_RATE, taps)
+}
+
+// all wet, the mix is smoothed so it's only properly wet once it settles
+fn spring(decay: f32, tone: f32) -> SpringReverb {
+    let mut reverb = SpringReverb::new(SAMPLE_RATE);
+    reverb.set_param(0, 1.0);
+    reverb.set_param(1, decay);
+    reverb.set_param(2, tone);
+    reverb
+}
+
+#[test]
+fn dc_blocker_removes_offset_and_keeps_audio() {
+    let mut blocker = DcBlocker::new(SAMPLE_RATE);
+    let offset = process(&mut blocker, vec![[0.5; BUFFER_SIZE]; BLOCKS]);
+    assert!(offset[offset.len() - 1].abs() < 1e-3, "{}", offset[offset.len() - 1]);
+
+    let mut blocker = DcBlocker::new(SAMPLE_RATE);
+    let tone = process(&mut blocker, sine(110.0));
+    assert!(db(settled_rms(&tone) / SINE_RMS).abs() < 0.1);
+}
+
+#[test]
+fn flat_eq_is_transparent() {
+    let mut eq = Equalizer::new(SAMPLE_RATE);
+    let input: Vec<f32> = sine(440.0).iter().flat_map(|b| b.iter().cloned()).collect();
+    let output = process(&mut eq, sine(440.0));
+    let error = input.iter().zip(output.iter()).map(|(i, o)| (i - o).abs()).fold(0.0, f32::max);
+    assert!(error < 1e-5, "{}", error);
+}
+
+#[test]
+fn eq_bands_boost_their_own_frequencies() {
+    let level = |param: usize, freq: f32| {
+        let mut eq = Equalizer::new(SAMPLE_RATE);
+        eq.set_param(param, 12.0);
+        db(settled_rms(&process(&mut eq, sine(freq))) / SINE_RMS)
+    };
+
+    // low shelf
+    assert!((level(0, 50.0) - 12.0).abs() < 0.5);
+    assert!(level(0, 10_000.0).abs() < 0.5);
+    // mid peak at its default 1kHz
+    assert!((level(1, 1_000.0) - 12.0).abs() < 0.5);
+    assert!(level(1, 50.0).abs() < 1.0);
+    // high shelf
+    assert!((level(3, 15_000.0) - 12.0).abs() < 0.5);
+    assert!(level(3, 50.0).abs() < 0.5);
+}
+
+#[test]
+fn gain_settles_without_jumping() {
+    let mut gain = Gain::new(SAMPLE_RATE);
+    gain.set_param(0, -6.0);
+    let output = process(&mut gain, vec![[1.0; BUFFER_SIZE]; BLOCKS]);
+
+    assert!(output[0] > 0.99, "{}", output[0]);
+    assert!((db(output[output.len() - 1]) + 6.0).abs() < 0.01);
+}
+
+#[test]
+fn spring_tail_falls_60db_in_the_decay_time() {
+    // from 0.1s to 0.8s
+    let fall = |decay: f32| {
+        let tail = process(&mut spring(decay, 3_500.0), impulse());
+        db(rms(&tail[4_410..8_820]) / rms(&tail[35_280..39_690]))
+    };
+    assert!((fall(2.0) - 21.0).abs() < 3.0, "{}", fall(2.0));
+    assert!(fall(0.5) > fall(2.0) + 30.0);
+    assert!(fall(6.0) < fall(2.0) - 10.0);
+}
+
+#[test]
+fn spring_echoes_drip() {
+    let tail = process(&mut spring(2.0, 3_500.0), impulse());
+
+    // the first echo starts high and falls, the dispersion of a spring
+    let onset = tail.iter().skip(441).position(|s| s.abs() > 1e-3).unwrap() + 441;
+    let early = zero_crossings(&tail[onset..(onset + 100)]);
+    let late = zero_crossings(&tail[(onset + 100)..(onset + 200)]);
+    assert!(early > 2 * late, "{} then {} zero crossings", early, late);
+}
+
+#[test]
+fn spring_tone_darkens_the_tail() {
+    let crossings = |tone: f32| zero_crossings(&process(&mut spring(2.0, tone), impulse())[22_050..26_460]);
+    assert!(crossings(8_000.0) as f32 > crossings(1_000.0) as f32 * 1.5);
+}
+
+#[test]
+fn spring_with_no_mix_is_dry_and_stays_bounded_when_wet() {
+    let mut reverb = SpringReverb::new(SAMPLE_RATE);
+    reverb.set_param(0, 0.0);
+    let dry = process(&mut reverb, sine(440.0));
+    let input: Vec<f32> = sine(440.0).iter().flat_map(|b| b.iter().cloned()).collect();
+    let half = input.len() / 2;
+    let error = input[half..].iter().zip(dry[half..].iter()).map(|(i, d)| (i - d).abs()).fold(0.0, f32::max);
+    assert!(error < 1e-6, "{}", error);
+
+    // the longest decay fed full scale noise
+    let wet = process(&mut spring(6.0, 8_000.0), noise(BLOCKS, 1));
+    assert!(wet.iter().all(|s| s.is_finite() && s.abs() < 4.0));
+}
+
+#[test]
+fn cabinet_matches_direct_convolution() {
+    // long enough for the tail partitions, not a whole number of them
+    let ir = response(SAMPLE_RATE, noise(200, 2).iter().flat_map(|b| b.iter().cloned()).take(3_000).collect());
+    let taps = ir.taps(SAMPLE_RATE);
+    let input: Vec<f32> = noise(400, 3).iter().flat_map(|b| b.iter().cloned()).collect();
+
+    let output = process(&mut Cabinet::new(vec![ir], SAMPLE_RATE), noise(400, 3));
+    let error = (0..input.len())
+        .map(|n| {
+                let direct: f32 = taps.iter().take(n + 1).enumerate().map(|(j, t)| t * input[n - j]).sum();
+                (direct - output[n]).abs()
+            })
+        .fold(0.0, f32::max);
+    assert!(error < 1e-4, "{}", error);
+}
+
+#[test]
+fn cabinet_switches_responses_without_a_click() {
+    // the second response puts the sine about half a cycle out, cutting straight over would
+    // jump by nearly twice its amplitude, and it's long enough to need the tail partitions
+    let mut cabinet = Cabinet::new(vec![delay(0), delay(650)], SAMPLE_RATE);
+    let input: Vec<f32> = sine(440.0).iter().flat_map(|b| b.iter().cloned()).collect();
+    let mut blocks = sine(440.0);
+
+    let mut output = Vec::new();
+    for (i, block) in blocks.iter_mut().enumerate() {
+        if i == 100 {
+            // only two are loaded
+            cabinet.set_param(0, 16.0);
+        }
+        cabinet.process(block);
+        output.extend_from_slice(block);
+    }
+
+    // steepest a 440Hz sine gets, and a bit for the fade
+    let steepest = output.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+    assert!(steepest < 0.07, "{}", steepest);
+    // and it's gone over completely within a few partitions
+    let settled = (200 * BUFFER_SIZE)..output.len();
+    let error = settled.map(|n| (output[n] - input[n - 650]).abs()).fold(0.0, f32::max);
+    assert!(error < 1e-4, "{}", error);
+}
+
+#[test]
+fn responses_are_resampled_to_the_engine_rate() {
+    // a sine at 48kHz comes out as the same sine at 44.1kHz
+    let wave = |sample_rate: u32, len: usize| {
+        (0..len).map(|n| (2.0 * PI * 100.0 * n as f32 / sample_rate as f32).sin()).collect::<Vec<f32>>()
+    };
+    let resampled = effects::resample(&wave(48_000, 4_800), 48_000, SAMPLE_RATE);
+    assert_eq!(resampled.len(), 4_410);
+    let expected = wave(SAMPLE_RATE, 4_410);
+    let error = (500..3_900).map(|n| (resampled[n] - expected[n]).abs()).fold(0.0, f32::max);
+    assert!(error < 1e-3, "{}", error);
+
+    // a click at half the rate is band limited to that rate's nyquist, with room either side
+    // for the filter to ring
+    let mut click = vec![0.0; 129];
+    click[64] = 1.0;
+    let mut cabinet = Cabinet::new(vec![response(22_050, click.clone())], SAMPLE_RATE);
+    let low = settled_rms(&process(&mut cabinet, sine(1_000.0)));
+    let mut cabinet = Cabinet::new(vec![response(22_050, click)], SAMPLE_RATE);
+    let high = settled_rms(&process(&mut cabinet, sine(15_000.0)));
+    assert!(db(high / low) < -40.0, "{}", db(high / low));
+}
+
+#[test]
+fn cabinets_load_mono_and_stereo_wavs() {
+    let dir = ::std::env::temp_dir();
+    let write = |name: &str, channels: u16, samples: &[f32]| {
+        let path = dir.join(format!("organn-test-{}-{}", ::std::process::id(), name));
+        let mut writer = WavWriter::create(&path, 48_000, channels).unwrap();
+        writer.write(samples).unwrap();
+        writer.finish().unwrap();
+        path
+    };
+    let mono = write("mono.wav", 1, &[1.0, 0.5]);
+    let stereo = write("stereo.wav", 2, &[1.0, 0.0, 0.0, 0.5]);
+    let surround = write("surround.wav", 3, &[0.0; 6]);
+
+    let ir = ImpulseResponse::load(&mono).unwrap();
+    assert_eq!((ir.sample_rate, ir.samples), (48_000, vec![1.0, 0.5]));
+    let ir = ImpulseResponse::load(&stereo).unwrap();
+    assert_eq!(ir.samples, [0.5, 0.25]);
+    assert!(ImpulseResponse::load(&surround).is_err());
+
+    let chain = effects::parse_chain(&format!("dc, cabinet={}+{}", mono.display(), stereo.display())).unwrap();
+    match chain[1] {
+        EffectType::Cabinet(ref responses) => assert_eq!(responses.len(), 2),
+        ref other => panic!("{:?}", other)
+    }
+    assert!(effects::parse_chain("cabinet=").is_err());
+    assert!(effects::parse_chain(&format!("cabinet={}", surround.display())).is_err());
+
+    for path in [mono, stereo, surround].iter() {
+        ::std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn chain_runs_in_order_and_can_be_rearranged() {
+    let mut chain = EffectChain::new(SAMPLE_RATE);
+    for effect in effects::parse_chain("eq, dc,gain").unwrap() {
+        chain.push(effect.create(SAMPLE_RATE));
+    }
+    let names = |chain: &EffectChain| (0..chain.len()).map(|i| chain.effect(i).unwrap().name()).collect::<Vec<_>>();
+    assert_eq!(names(&chain), ["eq", "dc", "gain"]);
+
+    chain.move_effect(2, 0);
+    assert_eq!(names(&chain), ["gain", "eq", "dc"]);
+    chain.remove(1);
+    assert_eq!(names(&chain), ["gain", "dc"]);
+    chain.insert(1, EffectType::Equalizer.create(SAMPLE_RATE));
+    assert_eq!(names(&chain), ["gain", "eq", "dc"]);
+
+    assert!(effects::parse_chain("eq,reverb").is_err());
+    assert_eq!(effects::parse_chain("spring").unwrap(), [EffectType::SpringReverb]);
+}
+
+#[test]
+fn bypassed_effects_are_skipped() {
+    let mut chain = EffectChain::new(SAMPLE_RATE);
+    chain.push(Box::new(Gain::new(SAMPLE_RATE)));
+    chain.set_param(0, 0, -24.0);
+
+    let mut graph = Graph::new();
+    let source = graph.add("source", Constant::new([0.5; BUFFER_SIZE]));
+    let chain = graph.add("effects", chain);
+    let recorder = graph.add("recorder", Recorder::new());
+    graph.connect(&source, &chain).unwrap();
+    graph.connect(&chain, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+
+    for _ in 0..BLOCKS {
+        graph.run().unwrap();
+    }
+    assert!(graph.output()[0] < 0.5 / 10.0);
+
+    graph.node_mut(&chain).set_bypass(0, true);
+    assert_eq!(*graph.run().unwrap(), [0.5; BUFFER_SIZE]);
+}
+
+#[test]
+fn params_are_clamped_and_follow_controllers() {
+    let mut chain = EffectChain::new(SAMPLE_RATE);
+    chain.push(Box::new(Equalizer::new(SAMPLE_RATE)));
+
+    chain.set_param(0, 0, 100.0);
+    assert_eq!(chain.effect(0).unwrap().param(0), 12.0);
+    chain.set_param_cc(0, 0, 0);
+    assert_eq!(chain.effect(0).unwrap().param(0), -12.0);
+
+    // the mid frequency goes logarithmically
+    chain.set_param_cc(0, 2, 0);
+    assert!((chain.effect(0).unwrap().param(2) - 200.0).abs() < 0.01);
+    chain.set_param_cc(0, 2, 127);
+    assert!((chain.effect(0).unwrap().param(2) - 5_000.0).abs() < 0.1);
+
+    // nothing there, nothing happens
+    chain.set_param(3, 0, 1.0);
+    chain.set_param(0, 9, 1.0);
+}
+
+#[test]
+fn cc_map_names_effect_controls() {
+    let map = CcMap::parse("20 effect1.2\n21 effect2.bypass").unwrap();
+    assert_eq!(map.control(20), Some(Control::Effect(0, 1)));
+    assert_eq!(map.control(21), Some(Control::EffectBypass(1)));
+
+    assert!(CcMap::parse("20 effect0.1").is_err());
+    assert!(CcMap::parse("20 effect1").is_err());
+    assert!(CcMap::parse("20 effect1.0").is_err());
+}
+
+#[test]
+fn controllers_reach_the_engines_effects() {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_threads = 1;
+    config.effects = vec![EffectType::Gain, EffectType::DcBlocker];
+    config.cc_map = CcMap::parse("20 effect1.1\n21 effect2.bypass").unwrap();
+    let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+
+    let (_, channel) = midi::utils::from_status_byte(0xB0);
+    midi_conn.midi_message(&midi::Message::ControlChange(channel, 20, 0));
+    midi_conn.midi_message(&midi::Message::ControlChange(channel, 21, 127));
+    multi.run();
+
+    assert_eq!(multi.effects().effect(0).unwrap().param(0), -24.0);
+    assert!(multi.effects().is_bypassed(1));
+}
+
+#[test]
+fn controllers_reach_the_effects_once_a_backend_has_the_engine() {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_threads = 1;
+    config.effects = vec![EffectType::Gain];
+    config.cc_map = CcMap::parse("20 effect1.1\n21 effect1.bypass").unwrap();
+    let (multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+    // nothing can get at the chain itself from here on, only the midi side
+    let mut renderer = Renderer::new(multi, 0);
+
+    let (_, channel) = midi::utils::from_status_byte(0xB0);
+    midi_conn.midi_message(&midi::Message::NoteOn(channel, 69, 100));
+    let mut level = |cc: midi::U7, value: midi::U7| {
+        midi_conn.midi_message(&midi::Message::ControlChange(channel, cc, value));
+        // let the gain's smoothing settle first
+        let mut out = vec![0.0; SAMPLE_RATE as usize / 5];
+        renderer.render(&mut out);
+        renderer.render(&mut out);
+        rms(&out)
+    };
+
+    let unity = level(20, 85);
+    // -24dB
+    let quiet = level(20, 0);
+    assert!(quiet < unity * 0.1 && quiet > 0.0, "{} {}", quiet, unity);
+    // bypassed the gain no longer applies
+    assert!((level(21, 127) - unity).abs() < unity * 0.05);
+    assert!((level(21, 0) - quiet).abs() < unity * 0.01);
+}
+
+#[test]
+fn a_running_chain_can_be_changed() {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_threads = 1;
+    config.cc_map = CcMap::parse("20 effect1.1").unwrap();
+    let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+    let control = multi.chain_control();
+    let mut renderer = Renderer::new(multi, 0);
+
+    let (_, channel) = midi::utils::from_status_byte(0xB0);
+    midi_conn.midi_message(&midi::Message::NoteOn(channel, 69, 100));
+    let mut level = |midi_conn: &mut organn::multi::MultiMidiConn, value: Option<midi::U7>| {
+        if let Some(value) = value {
+            midi_conn.midi_message(&midi::Message::ControlChange(channel, 20, value));
+        }
+        let mut out = vec![0.0; SAMPLE_RATE as usize / 5];
+        renderer.render(&mut out);
+        renderer.render(&mut out);
+        rms(&out)
+    };
+
+    let unity = level(&mut midi_conn, None);
+    let is_unity = |level: f32| (level - unity).abs() < unity * 0.05;
+
+    // a gain put in and turned down, the controller reaches the new effect
+    control.insert(0, &EffectType::Gain);
+    let quiet = level(&mut midi_conn, Some(0));
+    assert!(quiet < unity * 0.1, "{} {}", quiet, unity);
+
+    control.set_bypass(0, true);
+    assert!(is_unity(level(&mut midi_conn, None)));
+    control.set_bypass(0, false);
+    assert!(level(&mut midi_conn, None) < unity * 0.1);
+
+    // a dc blocker in front takes over the first controller, the gain stays down
+    control.insert(0, &EffectType::DcBlocker);
+    assert!(level(&mut midi_conn, Some(127)) < unity * 0.1);
+
+    // moved back to the front the gain's on the controller again
+    control.move_effect(1, 0);
+    assert!(is_unity(level(&mut midi_conn, Some(85))));
+    assert!(level(&mut midi_conn, Some(0)) < unity * 0.1);
+
+    // and gone
+    control.remove(0);
+    assert!(is_unity(level(&mut midi_conn, Some(0))));
+}
@@ -0,0 +1,79 @@
+// golden audio, every script in common/golden.rs rendered and compared with its reference in
+// golden/, so a change to the sound fails the suite
+// after a deliberate change write new references with
+// `GOLDEN_BLESS=1 cargo test --release --test golden`
+
+extern crate midi;
+extern crate organn;
+
+#[path = "common/golden.rs"]
+mod golden;
+
+use organn::wav::{WavData, WavWriter};
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden").join(format!("{}.wav", name))
+}
+
+fn bless(path: &Path, samples: &[f32]) {
+    let mut writer = WavWriter::create(path, golden::SAMPLE_RATE, 1).unwrap();
+    writer.write(samples).unwrap();
+    writer.finish().unwrap();
+}
+
+fn check(name: &str) {
+    let script = golden::scripts().into_iter().find(|s| s.name == name).unwrap();
+    let samples = golden::render(&script);
+    let path = reference_path(name);
+
+    if env::var_os("GOLDEN_BLESS").is_some() {
+        bless(&path, &samples);
+        return;
+    }
+
+    let reference = WavData::read(&path)
+        .unwrap_or_else(|e| panic!("can't read reference {}: {}, run with GOLDEN_BLESS=1 to make it", path.display(), e));
+    assert_eq!((reference.sample_rate, reference.channels), (golden::SAMPLE_RATE, 1), "{}", path.display());
+
+    let comparison = golden::compare(&reference.samples, &samples);
+    assert!(comparison.passed(), "{} differs from its reference\n{}", name, comparison);
+}
+
+#[test]
+fn every_script_is_checked() {
+    let names: Vec<&str> = golden::scripts().iter().map(|s| s.name).collect();
+    assert_eq!(names, ["voice_note", "voice_drawbars", "multi_chord", "multi_steal", "multi_spring", "multi_pipes"]);
+}
+
+#[test]
+fn voice_note() {
+    check("voice_note");
+}
+
+#[test]
+fn voice_drawbars() {
+    check("voice_drawbars");
+}
+
+#[test]
+fn multi_chord() {
+    check("multi_chord");
+}
+
+#[test]
+fn multi_steal() {
+    check("multi_steal");
+}
+
+#[test]
+fn multi_spring() {
+    check("multi_spring");
+}
+
+#[test]
+fn multi_pipes() {
+    check("multi_pipes");
+}
@@ -0,0 +1,99 @@
+// the processing graph, the order it resolves and the errors it gives for a bad topology
+
+extern crate organn;
+
+#[path = "common/doubles.rs"]
+mod doubles;
+
+use organn::basic_types::BUFFER_SIZE;
+use organn::basic_types::graph::{Graph, GraphError};
+use doubles::{Constant, Recorder, Scripted};
+use organn::mixer::Mixer;
+
+#[test]
+fn runs_in_topology_order_whatever_order_nodes_were_added() {
+    let mut graph = Graph::new();
+    // added back to front, the recorder has to run last regardless
+    let recorder = graph.add("recorder", Recorder::new());
+    let mixer = graph.add("mixer", Mixer::new(vec![1.0, 0.5]));
+    let b = graph.add("b", Scripted::new(vec![[2.0; BUFFER_SIZE], [4.0; BUFFER_SIZE]]));
+    let a = graph.add("a", Constant::new([1.0; BUFFER_SIZE]));
+    graph.connect(&a, &mixer).unwrap();
+    graph.connect(&b, &mixer).unwrap();
+    graph.connect(&mixer, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+
+    assert_eq!(*graph.run().unwrap(), [2.0; BUFFER_SIZE]);
+    assert_eq!(*graph.run().unwrap(), [3.0; BUFFER_SIZE]);
+    assert_eq!(graph.node(&recorder).blocks(), 2);
+}
+
+#[test]
+fn missing_input_is_an_error() {
+    let mut graph = Graph::new();
+    let a = graph.add("a", Constant::new([1.0; BUFFER_SIZE]));
+    let mixer = graph.add("mixer", Mixer::new(vec![1.0, 1.0]));
+    graph.connect(&a, &mixer).unwrap();
+    graph.set_output(&mixer).unwrap();
+
+    assert_eq!(graph.run().err(), Some(GraphError::Inputs { node: "mixer".to_string(), needs: 2, connected: 1 }));
+}
+
+#[test]
+fn too_many_inputs_is_an_error() {
+    let mut graph = Graph::new();
+    let a = graph.add("a", Constant::new([1.0; BUFFER_SIZE]));
+    let b = graph.add("b", Constant::new([1.0; BUFFER_SIZE]));
+    graph.connect(&b, &a).unwrap();
+    graph.set_output(&a).unwrap();
+
+    assert_eq!(graph.resolve(), Err(GraphError::Inputs { node: "a".to_string(), needs: 0, connected: 1 }));
+}
+
+#[test]
+fn cycle_is_an_error() {
+    let mut graph = Graph::new();
+    let a = graph.add("a", Recorder::new());
+    let b = graph.add("b", Recorder::new());
+    graph.connect(&a, &b).unwrap();
+    graph.connect(&b, &a).unwrap();
+    graph.set_output(&b).unwrap();
+
+    assert_eq!(graph.resolve(), Err(GraphError::Cycle(vec!["a".to_string(), "b".to_string()])));
+}
+
+#[test]
+fn no_output_is_an_error() {
+    let mut graph = Graph::new();
+    graph.add("a", Constant::new([1.0; BUFFER_SIZE]));
+
+    assert_eq!(graph.run().err(), Some(GraphError::NoOutput));
+}
+
+#[test]
+fn a_handle_from_another_graph_is_an_error() {
+    let mut other = Graph::new();
+    let stranger = other.add("stranger", Constant::new([1.0; BUFFER_SIZE]));
+    // the first node in both, so the index alone would pass
+    let mut graph = Graph::new();
+    let a = graph.add("a", Recorder::new());
+
+    assert_eq!(graph.connect(&stranger, &a), Err(GraphError::UnknownNode));
+    assert_eq!(graph.set_output(&stranger), Err(GraphError::UnknownNode));
+    assert_eq!(other.connect(&stranger, &a), Err(GraphError::UnknownNode));
+}
+
+#[test]
+fn output_is_silent_after_an_error() {
+    let mut graph = Graph::new();
+    let a = graph.add("a", Constant::new([1.0; BUFFER_SIZE]));
+    let recorder = graph.add("recorder", Recorder::new());
+    graph.connect(&a, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+    assert_eq!(*graph.run().unwrap(), [1.0; BUFFER_SIZE]);
+
+    // a second input the recorder doesn't take
+    graph.connect(&a, &recorder).unwrap();
+    assert!(graph.run().is_err());
+    assert_eq!(*graph.output(), [0.0; BUFFER_SIZE]);
+}
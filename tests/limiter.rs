@@ -0,0 +1,96 @@
+// the output limiter, its ceiling and threshold and the peak meter it keeps
+
+extern crate organn;
+
+#[path = "common/doubles.rs"]
+mod doubles;
+
+use organn::basic_types::{AudioBuffer, BUFFER_SIZE};
+use organn::basic_types::graph::Graph;
+use organn::limiter::Limiter;
+use doubles::{Scripted, Recorder};
+
+// everything the limiter puts out for some blocks
+fn limit(limiter: Limiter, blocks: Vec<AudioBuffer>) -> Vec<f32> {
+    let count = blocks.len();
+    let mut graph = Graph::new();
+    let source = graph.add("source", Scripted::new(blocks));
+    let limiter = graph.add("limiter", limiter);
+    let recorder = graph.add("recorder", Recorder::new());
+    graph.connect(&source, &limiter).unwrap();
+    graph.connect(&limiter, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+    for _ in 0..count {
+        graph.run().unwrap();
+    }
+    graph.node(&recorder).samples().to_vec()
+}
+
+// a block ramping from -level to level
+fn ramp(level: f32) -> AudioBuffer {
+    let mut block = [0.0; BUFFER_SIZE];
+    for (i, sample) in block.iter_mut().enumerate() {
+        *sample = level * (2.0 * i as f32 / (BUFFER_SIZE - 1) as f32 - 1.0);
+    }
+    block
+}
+
+#[test]
+fn nothing_gets_past_the_ceiling() {
+    let blocks: Vec<AudioBuffer> = [1.0, 2.0, 100.0, 1e30].iter().map(|l| ramp(*l)).collect();
+    let samples = limit(Limiter::new(), blocks);
+    assert!(samples.iter().all(|s| s.abs() <= 0.98), "{:?}", samples);
+
+    // still rising with the input, just more and more slowly
+    let full_scale = &samples[..BUFFER_SIZE];
+    assert!(full_scale.windows(2).all(|w| w[1] > w[0]));
+
+    let mut hard = Limiter::new();
+    hard.set_levels(0.5, 0.5);
+    let samples = limit(hard, vec![ramp(4.0)]);
+    assert!(samples.iter().all(|s| s.abs() <= 0.5));
+    assert_eq!((samples[0], samples[BUFFER_SIZE - 1]), (-0.5, 0.5));
+}
+
+#[test]
+fn below_the_threshold_passes_untouched() {
+    let quiet = ramp(0.8);
+    let samples = limit(Limiter::new(), vec![quiet, ramp(0.123_456)]);
+    assert_eq!(&samples[..BUFFER_SIZE], &quiet[..]);
+    assert_eq!(&samples[BUFFER_SIZE..], &ramp(0.123_456)[..]);
+
+    // levels out of range are held in range, the threshold under the ceiling
+    let mut limiter = Limiter::new();
+    limiter.set_levels(2.0, 0.9);
+    let samples = limit(limiter, vec![ramp(0.9), ramp(5.0)]);
+    assert_eq!(&samples[..BUFFER_SIZE], &ramp(0.9)[..]);
+    assert!(samples.iter().all(|s| s.abs() <= 0.9));
+}
+
+#[test]
+fn the_meter_counts_peaks_and_limited_samples() {
+    let mut graph = Graph::new();
+    let mut loud = [0.5; BUFFER_SIZE];
+    loud[3] = -1.5;
+    loud[7] = 0.81;
+    loud[8] = 0.8;
+    let source = graph.add("source", Scripted::new(vec![[0.25; BUFFER_SIZE], loud, loud]));
+    let limiter = graph.add("limiter", Limiter::new());
+    graph.connect(&source, &limiter).unwrap();
+    graph.set_output(&limiter).unwrap();
+    let meter = graph.node(&limiter).meter();
+
+    graph.run().unwrap();
+    assert_eq!((meter.take_peak(), meter.limited_samples()), (0.25, 0));
+    // taking the peak starts again from nothing
+    assert_eq!(meter.take_peak(), 0.0);
+
+    // two over the threshold each block, at it doesn't count
+    graph.run().unwrap();
+    graph.run().unwrap();
+    assert_eq!((meter.take_peak(), meter.limited_samples()), (1.5, 4));
+
+    // silence after the script ends, the count stays
+    graph.run().unwrap();
+    assert_eq!((meter.take_peak(), meter.limited_samples()), (0.0, 4));
+}
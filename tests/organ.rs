@@ -0,0 +1,227 @@
+// pipe organ stops, their ranks and couplers, and the voices playing them
+
+extern crate midi;
+extern crate organn;
+
+use organn::controls::{CcMap, Control};
+use organn::multi::{Multi, MultiConfig};
+use organn::organ::{Organ, Rank, parse_footage};
+use organn::tuning::{Tuning, TuningBank};
+use organn::voice::{Voice, VoiceMessage};
+
+use std::f32::consts::PI;
+use std::sync::{mpsc, Arc};
+
+const SAMPLE_RATE: u32 = 44_100;
+// 0.2s
+const BLOCKS: usize = 551;
+
+fn channel(number: u8) -> midi::Channel {
+    midi::utils::from_status_byte(0x90 | (number - 1)).1
+}
+
+// level of one frequency in the samples, goertzel
+fn level(samples: &[f32], freq: f32) -> f32 {
+    let coeff = 2.0 * (2.0 * PI * freq / SAMPLE_RATE as f32).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for sample in samples {
+        let s = sample + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0).sqrt() * 2.0 / samples.len() as f32
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+fn pipe_voice(organ: Organ) -> (Voice, mpsc::Sender<VoiceMessage>) {
+    let (messages, midi_input) = mpsc::channel();
+    (Voice::pipes(SAMPLE_RATE, Tuning::new(440.0, 0), Arc::new(organ), midi_input), messages)
+}
+
+fn render(voice: &mut Voice, blocks: usize) -> Vec<f32> {
+    let mut samples = Vec::new();
+    for _ in 0..blocks {
+        if voice.run().unwrap() {
+            samples.extend_from_slice(voice.output());
+        }
+    }
+    samples
+}
+
+// a note held on a channel, all after the voice has settled
+fn play(organ: &str, channel_number: u8, note: midi::U7) -> Vec<f32> {
+    let (mut voice, messages) = pipe_voice(Organ::parse(organ).unwrap());
+    messages.send(VoiceMessage::Midi(midi::Message::NoteOn(channel(channel_number), note, 100))).unwrap();
+    render(&mut voice, BLOCKS).split_off(SAMPLE_RATE as usize / 10)
+}
+
+#[test]
+fn footages_parse() {
+    assert_eq!(parse_footage("8"), Some(8.0));
+    assert_eq!(parse_footage("2-2/3"), Some(2.0 + 2.0 / 3.0));
+    assert_eq!(parse_footage("1/2"), Some(0.5));
+    assert_eq!(parse_footage("5.5"), Some(5.5));
+    for bad in &["", "0", "-8", "8/0", "eight", "2-", "2-2/"] {
+        assert_eq!(parse_footage(bad), None, "{}", bad);
+    }
+
+    assert_eq!(Rank::new(16.0).ratio(60), 0.5);
+    assert_eq!(Rank::new(2.0 + 2.0 / 3.0).ratio(60), 3.0);
+}
+
+#[test]
+fn organs_parse_stops_mixtures_and_couplers() {
+    let organ = Organ::parse("
+        # a comment
+        stop upper Principal8 principal 8 on
+        stop pedal Mixture reed 2+1 60:4+2 72:8+4   # breaking back
+        coupler upper pedal on
+        coupler lower upper
+    ").unwrap();
+
+    assert_eq!(organ.stops.len(), 2);
+    assert_eq!((organ.stops[0].division, organ.stops[0].on, organ.stops[0].profile.name), (0, true, "principal"));
+    let mixture = &organ.stops[1];
+    assert_eq!((mixture.name.as_str(), mixture.division, mixture.on), ("Mixture", 2, false));
+    assert_eq!(mixture.ranks.len(), 2);
+    let footages: Vec<f32> = [0, 59, 60, 71, 72, 127].iter().map(|n| mixture.ranks[1].footage(*n)).collect();
+    assert_eq!(footages, vec![1.0, 1.0, 2.0, 2.0, 4.0, 4.0]);
+
+    assert_eq!(organ.couplers.len(), 2);
+    assert_eq!((organ.couplers[0].from, organ.couplers[0].to, organ.couplers[0].on), (0, 2, true));
+    assert_eq!(organ.sounding(0, &[true, true]), [true, true, false]);
+    assert_eq!(organ.sounding(2, &[true, true]), [true, false, true]);
+    assert_eq!(organ.sounding(2, &[false, true]), [false, false, true]);
+
+    let builtin = Organ::builtin();
+    assert_eq!((builtin.stops.len(), builtin.couplers.len()), (14, 3));
+}
+
+#[test]
+fn bad_organs_say_where() {
+    let errors = [
+        ("stop middle Flute8 flute 8", "unknown division"),
+        ("stop upper Flute8 kazoo 8", "unknown pipe"),
+        ("stop upper Flute8 flute 8+x", "bad footages"),
+        ("stop upper Flute8 flute 8 60-4", "bad break"),
+        ("stop upper Flute8 flute 8 200:4", "bad break"),
+        ("stop upper Mix principal 2+1 60:4", "has 1 ranks, not 2"),
+        ("stop upper Mix principal 2 60:4 48:8", "isn't above the last"),
+        ("coupler upper", "expected"),
+        ("bells upper", "expected")
+    ];
+    for &(line, expected) in errors.iter() {
+        let error = Organ::parse(&format!("stop upper Principal8 principal 8\n{}", line)).unwrap_err();
+        assert!(error.starts_with("line 2: ") && error.contains(expected), "{}: {}", line, error);
+    }
+}
+
+#[test]
+fn pipes_sound_at_their_footage() {
+    // a220
+    for &(footage, freq) in [("16", 110.0), ("8", 220.0), ("4", 440.0), ("2-2/3", 660.0)].iter() {
+        let samples = play(&format!("stop upper Flute flute {} on", footage), 1, 57);
+        let others = [110.0, 220.0, 440.0, 660.0].iter().filter(|f| **f != freq).map(|f| level(&samples, *f)).fold(0.0, f32::max);
+        assert!(level(&samples, freq) > 0.05 && others < level(&samples, freq) / 5.0, "{}' {} {}", footage, level(&samples, freq), others);
+    }
+
+    // a mixture rank jumping up an octave at its break
+    let organ = "stop upper Mixture principal 2 60:1 on";
+    assert!(level(&play(organ, 1, 57), 880.0) > 0.02);
+    let above = play(organ, 1, 60);
+    assert!(level(&above, 261.63 * 8.0) > 0.02 && level(&above, 261.63 * 4.0) < 0.01);
+}
+
+#[test]
+fn stops_are_drawn_and_pushed_in() {
+    let (mut voice, messages) = pipe_voice(Organ::parse("stop upper Flute8 flute 8\nstop upper Flute4 flute 4 on").unwrap());
+    messages.send(VoiceMessage::Stop(1, false)).unwrap();
+    messages.send(VoiceMessage::Midi(midi::Message::NoteOn(channel(1), 57, 100))).unwrap();
+    assert!(render(&mut voice, 10).iter().all(|s| *s == 0.0));
+
+    messages.send(VoiceMessage::Stop(0, true)).unwrap();
+    // stops past the end are ignored
+    messages.send(VoiceMessage::Stop(2, true)).unwrap();
+    let samples = render(&mut voice, BLOCKS).split_off(SAMPLE_RATE as usize / 10);
+    // no 4' on top of the 8' flute's own second harmonic
+    assert!(level(&samples, 220.0) > 0.05 && level(&samples, 440.0) < level(&samples, 220.0) / 4.0);
+}
+
+#[test]
+fn couplers_sound_one_division_from_anothers_keys() {
+    let organ = "stop lower Gedackt stopped 8 on\nstop pedal Subbass stopped 16 on\ncoupler lower upper";
+    assert!(play(organ, 1, 57).iter().all(|s| *s == 0.0));
+    assert!(level(&play(organ, 2, 57), 220.0) > 0.05);
+    assert!(level(&play(organ, 3, 57), 110.0) > 0.05);
+
+    let (mut voice, messages) = pipe_voice(Organ::parse(organ).unwrap());
+    messages.send(VoiceMessage::Coupler(0, true)).unwrap();
+    messages.send(VoiceMessage::Midi(midi::Message::NoteOn(channel(1), 57, 100))).unwrap();
+    let samples = render(&mut voice, BLOCKS).split_off(SAMPLE_RATE as usize / 10);
+    // only the lower manual comes along, not the pedals
+    assert!(level(&samples, 220.0) > 0.05 && level(&samples, 110.0) < 0.01);
+}
+
+#[test]
+fn pipes_chiff_as_they_start_to_speak() {
+    let text = "stop upper Flute8 flute 8 on";
+    let mut quiet = Organ::parse(text).unwrap();
+    quiet.stops[0].profile.chiff = 0.0;
+
+    let start = |organ: Organ| {
+        let (mut voice, messages) = pipe_voice(organ);
+        messages.send(VoiceMessage::Midi(midi::Message::NoteOn(channel(1), 57, 100))).unwrap();
+        render(&mut voice, BLOCKS)
+    };
+    let chiffed = start(Organ::parse(text).unwrap());
+    let clean = start(quiet);
+    let chiff: Vec<f32> = chiffed.iter().zip(clean.iter()).map(|(a, b)| a - b).collect();
+
+    // a burst over the first 50ms a good way under the tone, all but gone by 0.15s
+    let burst = rms(&chiff[..2_205]);
+    assert!(burst > rms(&clean[4_410..]) * 0.15, "{} {}", burst, rms(&clean[4_410..]));
+    assert!(rms(&chiff[6_615..]) < burst * 0.05, "{} {}", rms(&chiff[6_615..]), burst);
+}
+
+#[test]
+fn controllers_draw_stops_in_a_multi() {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_threads = 1;
+    config.cc_map = CcMap::parse("30 stop1\n31 coupler1").unwrap();
+    config.organ = Some(Arc::new(Organ::parse("stop upper Flute8 flute 8\ncoupler lower upper").unwrap()));
+    let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+
+    let mut play = |multi: &mut Multi, value: midi::U7| {
+        midi_conn.midi_message(&midi::Message::ControlChange(channel(1), 30, value));
+        midi_conn.midi_message(&midi::Message::NoteOn(channel(1), 69, 100));
+        let mut samples = Vec::new();
+        for _ in 0..BLOCKS {
+            multi.run();
+            samples.extend_from_slice(multi.output());
+        }
+        midi_conn.midi_message(&midi::Message::AllNotesOff(channel(1)));
+        for _ in 0..BLOCKS {
+            multi.run();
+        }
+        rms(&samples)
+    };
+
+    assert_eq!(play(&mut multi, 0), 0.0);
+    assert!(play(&mut multi, 127) > 0.01);
+    assert_eq!(play(&mut multi, 63), 0.0);
+}
+
+#[test]
+fn cc_map_names_stops_and_couplers() {
+    let map = CcMap::parse("30 stop1\n31 stop14\n32 coupler3").unwrap();
+    assert_eq!(map.control(30), Some(Control::Stop(0)));
+    assert_eq!(map.control(31), Some(Control::Stop(13)));
+    assert_eq!(map.control(32), Some(Control::Coupler(2)));
+
+    assert!(CcMap::parse("30 stop0").is_err());
+    assert!(CcMap::parse("30 stop").is_err());
+    assert!(CcMap::parse("30 coupler1.on").is_err());
+}
@@ -0,0 +1,47 @@
+// the table sine against f32::sin, rendered side by side from the same phase
+
+extern crate organn;
+
+#[path = "common/doubles.rs"]
+mod doubles;
+
+use organn::basic_types::graph::Graph;
+use doubles::Recorder;
+use organn::oscillator::{Oscillator, SineMethod};
+
+// linear interpolation in 4096 entries is good to about 3e-7, the rest is f32 rounding
+const MAX_ERROR: f32 = 2e-6;
+const BLOCKS: usize = 2_000;
+
+fn render(method: SineMethod, sample_rate: u32, freq: f32) -> Vec<f32> {
+    let mut graph = Graph::new();
+    let osc = graph.add("oscillator", Oscillator::with_method(sample_rate, method));
+    let recorder = graph.add("recorder", Recorder::new());
+    graph.connect(&osc, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+    graph.node_mut(&osc).set_freq(freq);
+    for _ in 0..BLOCKS {
+        graph.run().unwrap();
+    }
+    graph.node(&recorder).samples().to_vec()
+}
+
+#[test]
+fn table_matches_sin() {
+    for &sample_rate in [22_050, 44_100, 48_000, 96_000].iter() {
+        for &freq in [16.35, 261.63, 440.0, 1234.5, 4186.0, 9000.0].iter() {
+            let sin = render(SineMethod::Sin, sample_rate, freq);
+            let table = render(SineMethod::Table, sample_rate, freq);
+
+            let error = sin.iter().zip(table.iter()).map(|(s, t)| (s - t).abs()).fold(0.0, f32::max);
+            assert!(error < MAX_ERROR, "{}Hz at {}Hz: error {}", freq, sample_rate, error);
+        }
+    }
+}
+
+#[test]
+fn table_is_silent_above_nyquist() {
+    // the phase doesn't move, like the sin oscillator
+    let table = render(SineMethod::Table, 44_100, 30_000.0);
+    assert!(table.iter().all(|s| *s == 0.0));
+}
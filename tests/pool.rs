@@ -0,0 +1,89 @@
+// voices rendered across worker threads, the same mix whatever the number of threads
+
+extern crate midi;
+extern crate organn;
+
+use organn::basic_types::graph::Graph;
+use organn::basic_types::AudioBuffer;
+use organn::controls::DEFAULT_REGISTRATION;
+use organn::pool::VoicePool;
+use organn::stats::EngineStats;
+use organn::tuning::Tuning;
+use organn::voice::{Voice, VoiceMessage};
+
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+const SAMPLE_RATE: u32 = 44_100;
+const BLOCKS: usize = 200;
+
+// a pool with a note on each voice, a chord across them all
+fn pool(num_voices: usize, num_threads: usize) -> (VoicePool, Vec<mpsc::Sender<VoiceMessage>>, Arc<EngineStats>) {
+    let stats = Arc::new(EngineStats::new(SAMPLE_RATE));
+    let mut senders = Vec::new();
+    let mut voices = Vec::new();
+    for note in 0..num_voices {
+        let (sender, receiver) = mpsc::channel();
+        let channel = midi::utils::from_status_byte(0x90).1;
+        sender.send(VoiceMessage::Midi(midi::Message::NoteOn(channel, 48 + note as midi::U7 * 3, 100))).unwrap();
+        voices.push(Voice::new(SAMPLE_RATE, Tuning::new(440.0, 0), &DEFAULT_REGISTRATION, receiver));
+        senders.push(sender);
+    }
+    (VoicePool::new(voices, num_threads, 0.25, SAMPLE_RATE, stats.clone()), senders, stats)
+}
+
+fn render(pool: VoicePool, blocks: usize) -> Vec<AudioBuffer> {
+    let mut graph = Graph::new();
+    let voices = graph.add("voices", pool);
+    graph.set_output(&voices).unwrap();
+    (0..blocks).map(|_| *graph.run().unwrap()).collect()
+}
+
+#[test]
+fn threads_mix_the_same_as_one() {
+    let (single, _senders, _) = pool(8, 1);
+    let expected = render(single, BLOCKS);
+    assert!(expected.iter().any(|block| block.iter().any(|s| *s != 0.0)));
+
+    for &threads in [2, 3, 4].iter() {
+        let (threaded, _senders, stats) = pool(8, threads);
+        assert!(render(threaded, BLOCKS) == expected, "{} threads", threads);
+        let report = stats.take_report();
+        assert_eq!(report.max_active_voices, 8);
+    }
+}
+
+#[test]
+fn more_threads_than_voices() {
+    let (single, _senders, _) = pool(2, 1);
+    let (threaded, _senders, _) = pool(2, 8);
+    assert!(render(threaded, BLOCKS) == render(single, BLOCKS));
+}
+
+#[test]
+fn stopping_joins_the_workers() {
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+            let (pool, _senders, _) = pool(4, 4);
+            let mut graph = Graph::new();
+            let voices = graph.add("voices", pool);
+            graph.set_output(&voices).unwrap();
+            graph.run().unwrap();
+
+            graph.node_mut(&voices).stop();
+            // again, and a block after, are harmless and silent
+            graph.node_mut(&voices).stop();
+            let after = *graph.run().unwrap();
+            // dropping stops too
+            drop(graph);
+            done.send(after).unwrap();
+        });
+
+    let after = finished.recv_timeout(Duration::from_secs(10)).expect("the pool didn't stop");
+    assert!(after.iter().all(|s| *s == 0.0));
+
+    // dropped without ever running
+    let (idle, _senders, _) = pool(4, 4);
+    drop(idle);
+}
@@ -0,0 +1,186 @@
+// property tests, the same properties as the fuzz targets checked over a fixed set of
+// pseudo random cases so they run with `cargo test` on stable
+
+extern crate midi;
+extern crate midi_wrap;
+extern crate organn;
+
+#[path = "common/doubles.rs"]
+mod doubles;
+
+use midi_wrap::{Parser, Event};
+use organn::basic_types::BUFFER_SIZE;
+use organn::basic_types::graph::Graph;
+use doubles::{Constant, Recorder};
+use organn::env::Env;
+use organn::multi::{Multi, MultiConfig};
+use organn::tuning::{Tuning, TuningBank};
+
+const CASES: usize = 200;
+
+// xorshift, seeded per case so a failure names the case to rerun
+struct Rng(u64);
+
+impl Rng {
+    fn new(case: usize) -> Self {
+        Rng(0x9E37_79B9_7F4A_7C15 ^ (case as u64 + 1).wrapping_mul(0x2545_F491_4F6C_DD1D))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    // mostly status and data bytes mixed, with runs of sysex now and then
+    fn midi_bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| {
+                match self.below(8) {
+                    0 => 0xF0,
+                    1 => 0xF7,
+                    2 | 3 => 0x80 | self.below(0x80) as u8,
+                    _ => self.below(0x80) as u8
+                }
+            })
+            .collect()
+    }
+}
+
+fn count(events: &mut [usize; 4], event: Event) {
+    let kind = match event {
+        Event::Message(_) => 0,
+        Event::SysEx(_) => 1,
+        Event::SystemCommon(..) => 2,
+        Event::Realtime(_) => 3
+    };
+    events[kind] += 1;
+}
+
+#[test]
+fn parser_never_panics_and_ignores_packet_boundaries() {
+    for case in 0..CASES {
+        let mut rng = Rng::new(case);
+        let len = rng.below(512);
+        let bytes = rng.midi_bytes(len);
+        let packet = rng.below(16) + 1;
+
+        let mut whole = [0; 4];
+        Parser::new().parse(&bytes, |e| count(&mut whole, e));
+
+        let mut split = [0; 4];
+        let mut parser = Parser::new();
+        for chunk in bytes.chunks(packet) {
+            parser.parse(chunk, |e| count(&mut split, e));
+        }
+
+        assert_eq!(whole, split, "case {}", case);
+    }
+}
+
+#[test]
+fn note_offs_release_exactly_their_voices_and_nothing_sticks() {
+    let (_, channel) = midi::utils::from_status_byte(0x90);
+
+    for case in 0..CASES {
+        let mut rng = Rng::new(case);
+        let mut config = MultiConfig::new(8_000);
+        config.num_voices = rng.below(8) + 1;
+        config.num_threads = 1;
+        let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+        let stats = multi.stats();
+
+        // a small range of pitches so notes collide and voices get stolen
+        for i in 0..rng.below(200) {
+            let pitch = 60 + rng.below(16) as midi::U7;
+            let before = midi_conn.notes();
+
+            if rng.below(2) == 0 {
+                midi_conn.midi_message(&midi::Message::NoteOn(channel, pitch, 100));
+                let after = midi_conn.notes();
+                assert!(after.contains(&Some(pitch)), "case {}", case);
+                assert!(before.iter().zip(after.iter()).filter(|&(b, a)| a != b).count() <= 1, "case {}", case);
+            }
+            else {
+                midi_conn.midi_message(&midi::Message::NoteOff(channel, pitch, 0));
+                let after = midi_conn.notes();
+                for (b, a) in before.iter().zip(after.iter()) {
+                    let expected = if *b == Some(pitch) { None } else { *b };
+                    assert_eq!(*a, expected, "case {}", case);
+                }
+            }
+
+            if i % 4 == 3 {
+                multi.run();
+            }
+        }
+
+        for pitch in 60..(60 + 16) {
+            midi_conn.midi_message(&midi::Message::NoteOff(channel, pitch, 0));
+        }
+        assert!(midi_conn.notes().iter().all(|n| n.is_none()), "case {}", case);
+
+        // well past the release, then nothing should be sounding
+        for _ in 0..20 {
+            multi.run();
+        }
+        stats.take_report();
+        multi.run();
+        assert_eq!(stats.take_report().max_active_voices, 0, "case {}", case);
+    }
+}
+
+#[test]
+fn envelope_scales_its_input_at_any_time_and_rate() {
+    for case in 0..CASES {
+        let mut rng = Rng::new(case);
+        // includes ramps too short to last a sample
+        let time_ms = rng.below(50) as u32;
+        let sample_rate = rng.below(96_000) as u32;
+
+        let mut graph = Graph::new();
+        let source = graph.add("source", Constant::new([1.0; BUFFER_SIZE]));
+        let env = graph.add("env", Env::new(time_ms, sample_rate));
+        let recorder = graph.add("recorder", Recorder::new());
+        graph.connect(&source, &env).unwrap();
+        graph.connect(&env, &recorder).unwrap();
+        graph.set_output(&recorder).unwrap();
+
+        for _ in 0..rng.below(100) {
+            match rng.below(4) {
+                0 => graph.node_mut(&env).note_on(),
+                1 => graph.node_mut(&env).note_off(),
+                2 => graph.set_sample_rate(rng.below(96_000) as u32),
+                _ => {}
+            }
+            graph.run().unwrap();
+        }
+
+        for sample in graph.node(&recorder).samples() {
+            assert!((0.0..=1.0).contains(sample), "case {}: sample {} from a 1.0 input", case, sample);
+        }
+    }
+}
+
+#[test]
+fn tuning_sysex_never_panics() {
+    for case in 0..CASES {
+        let mut rng = Rng::new(case);
+        let mut tuning = Tuning::new(440.0, 0);
+        let mut bank = TuningBank::new();
+
+        // a tuning sysex header followed by anything, any byte values at all
+        let mut data = vec![[0x7E, 0x7F][rng.below(2)], 0x7F, 0x08, [0x01, 0x02, 0x07][rng.below(3)]];
+        let len = rng.below(450);
+        data.extend((0..len).map(|_| rng.below(256) as u8));
+        bank.sysex(&mut tuning, &data);
+
+        for note in 0..128 {
+            assert!(!tuning.note_to_hz(note).is_nan(), "case {}", case);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// scala scales and keyboard mappings, and the note tables made from them
+
+extern crate organn;
+
+use organn::scala::{self, Scale, KeyboardMap};
+
+// a pentatonic scale in cents and ratios
+const PENTATONIC: &str = "! pentatonic.scl
+!
+A pentatonic scale
+ 5
+!
+ 200.0 a whole tone
+ 5/4
+ 701.955
+ 27/16
+ 2  ! the octave
+";
+
+// three keys repeating, the middle one silent, with e above middle c at 300Hz
+fn keyboard(first: i32, last: i32, octave_degree: i32) -> String {
+    format!("! three.kbm
+3
+{}
+{}
+60
+63
+300.0
+{}
+! the mapping
+0
+x
+2
+", first, last, octave_degree)
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a / b - 1.0).abs() < 1e-5
+}
+
+#[test]
+fn scales_parse_cents_and_ratios() {
+    let scale = Scale::parse(PENTATONIC).unwrap();
+    assert_eq!(scale.description, "A pentatonic scale");
+
+    // laid out from middle c, a above it is 440Hz and the 5th degree up
+    let table = scala::note_table(&scale, &KeyboardMap::linear(&scale)).unwrap();
+    let c = 440.0 / (2.0 * 27.0 / 16.0);
+    assert!(close(table[69], 440.0));
+    assert!(close(table[60], c), "{}", table[60]);
+    assert!(close(table[61], c * 2.0_f32.powf(200.0 / 1_200.0)));
+    assert!(close(table[62], c * 1.25));
+    assert!(close(table[63], c * 1.5));
+    assert!(close(table[65], c * 2.0));
+    assert!(close(table[55], c / 2.0));
+}
+
+#[test]
+fn bad_scales_are_errors() {
+    assert!(Scale::parse("A scale\n 2\n 100.0\n").unwrap_err().contains("not enough notes"));
+    assert!(Scale::parse("A scale\n two\n 100.0\n 2/1\n").unwrap_err().contains("note count"));
+    for pitch in &["a/b", "-3/2", "3/0", "0", "1.2.3"] {
+        let error = Scale::parse(&format!("A scale\n 2\n {}\n 2/1\n", pitch)).unwrap_err();
+        assert!(error.contains("bad pitch"), "{}: {}", pitch, error);
+    }
+}
+
+#[test]
+fn keyboard_maps_place_the_scale() {
+    let scale = Scale::parse(PENTATONIC).unwrap();
+    let table = scala::note_table(&scale, &KeyboardMap::parse(&keyboard(36, 96, 5)).unwrap()).unwrap();
+
+    // the reference key, a repeat of middle c a period up
+    assert!(close(table[63], 300.0));
+    assert!(close(table[60], 150.0));
+    assert!(close(table[62], 150.0 * 1.25));
+    assert!(close(table[65], 300.0 * 1.25));
+    assert!(close(table[57], 75.0));
+
+    // unmapped keys and those outside the range stay silent
+    assert_eq!((table[61], table[64]), (0.0, 0.0));
+    assert_eq!((table[35], table[97]), (0.0, 0.0));
+    assert!(table[36] > 0.0 && table[96] > 0.0);
+
+    // 0 for the formal octave is the scale's own
+    let own = scala::note_table(&scale, &KeyboardMap::parse(&keyboard(36, 96, 0)).unwrap()).unwrap();
+    assert_eq!(&own[..], &table[..]);
+
+    // the reference key has to play something
+    let mut unmapped = keyboard(0, 127, 5);
+    unmapped = unmapped.replacen("63\n", "64\n", 1);
+    assert!(scala::note_table(&scale, &KeyboardMap::parse(&unmapped).unwrap()).is_err());
+}
+
+#[test]
+fn bad_keyboard_maps_are_errors() {
+    let good = keyboard(0, 127, 5);
+    assert!(KeyboardMap::parse(&good).is_ok());
+    assert!(KeyboardMap::parse(&good.replace("300.0", "loud")).unwrap_err().contains("reference frequency"));
+    assert!(KeyboardMap::parse(&good.replace("\n2\n", "\ntwo\n")).unwrap_err().contains("bad mapping"));
+    assert!(KeyboardMap::parse(&good.replacen("3\n", "4\n", 1)).unwrap_err().contains("not enough keys"));
+    assert!(KeyboardMap::parse("").unwrap_err().contains("map size"));
+}
@@ -0,0 +1,89 @@
+// the engine's load, voice, xrun and error accounting
+
+extern crate organn;
+
+use organn::basic_types::BUFFER_SIZE;
+use organn::stats::EngineStats;
+
+use std::time::Duration;
+
+const SAMPLE_RATE: u32 = 44_100;
+
+// real time there is for a block
+fn budget_ns() -> u64 {
+    BUFFER_SIZE as u64 * 1_000_000_000 / SAMPLE_RATE as u64
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 0.01
+}
+
+#[test]
+fn load_is_render_time_against_the_block_budget() {
+    let stats = EngineStats::new(SAMPLE_RATE);
+    stats.record_block(Duration::from_nanos(budget_ns() / 4));
+    stats.record_block(Duration::from_nanos(budget_ns() * 3 / 4));
+    stats.record_block(Duration::from_nanos(budget_ns() / 2));
+
+    let report = stats.take_report();
+    assert_eq!(report.blocks, 3);
+    assert!(close(report.load, 50.0), "{}", report.load);
+    assert!(close(report.peak_load, 75.0), "{}", report.peak_load);
+    assert!(close(report.max_render_us, budget_ns() as f64 * 0.75 / 1000.0), "{}", report.max_render_us);
+
+    // twice the rate is half the time for a block
+    stats.set_sample_rate(SAMPLE_RATE * 2);
+    stats.record_block(Duration::from_nanos(budget_ns() / 2));
+    assert!(close(stats.take_report().load, 100.0));
+}
+
+#[test]
+fn voices_and_imbalance_are_averaged_per_block() {
+    let stats = EngineStats::new(SAMPLE_RATE);
+    for &(active, imbalance) in [(2, 0.0), (6, 0.5), (4, 2.0)].iter() {
+        stats.record_block(Duration::from_nanos(1000));
+        stats.record_voices(active, imbalance);
+    }
+
+    let report = stats.take_report();
+    assert!(close(report.mean_active_voices, 4.0));
+    assert_eq!(report.max_active_voices, 6);
+    // imbalance is limited to all of it
+    assert!(close(report.imbalance, 50.0), "{}", report.imbalance);
+}
+
+#[test]
+fn reports_start_again_but_xruns_and_errors_add_up() {
+    let stats = EngineStats::new(SAMPLE_RATE);
+    stats.record_block(Duration::from_nanos(budget_ns()));
+    stats.record_voices(3, 0.25);
+    stats.record_xrun();
+    stats.record_xrun();
+    stats.record_error("first".to_string());
+    assert_eq!(stats.take_report().xruns, 2);
+
+    let empty = stats.take_report();
+    assert_eq!((empty.blocks, empty.max_active_voices), (0, 0));
+    assert_eq!((empty.load, empty.peak_load, empty.mean_active_voices, empty.imbalance), (0.0, 0.0, 0.0, 0.0));
+    assert_eq!((empty.xruns, empty.errors), (2, 1));
+
+    stats.record_xrun();
+    assert_eq!((stats.xruns(), stats.take_report().xruns), (3, 3));
+    assert!(format!("{}", stats.take_report()).contains("xruns 3, errors 1"));
+}
+
+#[test]
+fn errors_wait_to_be_reported_one_at_a_time() {
+    let stats = EngineStats::new(SAMPLE_RATE);
+    assert_eq!(stats.take_error(), None);
+
+    // others while the first waits are only counted
+    stats.record_error("voice graph: broken".to_string());
+    stats.record_error("engine graph: broken".to_string());
+    assert_eq!(stats.take_error(), Some("voice graph: broken".to_string()));
+    assert_eq!(stats.take_error(), None);
+
+    stats.record_error("a voice panicked".to_string());
+    assert_eq!(stats.take_error(), Some("a voice panicked".to_string()));
+    assert_eq!(stats.errors(), 3);
+}
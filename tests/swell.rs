@@ -0,0 +1,122 @@
+// the expression pedal and volume after the mix, their taper and smoothing and the tone mode
+
+extern crate organn;
+
+#[path = "common/doubles.rs"]
+mod doubles;
+
+use organn::basic_types::{AudioBuffer, BUFFER_SIZE};
+use organn::basic_types::graph::{Graph, Handle};
+use organn::swell::Swell;
+use doubles::{Constant, Recorder};
+
+const SAMPLE_RATE: u32 = 44_100;
+// 0.1s, ten smoothing time constants
+const SETTLE_BLOCKS: usize = 276;
+
+// the swell with a steady input and everything it puts out recorded
+fn chain(input: AudioBuffer) -> (Graph, Handle<Swell>, Handle<Recorder>) {
+    let mut graph = Graph::new();
+    let source = graph.add("source", Constant::new(input));
+    let swell = graph.add("swell", Swell::new(SAMPLE_RATE));
+    let recorder = graph.add("recorder", Recorder::new());
+    graph.connect(&source, &swell).unwrap();
+    graph.connect(&swell, &recorder).unwrap();
+    graph.set_output(&recorder).unwrap();
+    (graph, swell, recorder)
+}
+
+fn run(graph: &mut Graph, blocks: usize) {
+    for _ in 0..blocks {
+        graph.run().unwrap();
+    }
+}
+
+// the gain once a pedal or fader has settled at a controller value
+fn settled_gain(expression: u8, volume: u8) -> f32 {
+    let (mut graph, swell, recorder) = chain([1.0; BUFFER_SIZE]);
+    graph.node_mut(&swell).set_expression(expression as f32 / 127.0);
+    graph.node_mut(&swell).set_volume(volume as f32 / 127.0);
+    run(&mut graph, SETTLE_BLOCKS);
+    *graph.node(&recorder).samples().last().unwrap()
+}
+
+#[test]
+fn gain_rises_with_the_pedal_and_fader() {
+    let expression: Vec<f32> = (0..128).map(|v| settled_gain(v, 127)).collect();
+    assert!(expression.windows(2).all(|w| w[1] > w[0]), "{:?}", expression);
+    // closed is quiet, 30dB down, rather than silent
+    assert!((expression[0] - 0.0316).abs() < 0.001, "{}", expression[0]);
+    assert!((expression[127] - 1.0).abs() < 1e-4);
+    // more of the travel near the top than a straight dB taper
+    assert!(20.0 * expression[64].log10() > -15.0, "{}", expression[64]);
+
+    let volume: Vec<f32> = (0..128).map(|v| settled_gain(127, v)).collect();
+    assert!(volume.windows(2).all(|w| w[1] > w[0]), "{:?}", volume);
+    // the volume fader goes all the way to silence, or 80dB down on the way there
+    assert!(volume[0] < 1e-4, "{}", volume[0]);
+    assert!((volume[64] - 0.254).abs() < 0.001, "{}", volume[64]);
+}
+
+#[test]
+fn moves_are_smoothed_not_stepped() {
+    let (mut graph, swell, recorder) = chain([1.0; BUFFER_SIZE]);
+    run(&mut graph, 10);
+    graph.node_mut(&swell).set_expression(0.0);
+    run(&mut graph, SETTLE_BLOCKS);
+    graph.node_mut(&swell).set_expression(1.0);
+    run(&mut graph, SETTLE_BLOCKS);
+
+    // a 10ms one pole moves at most this much of the full swing in a sample
+    let samples = graph.node(&recorder).samples();
+    let max_step = 1.0 - (-1000.0 / (10.0 * SAMPLE_RATE as f32)).exp();
+    let steepest = samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+    assert!(steepest <= max_step * 1.001, "{} {}", steepest, max_step);
+
+    // and gets there in 50ms
+    let closing = &samples[(10 * BUFFER_SIZE)..];
+    assert!(closing[2_205] < 0.0316 + 0.01, "{}", closing[2_205]);
+    assert!(*samples.last().unwrap() > 0.99);
+}
+
+#[test]
+fn tone_mode_keeps_the_bass_as_the_pedal_closes() {
+    // dc is as bassy as it gets, alternating samples as trebly
+    let mut treble = [1.0; BUFFER_SIZE];
+    for sample in treble.iter_mut().skip(1).step_by(2) {
+        *sample = -1.0;
+    }
+    let level = |input: AudioBuffer, tone: bool, expression: f32| {
+        let (mut graph, swell, recorder) = chain(input);
+        graph.node_mut(&swell).set_tone(tone);
+        graph.node_mut(&swell).set_expression(expression);
+        run(&mut graph, SETTLE_BLOCKS);
+        graph.node(&recorder).samples().iter().rev().take(BUFFER_SIZE).map(|s| s.abs()).fold(0.0, f32::max)
+    };
+
+    // closed, the bass comes back 2.5 times over and the treble hardly at all
+    let bass_lift = level([1.0; BUFFER_SIZE], true, 0.0) / level([1.0; BUFFER_SIZE], false, 0.0);
+    let treble_lift = level(treble, true, 0.0) / level(treble, false, 0.0);
+    assert!((bass_lift - 2.5).abs() < 0.01, "{}", bass_lift);
+    assert!(treble_lift < 1.1, "{}", treble_lift);
+
+    // open, tone mode changes nothing
+    assert_eq!(level([1.0; BUFFER_SIZE], true, 1.0), level([1.0; BUFFER_SIZE], false, 1.0));
+}
+
+#[test]
+fn fade_out_ends_in_silence() {
+    let (mut graph, swell, recorder) = chain([1.0; BUFFER_SIZE]);
+    let fade_out = graph.node(&swell).fade_out();
+    run(&mut graph, 10);
+    fade_out.start();
+    // 50ms is 137.8 blocks
+    run(&mut graph, 137);
+    assert!(!fade_out.is_done());
+    run(&mut graph, 1);
+    assert!(fade_out.is_done());
+    let samples = graph.node(&recorder).samples();
+    assert_eq!(*samples.last().unwrap(), 0.0);
+    let fading = &samples[(10 * BUFFER_SIZE)..];
+    assert!(fading.windows(2).all(|w| w[1] <= w[0]));
+}
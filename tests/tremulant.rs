@@ -0,0 +1,194 @@
+// tremulants, their lfo and each division's voices following their own
+
+extern crate midi;
+extern crate organn;
+
+use organn::controls::{CcMap, Control, DEFAULT_REGISTRATION, NUM_DIVISIONS};
+use organn::multi::{Multi, MultiConfig};
+use organn::tremulant::{Tremulant, Modulation};
+use organn::tuning::{Tuning, TuningBank};
+use organn::voice::{Voice, VoiceMessage};
+
+use std::sync::mpsc;
+
+const SAMPLE_RATE: u32 = 44_100;
+const BLOCK: usize = 16;
+// a second
+const BLOCKS: usize = 2_756;
+
+fn channel(number: u8) -> midi::Channel {
+    midi::utils::from_status_byte(0x90 | (number - 1)).1
+}
+
+fn blocks(tremulant: &mut Tremulant, count: usize) -> Vec<Modulation> {
+    (0..count).map(|_| tremulant.next_block(BLOCK)).collect()
+}
+
+// the biggest jump in gain from one block to the next
+fn steepest(modulations: &[Modulation]) -> f32 {
+    modulations.windows(2).map(|w| (w[1].gain - w[0].gain).abs()).fold(0.0, f32::max)
+}
+
+#[test]
+fn tremulant_swings_at_its_rate_and_depth() {
+    let mut tremulant = Tremulant::new(SAMPLE_RATE);
+    tremulant.set_on(true);
+    tremulant.set_rate(5.0);
+    tremulant.set_depth(1.0);
+
+    // past easing in, 0.9s at 5Hz
+    let swings = blocks(&mut tremulant, BLOCKS).split_off(BLOCKS / 10);
+    let max_gain = swings.iter().map(|m| m.gain).fold(0.0, f32::max);
+    let min_gain = swings.iter().map(|m| m.gain).fold(2.0, f32::min);
+    assert!((max_gain - 1.3).abs() < 0.01 && (min_gain - 0.7).abs() < 0.01, "{} to {}", min_gain, max_gain);
+    let max_pitch = swings.iter().map(|m| m.pitch).fold(0.0, f32::max);
+    assert!((1_200.0 * max_pitch.log2() - 30.0).abs() < 0.5, "{}", max_pitch);
+
+    let rises = swings.windows(2).filter(|w| w[0].gain < 1.0 && w[1].gain >= 1.0).count();
+    assert!(rises == 4 || rises == 5, "{}", rises);
+
+    // pitch goes up as the level does, like a real tremulant's wind
+    assert!(swings.iter().all(|m| (m.gain >= 1.0) == (m.pitch >= 1.0)));
+
+    // out of range settings are held in range
+    tremulant.set_rate(100.0);
+    tremulant.set_depth(-1.0);
+    assert_eq!((tremulant.rate(), tremulant.depth()), (12.0, 0.0));
+}
+
+#[test]
+fn tremulant_eases_in_and_out() {
+    let mut tremulant = Tremulant::new(SAMPLE_RATE);
+    tremulant.set_depth(1.0);
+    assert!(blocks(&mut tremulant, 100).iter().all(|m| *m == Modulation::none()));
+
+    tremulant.set_on(true);
+    let on = blocks(&mut tremulant, BLOCKS / 2);
+    tremulant.set_on(false);
+    let off = blocks(&mut tremulant, BLOCKS / 2);
+
+    // no steps bigger than a full depth swing moves in a block anyway
+    assert!(steepest(&on) < 0.01 && steepest(&off) < 0.01, "{} {}", steepest(&on), steepest(&off));
+    // and it's settled back to nothing in a fifth of a second
+    assert!(off[(BLOCKS / 5)..].iter().all(|m| *m == Modulation::none()));
+}
+
+// the output of a voice playing a note on a channel, every block after the first, and its
+// division
+fn render(channel_number: u8, note: midi::U7, tremulants: [Modulation; NUM_DIVISIONS]) -> (Vec<f32>, usize) {
+    let (messages, midi_input) = mpsc::channel();
+    let mut voice = Voice::new(SAMPLE_RATE, Tuning::new(440.0, 0), &DEFAULT_REGISTRATION, midi_input);
+    messages.send(VoiceMessage::Midi(midi::Message::NoteOn(channel(channel_number), note, 100))).unwrap();
+
+    let mut samples = Vec::new();
+    for block in 0..100 {
+        voice.set_tremulants(&tremulants);
+        assert!(voice.run().unwrap());
+        if block > 0 {
+            samples.extend_from_slice(voice.output());
+        }
+    }
+    (samples, voice.division())
+}
+
+#[test]
+fn voices_follow_their_own_divisions_tremulant() {
+    let steady = [Modulation::none(); NUM_DIVISIONS];
+    // the upper manual a semitone up and half as loud
+    let mut upper_shaken = steady;
+    upper_shaken[0] = Modulation { gain: 0.5, pitch: 2.0_f32.powf(1.0 / 12.0) };
+
+    // the lower manual stays steady, channels past the pedals play the upper manual
+    let (lower, division) = render(2, 60, upper_shaken);
+    assert_eq!(division, 1);
+    assert_eq!(lower, render(2, 60, steady).0);
+    assert_eq!(render(3, 60, steady).1, 2);
+    assert_eq!(render(10, 60, upper_shaken), render(1, 60, upper_shaken));
+
+    let (shaken, _) = render(1, 60, upper_shaken);
+    let (semitone_up, _) = render(1, 61, steady);
+    let error = shaken.iter().zip(semitone_up.iter()).map(|(s, u)| (s - u * 0.5).abs()).fold(0.0, f32::max);
+    assert!(error < 1e-3, "{}", error);
+}
+
+#[test]
+fn cc_map_names_tremulant_controls() {
+    let map = CcMap::parse("20 tremulant1.on\n21 tremulant2.rate\n22 tremulant3.depth").unwrap();
+    assert_eq!(map.control(20), Some(Control::Tremulant(0)));
+    assert_eq!(map.control(21), Some(Control::TremulantRate(1)));
+    assert_eq!(map.control(22), Some(Control::TremulantDepth(2)));
+
+    assert!(CcMap::parse("20 tremulant4.on").is_err());
+    assert!(CcMap::parse("20 tremulant0.on").is_err());
+    assert!(CcMap::parse("20 tremulant1").is_err());
+    assert!(CcMap::parse("20 tremulant1.speed").is_err());
+}
+
+// how far the level of a held a440 wanders, loudest 50ms over quietest, whole cycles of every
+// partial so a steady note doesn't wander at all
+fn wander(samples: &[f32]) -> f32 {
+    let levels: Vec<f32> = samples.chunks_exact(2_205)
+        .map(|w| (w.iter().map(|s| s * s).sum::<f32>() / w.len() as f32).sqrt())
+        .collect();
+    levels.iter().cloned().fold(0.0, f32::max) / levels.iter().cloned().fold(1.0, f32::min)
+}
+
+#[test]
+fn controllers_shake_only_their_own_division() {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_threads = 1;
+    config.cc_map = CcMap::parse("20 tremulant1.on\n21 tremulant1.rate\n22 tremulant1.depth").unwrap();
+    let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+
+    let mut play = |multi: &mut Multi, number: u8| {
+        midi_conn.midi_message(&midi::Message::ControlChange(channel(1), 20, 127));
+        midi_conn.midi_message(&midi::Message::NoteOn(channel(number), 69, 100));
+        let mut samples = Vec::new();
+        for _ in 0..BLOCKS {
+            multi.run();
+            samples.extend_from_slice(multi.output());
+        }
+        midi_conn.midi_message(&midi::Message::AllNotesOff(channel(number)));
+        for _ in 0..BLOCKS {
+            multi.run();
+        }
+        // past the attack and the tremulant easing in
+        samples.split_off(samples.len() / 5)
+    };
+
+    let upper = play(&mut multi, 1);
+    let lower = play(&mut multi, 2);
+    assert!(multi.tremulant(0).unwrap().is_on());
+    assert!(!multi.tremulant(1).unwrap().is_on());
+    assert!(wander(&upper) > 1.2, "{}", wander(&upper));
+    assert!(wander(&lower) < 1.01, "{}", wander(&lower));
+}
+
+#[test]
+fn controllers_set_tremulant_rate_and_depth() {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_threads = 1;
+    config.cc_map = CcMap::parse("21 tremulant3.rate\n22 tremulant3.depth").unwrap();
+    let (mut multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+
+    midi_conn.midi_message(&midi::Message::ControlChange(channel(1), 21, 127));
+    midi_conn.midi_message(&midi::Message::ControlChange(channel(1), 22, 0));
+    multi.run();
+    assert_eq!(multi.tremulant(2).unwrap().rate(), 12.0);
+    assert_eq!(multi.tremulant(2).unwrap().depth(), 0.0);
+    assert!(multi.tremulant(NUM_DIVISIONS).is_none());
+}
+
+#[test]
+fn the_same_note_on_two_manuals_is_two_notes() {
+    let mut config = MultiConfig::new(SAMPLE_RATE);
+    config.num_threads = 1;
+    let (_multi, mut midi_conn) = Multi::new(config, Tuning::new(440.0, 0), TuningBank::new());
+
+    midi_conn.midi_message(&midi::Message::NoteOn(channel(1), 60, 100));
+    midi_conn.midi_message(&midi::Message::NoteOn(channel(2), 60, 100));
+    midi_conn.midi_message(&midi::Message::NoteOff(channel(2), 60, 0));
+    assert_eq!(midi_conn.notes().iter().filter(|n| **n == Some(60)).count(), 1);
+    midi_conn.midi_message(&midi::Message::NoteOff(channel(1), 60, 0));
+    assert!(midi_conn.notes().iter().all(|n| n.is_none()));
+}
@@ -0,0 +1,117 @@
+// tuning, the master reference under tables and rpn data entry
+
+extern crate midi;
+extern crate organn;
+
+use organn::tuning::{Tuning, TuningBank, Rpn};
+
+// a tuning with every note a semitone up on program 1
+fn sharp_bank() -> TuningBank {
+    let mut bank = TuningBank::new();
+    let mut table = [0.0; 128];
+    for (note, freq) in table.iter_mut().enumerate() {
+        *freq = 440.0 * 2.0_f32.powf((note as f32 - 68.0) / 12.0);
+    }
+    bank.set_program(1, Some(table));
+    bank
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a / b - 1.0).abs() < 1e-5
+}
+
+#[test]
+fn master_tuning_moves_tables_too() {
+    let mut bank = sharp_bank();
+    let mut tuning = Tuning::new(415.0, 0);
+    assert!(close(tuning.note_to_hz(69), 415.0));
+
+    bank.select(&mut tuning, 1);
+    assert!(close(tuning.note_to_hz(69), 415.0 * 2.0_f32.powf(1.0 / 12.0)), "{}", tuning.note_to_hz(69));
+    assert!(close(tuning.note_to_hz(68), 415.0));
+
+    // a midi tuning standard note change, a4 to 440 as the sender sees it
+    let change = [0x7F, 0x7F, 0x08, 0x02, 0x01, 0x01, 69, 69, 0, 0];
+    assert!(bank.sysex(&mut tuning, &change));
+    assert!(close(tuning.note_to_hz(69), 415.0), "{}", tuning.note_to_hz(69));
+
+    // an empty program is equal temperament at the reference either way
+    bank.select(&mut tuning, 2);
+    assert!(close(tuning.note_to_hz(81), 830.0));
+}
+
+#[test]
+fn data_entry_only_follows_an_rpn_selection() {
+    let mut bank = TuningBank::new();
+    let mut tuning = Tuning::new(440.0, 0);
+    let mut rpn = Rpn::new();
+    let mut cc = |tuning: &mut Tuning, control, value| rpn.control(tuning, &mut bank, control, value);
+
+    // nothing selected, cc 6 is free for something else
+    assert!(!cc(&mut tuning, 6, 100));
+
+    // bend range to 12 semitones and 50 cents, without deselecting after
+    assert!(cc(&mut tuning, 101, 0) && cc(&mut tuning, 100, 0));
+    assert!(cc(&mut tuning, 6, 12) && cc(&mut tuning, 38, 50));
+    tuning.set_bend(16383);
+    assert!(close(tuning.note_to_hz(69), 440.0 * 2.0_f32.powf(12.5 * 8191.0 / 8192.0 / 12.0)), "{}", tuning.note_to_hz(69));
+
+    // the sequence is over, data entry goes back to being a controller
+    assert!(!cc(&mut tuning, 6, 100));
+    assert!(!cc(&mut tuning, 38, 10));
+
+    // the lsb can come first, and another controller in between ends the sequence
+    assert!(cc(&mut tuning, 101, 0) && cc(&mut tuning, 100, 2));
+    assert!(cc(&mut tuning, 6, 65));
+    assert!(!cc(&mut tuning, 7, 100) && !cc(&mut tuning, 38, 10));
+    tuning.set_bend(8192);
+    assert!(close(tuning.note_to_hz(68), 440.0));
+
+    // nrpns aren't ours
+    assert!(cc(&mut tuning, 99, 0) && cc(&mut tuning, 98, 0));
+    assert!(!cc(&mut tuning, 6, 0));
+}
+
+#[test]
+fn master_tuning_and_transposition_are_checked() {
+    let mut tuning = Tuning::new(440.0, 0);
+    assert!(tuning.set_reference(415.0).is_ok());
+    assert!(close(tuning.note_to_hz(69), 415.0));
+    for &bad in [399.0, 481.0, 0.0, -440.0, f32::NAN].iter() {
+        assert!(tuning.set_reference(bad).is_err(), "{}", bad);
+    }
+    assert_eq!(tuning.reference(), 415.0);
+
+    assert!(tuning.set_transpose(-12).is_ok());
+    assert!(close(tuning.note_to_hz(81), 415.0));
+    assert!(tuning.set_transpose(64).is_err() && tuning.set_transpose(-65).is_err());
+    assert!(close(tuning.note_to_hz(81), 415.0));
+}
+
+#[test]
+fn fine_tuning_follows_rpn_1() {
+    let mut bank = TuningBank::new();
+    let mut tuning = Tuning::new(440.0, 0);
+    let mut rpn = Rpn::new();
+
+    // a semitone up at the top, coarse then fine
+    for &(control, value) in [(101, 0), (100, 1), (6, 127), (38, 127)].iter() {
+        assert!(rpn.control(&mut tuning, &mut bank, control, value));
+    }
+    assert!(close(tuning.note_to_hz(69), 440.0 * 2.0_f32.powf((8191.0 / 8192.0) / 12.0)), "{}", tuning.note_to_hz(69));
+
+    // the lsb first, then the msb, back to the middle
+    for &(control, value) in [(101, 0), (100, 1), (38, 0), (101, 0), (100, 1), (6, 64)].iter() {
+        assert!(rpn.control(&mut tuning, &mut bank, control, value));
+    }
+    assert!(close(tuning.note_to_hz(69), 440.0));
+
+    // a semitone down, on a table too
+    for &(control, value) in [(101, 0), (100, 1), (6, 0)].iter() {
+        assert!(rpn.control(&mut tuning, &mut bank, control, value));
+    }
+    assert!(close(tuning.note_to_hz(70), 440.0));
+    let mut sharp = sharp_bank();
+    sharp.select(&mut tuning, 1);
+    assert!(close(tuning.note_to_hz(69), 440.0));
+}